@@ -0,0 +1,144 @@
+use std::{collections::HashMap, time::Duration};
+
+use tokio_sky::{
+    async_trait, 
+    BatchError, 
+    BatchResult, 
+    BatchTransformer, 
+    BatchKey, 
+    Message, 
+    Processor, 
+    ProcResult, 
+    Topology
+};
+use tokio_sky::testing::TestSource;
+
+
+#[tokio::main]
+async fn main() {
+
+    //                         one lookup per batch
+    //  producer ---> parse ---> enrich[batch] ---> store (each record)
+
+    let mut source = TestSource::new();
+
+    let topology = 
+                Topology::producer(source.producer())
+
+                    .then(|| Parse)
+                    .concurrency(2)
+
+                    .then_batch(|| Enrich)
+                    .batch_size(100)
+                    .batch_timeout(Duration::from_millis(20))
+
+                    .then(|| Store)
+
+                    .start();
+
+
+    let records = source.push_batch((0..250).map(|n| format!("{},user-{}", n, n % 7)));
+    let broken = source.push("x,user-0".to_owned());
+    let unknown = source.push("1,nobody".to_owned());
+
+
+    // every record acked after stored by last layer
+    for msg in records {
+        source.assert_acked(msg).await;
+    }
+
+    source.assert_failed(broken).await;
+    source.assert_failed(unknown).await;
+
+    println!("==> 250 records enriched by batch and stored");
+
+
+    source.close();
+    topology.shutdown().await;
+
+}
+
+
+
+#[derive(Clone)]
+struct Record {
+    id: u64,
+    user: String
+}
+
+#[derive(Clone)]
+struct Enriched {
+    id: u64,
+    country: String
+}
+
+
+
+struct Parse;
+#[async_trait]
+impl Processor<String, Record> for Parse {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<String>) ->  ProcResult<Record> {
+        let (id, user) = msg.data.split_once(',').unwrap_or_default();
+
+        match id.parse() {
+            Ok(id) => {
+                let user = user.to_owned();
+                ProcResult::Dispatch(msg.replace(Record { id, user }))
+            }
+            Err(_) => ProcResult::Fail(format!("bad id {}", id))
+        }
+    } 
+}
+
+
+
+struct Enrich;
+#[async_trait]
+impl BatchTransformer<Record, Enriched> for Enrich {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<Record>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, batch: Vec<Message<Record>>) -> Result<BatchResult<Enriched>, BatchError<Record>> {
+
+        // one call for whole batch
+        let countries = lookup(batch.iter().map(|m| m.data.user.as_str()));
+
+        let results = batch
+            .into_iter()
+            .map(|msg| match countries.get(&msg.data.user) {
+                Some(country) => {
+                    let country = country.clone();
+                    ProcResult::Dispatch(msg.map(|r| Enriched { id: r.id, country }))
+                }
+                None => ProcResult::Fail(format!("unknown user {}", msg.data.user))
+            })
+            .collect();
+
+        Ok(BatchResult::Each(results))
+    }
+}
+
+fn lookup<'a>(users: impl Iterator<Item = &'a str>) -> HashMap<String, String> {
+    users
+        .filter(|u| u.starts_with("user-"))
+        .map(|u| (u.to_owned(), "NL".to_owned()))
+        .collect()
+}
+
+
+
+struct Store;
+#[async_trait]
+impl Processor<Enriched, ()> for Store {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<Enriched>) ->  ProcResult<()> {
+        let _ = (msg.data.id, msg.data.country);
+        ProcResult::Continue
+    } 
+}
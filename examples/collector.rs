@@ -1,3 +1,19 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::sync::mpsc::channel;
+use tokio_sky::{
+    async_trait, 
+    builtin::collector::Collector, 
+    Message, 
+    Processor, 
+    ProcResult, 
+    ProcessingType, 
+    ProducerRouter, 
+    Topology
+};
+
+
+
 
 #[tokio::main]
 async fn main() {
@@ -8,22 +24,18 @@ async fn main() {
     // fill buffer until buffer become full or timeout happen
     let timeout = Duration::from_secs(1);
 
-    let producer_factory = || Collector::new(collector_recv, timeout);
-    let producer_concurrency = 1;
-    let producer_router = RouterType::RoundRobin;
-    let producer_buffer_pool = 100;
-
 
+    // Collector own the receiver, so just one instance can be created
+    let collector_recv = Mutex::new(Some(collector_recv));
+    let producer_factory = move || {
+        let recv = collector_recv.lock().unwrap().take().expect("==> Collector concurrency must be 1");
+        Collector::new(recv, ProcessingType::CustomTimeout(timeout))
+    };
 
-    let proc_factory = || Layer1Process;
-    let proc_concurrency = 3;
-    let proc_buffer_size = 10;
 
-    
-
-    // 1. create X processor instances by 'proc_concurrency'
+    // 1. create X processor instances by 'concurrency'
     //
-    // 2. create X producer instances  by 'producer_concurrency'
+    // 2. create 1 collector instance
     // 
     // 3. create topology and syncing
     //  
@@ -37,17 +49,17 @@ async fn main() {
     //                                 processor-x
     //               
 
-    let safe_shutdown = 
-                run_topology_1(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
+    let topology = 
+                Topology::producer(producer_factory)
+                    .concurrency(1)
+                    .router(ProducerRouter::RoundRobin)
+                    .buffer_pool_size(100)
                 
-                   proc_factory,
-                   proc_concurrency,
-                   proc_buffer_size,
-                );
+                    .then(|| Layer1Process)
+                    .concurrency(3)
+                    .buffer_size(10)
+
+                    .start();
 
 
     
@@ -59,8 +71,9 @@ async fn main() {
     
 
         
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    safe_shutdown.send(());
+    // Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done
+    topology.shutdown().await;
 
 }
 
@@ -73,15 +86,12 @@ impl Processor<i32, ()> for Layer1Process {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn handle_message(&mut self, msg: i32) ->  ProcResult<()> {
+    async fn handle_message(&mut self, msg: Message<i32>) ->  ProcResult<()> {
         
         // print
-        println!("==> {}", msg);
+        println!("==> {}", msg.data);
 
         // return
         ProcResult::Continue
     } 
 }
-
-
-
@@ -0,0 +1,121 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio_sky::{async_trait, Producer, Processor, ProcResult, Message, RouterType, ProducerRouter, Terminate, Topology};
+
+
+#[tokio::main]
+async fn main() {
+
+    // 1. create X processor instances by 'concurrency'
+    //
+    // 2. create X producer instances  by 'concurrency'
+    // 
+    // 3. create topology and syncing
+    //  
+    //                                                                   /   layer3_processor-1
+    //                                         /   layer2_processor-1   /
+    //              /     layer1_processor-1  /                        /
+    //  producer-1 /                         /                        /
+    //  producer-2 ----   layer1_processor-2 ----- layer2_processor-2 -----  layer3_processor-2
+    //  producer-3 \                         \                        \
+    //              \     layer1_processor-3  \                        \
+    //                                         \   layer2_processor-3   \  
+    //                                                                   \   layer3_processor-3
+    //               
+
+    let topology = 
+                Topology::producer(|| Prod)
+                    .concurrency(3)
+                    .router(ProducerRouter::RoundRobin)
+                    .buffer_pool_size(100)
+                
+                    .then(|| Layer1Process)
+                    .concurrency(3)
+                    .router(RouterType::RoundRobin)
+                    .buffer_size(10)
+
+                    .then(|| Layer2Process)
+                    .concurrency(3)
+                    .router(RouterType::RoundRobin)
+                    .buffer_size(10)
+
+                    .then(|| Layer3Process)
+                    .concurrency(3)
+                    .buffer_size(10)
+
+                    .start();
+
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    
+    // Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done
+    topology.shutdown().await;
+
+
+}
+
+
+
+struct Prod;
+#[async_trait]
+impl Producer<usize> for Prod {
+
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn drain(&mut self, _buffer: VecDeque<Message<usize>>) {}
+
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<usize>>, Terminate> {
+        
+        Ok((0..buffer_size).map(Message::new)
+            .collect::<VecDeque<Message<usize>>>())
+    }
+} 
+
+
+struct Layer1Process;
+#[async_trait]
+impl Processor<usize, String> for Layer1Process {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<usize>) ->  ProcResult<String> {
+
+        let new_msg = format!("msg-{}", msg.data);
+
+        ProcResult::Dispatch(msg.replace(new_msg))
+    } 
+}
+
+
+
+#[derive(Clone)]
+struct Text(String);
+
+struct Layer2Process;
+#[async_trait]
+impl Processor<String, Text> for Layer2Process {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<String>) ->  ProcResult<Text> {
+        
+        ProcResult::Dispatch(msg.map(Text))
+    } 
+}
+
+
+struct Layer3Process;
+#[async_trait]
+impl Processor<Text, ()> for Layer3Process {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<Text>) ->  ProcResult<()> {
+        
+        println!("==> {}", msg.data.0);
+        
+        ProcResult::Continue
+    } 
+}
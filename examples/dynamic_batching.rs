@@ -1,30 +1,24 @@
-
-use blueriver::{run_topology_1_with_batcher, batcher::BatchProcessor, BATCH_TIMEOUT};
+use std::{collections::VecDeque, time::Duration};
+
+use tokio_sky::{
+    async_trait, 
+    BatchProcessor, 
+    BatchError, 
+    Producer, 
+    BatchKey, 
+    Message, 
+    Processor, 
+    ProcResult, 
+    RouterType, ProducerRouter, 
+    Terminate, 
+    Topology, 
+    BATCH_TIMEOUT
+};
 
 
 #[tokio::main]
 async fn main() {
 
-    let producer_factory = || Prod;
-    let producer_concurrency = 3;
-    let producer_router = RouterType::RoundRobin;
-    let producer_buffer_pool = 100;
-
-
-    let proc_factory = || Layer1Process;
-    let proc_concurrency = 3;
-    let proc_router = RouterType::Partition;
-    let proc_buffer_size = 10;
-
-
-    let batcher_factory = || Batcher;
-    let batcher_concurrency = 2;
-    let batcher_buffer_size = 10;
-    let batcher_batch_size = 10;
-    let batcher_batch_timeout: BATCH_TIMEOUT;
-    
-    
-
     //                                      ---> batcher[category_id]
     //                                    /       
     //              /     processor-1    /
@@ -32,29 +26,36 @@ async fn main() {
     //  producer-2 ----   processor-2   \
     //  producer-3 \                     \ 
     //              \     processor-3      -----> batcher[category_id]
-
-    let safe_shutdown = 
-                run_topology_1_with_batcher(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
+    //
+    // several categories can be routed to same instance,
+    // batch_by_key keep a pending batch per category inside each instance
+
+    let topology = 
+                Topology::producer(|| Prod)
+                    .concurrency(3)
+                    .router(ProducerRouter::RoundRobin)
+                    .buffer_pool_size(100)
                 
-                   proc_factory,
-                   proc_concurrency,
-                   proc_router,
-                   proc_buffer_size,
-
-                   batcher_factory,
-                   batcher_concurrency,
-                   batcher_buffer_size,
-                   batcher_batch_size,
-                   batcher_batch_timeout
-                );
+                    .then(|| Layer1Process)
+                    .concurrency(3)
+                    .router(RouterType::Partition)
+                    .buffer_size(10)
 
+                    .batcher(|| Batcher)
+                    .concurrency(3)
+                    .buffer_size(10)
+                    .batch_size(10)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .batch_by_key()
+
+                    .start();
+
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
     
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    safe_shutdown.send(());
+    // Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done
+    topology.shutdown().await;
 
 
 }
@@ -62,12 +63,14 @@ async fn main() {
 
 
 
+#[derive(Clone, Copy)]
 enum Category {
     Cars,
     Mobiles,
     Accessories
 }
 
+#[derive(Clone)]
 struct Product {
     ctype: Category   
 }
@@ -80,19 +83,18 @@ impl Producer<Product> for Prod {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn drain(&mut self, _buffer: VecDeque<Product>) {}
+    async fn drain(&mut self, _buffer: VecDeque<Message<Product>>) {}
 
 
-    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Product>, Terminate> {
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<Product>>, Terminate> {
         
         Ok((0..buffer_size)
-            .into_iter()
             .map(|i| {
 
-                let ctype = match i {
-                    1 | 2  => Category::Cars,
-                    3 | 4  => Category::Mobiles,
-                    5 => Category::Accessories 
+                let ctype = match i % 3 {
+                    0 => Category::Cars,
+                    1 => Category::Mobiles,
+                    _ => Category::Accessories 
                 };
 
 
@@ -101,7 +103,8 @@ impl Producer<Product> for Prod {
                 }
 
             })
-            .collect::<VecDeque<Product>>())
+            .map(Message::new)
+            .collect::<VecDeque<Message<Product>>>())
     }
 } 
 
@@ -112,62 +115,55 @@ impl Processor<Product, Product> for Layer1Process {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn handle_message(&mut self, msg: Product) ->  ProcResult<Product> {
+    async fn handle_message(&mut self, msg: Message<Product>) ->  ProcResult<Product> {
 
         // Parition_key
-        let pk = match msg.ctype {
+        let pk = match msg.data.ctype {
             Category::Cars => "cars".to_owned(),
             Category::Mobiles => "mobiles".to_owned(),
             Category::Accessories => "accessories".to_owned()
         };
 
-        ProcResult::Dispatch(msg,  Some(pk))
+        ProcResult::Dispatch(msg.with_batch_key(pk))
     } 
 }
 
 
 struct Batcher;
 impl Batcher {
-    pub fn batch_cars_insert(&self, batch: Vec<Product>) {
-        ()
+    pub fn batch_cars_insert(&self, batch: Vec<Message<Product>>) {
+        println!("==> insert {} cars", batch.len());
     }
-    pub fn batch_mobiles_insert(&self, msg: Product) {
-        ()
+    pub fn batch_mobiles_insert(&self, batch: Vec<Message<Product>>) {
+        println!("==> insert {} mobiles", batch.len());
     }
-    pub fn batch_accessories_insert(&self, msg: Product) {
-        ()
+    pub fn batch_accessories_insert(&self, batch: Vec<Message<Product>>) {
+        println!("==> insert {} accessories", batch.len());
     }
 }
 #[async_trait]
-impl BatchProcessor<Product, ()> for Batcher {
+impl BatchProcessor<Product> for Batcher {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn drain(&mut self, batch: Vec<Product>);
+    async fn drain(&mut self, _batch: Vec<Message<Product>>) {}
 
-    async fn handle_batch(&mut self, batch: Vec<Product>) -> Result<(), BatcherTerminate<Input>> {
+    async fn handle_batch(&mut self, key: Option<BatchKey>, batch: Vec<Message<Product>>) -> Result<(), BatchError<Product>> {
         
-        // we just check first product
-        // because we now all others same for current instance
-        
-        let ct = batch[0].ctype;
-
-        match ct {
-            Category::Cars => {
+        // all products of batch have same category
+        match key.as_deref() {
+            Some("cars") => {
                 self.batch_cars_insert(batch);
             }
-            Category::Mobiles => {
+            Some("mobiles") => {
                 self.batch_mobiles_insert(batch);
             }
-            Category::Accessories => {
+            Some("accessories") => {
                 self.batch_accessories_insert(batch);
             }
+            _ => return Err(BatchError::Fail("product without category".to_owned()))
         }
 
-
-        ProcResult::Continue
+        Ok(())
     } 
 }
-
-
-
@@ -1,10 +1,11 @@
+use tokio_sky::{Topology, Message};
 
 #[tokio::main]
 async fn main() {
 
     let producer_factory = || Prod;
     let producer_concurrency = 3;
-    let producer_router = RouterType::RoundRobin;
+    let producer_router = ProducerRouter::RoundRobin;
     let producer_buffer_pool = 100;
 
 
@@ -38,21 +39,22 @@ async fn main() {
     //              \ / 
     //               
 
-    let safe_shutdown = 
-                run_topology_1(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
-                
-                   proc_factory,
-                   proc_concurrency,
-                   proc_buffer_size,
-                );
+    let topology = 
+                Topology::producer(producer_factory)
+                    .concurrency(producer_concurrency)
+                    .router(producer_router)
+                    .buffer_pool_size(producer_buffer_pool)
 
+                    .then(proc_factory)
+                    .concurrency(proc_concurrency)
+                    .buffer_size(proc_buffer_size)
 
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    safe_shutdown.send(());
+                    .start();
+
+
+    // Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done
+    topology.shutdown().await;
 
 }
 
@@ -66,9 +68,9 @@ impl Producer<ProcKafkaMessage> for Prod {
 
     async fn terminate(&mut self) {}
 
-    async fn drain(&mut self, _buffer: VecDeque<ProcKafkaMessage>) {}
+    async fn drain(&mut self, _buffer: VecDeque<Message<ProcKafkaMessage>>) {}
 
-    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<ProcKafkaMessage>, Terminate> {
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<ProcKafkaMessage>>, Terminate> {
 
         Ok((0..buffer_size)
             .into_iter()
@@ -80,7 +82,8 @@ impl Producer<ProcKafkaMessage> for Prod {
                     partition: 1,
                 }
             })
-            .collect::<VecDeque<ProcKafkaMessage>>())
+            .map(Message::new)
+            .collect::<VecDeque<Message<ProcKafkaMessage>>>())
     }
 } 
 
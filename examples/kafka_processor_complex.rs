@@ -1,3 +1,4 @@
+use tokio_sky::{Topology, Message};
 use tokio_sky::builtin::kafka_processor::KafkaProcessor;
 
 
@@ -6,7 +7,7 @@ async fn main() {
 
     let producer_factory = || Prod;
     let producer_concurrency = 3;
-    let producer_router = RouterType::RoundRobin;
+    let producer_router = ProducerRouter::RoundRobin;
     let producer_buffer_pool = 100;
 
 
@@ -43,26 +44,27 @@ async fn main() {
     //              \ / 
     //               
 
-    let safe_shutdown = 
-                run_topology_2(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
-                
-                   kafka_proc_factory,
-                   kafka_proc_concurrency,
-                   kafka_proc_router,
-                   kafka_proc_buffer_size,
+    let topology = 
+                Topology::producer(producer_factory)
+                    .concurrency(producer_concurrency)
+                    .router(producer_router)
+                    .buffer_pool_size(producer_buffer_pool)
 
-                   result_handler_proc_factory,
-                   result_handler_proc_concurrency,
-                   result_handler_proc_buffer_size
-                );
+                    .then(kafka_proc_factory)
+                    .concurrency(kafka_proc_concurrency)
+                    .router(kafka_proc_router)
+                    .buffer_size(kafka_proc_buffer_size)
 
+                    .then(result_handler_proc_factory)
+                    .concurrency(result_handler_proc_concurrency)
+                    .buffer_size(result_handler_proc_buffer_size)
 
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    safe_shutdown.send(());
+                    .start();
+
+
+    // Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done
+    topology.shutdown().await;
 
 }
 
@@ -76,9 +78,9 @@ impl Producer<ProcKafkaMessage> for Prod {
 
     async fn terminate(&mut self) {}
 
-    async fn drain(&mut self, _buffer: VecDeque<ProcKafkaMessage>) {}
+    async fn drain(&mut self, _buffer: VecDeque<Message<ProcKafkaMessage>>) {}
 
-    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<ProcKafkaMessage>, Terminate> {
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<ProcKafkaMessage>>, Terminate> {
 
         Ok((0..buffer_size)
             .into_iter()
@@ -90,7 +92,8 @@ impl Producer<ProcKafkaMessage> for Prod {
                     partition: 1,
                 }
             })
-            .collect::<VecDeque<ProcKafkaMessage>>())
+            .map(Message::new)
+            .collect::<VecDeque<Message<ProcKafkaMessage>>>())
     }
 } 
 
@@ -107,9 +110,9 @@ impl Processor<OwnedDeliveryResult, ()> for Layer2Process {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn handle_message(&mut self, msg: OwnedDeliveryResult) ->  ProcResult<()> {
+    async fn handle_message(&mut self, msg: Message<OwnedDeliveryResult>) ->  ProcResult<()> {
         
-        match msg {
+        match msg.data {
             Some((partition, offset)) => {
 
             }
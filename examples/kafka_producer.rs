@@ -1,3 +1,4 @@
+use tokio_sky::{Topology, Message};
 
 
 
@@ -23,7 +24,7 @@ async fn main() {
                                    ProcessingType::Batch);
     
     let producer_concurrency = 3;
-    let producer_router = RouterType::RoundRobin;
+    let producer_router = ProducerRouter::RoundRobin;
     let producer_buffer_pool = 100;
 
 
@@ -49,21 +50,22 @@ async fn main() {
     //                    \     processor-3
     //               
 
-    let safe_shutdown = 
-                run_topology_1(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
-                
-                   proc_factory,
-                   proc_concurrency,
-                   proc_buffer_size,
-                );
+    let topology = 
+                Topology::producer(producer_factory)
+                    .concurrency(producer_concurrency)
+                    .router(producer_router)
+                    .buffer_pool_size(producer_buffer_pool)
 
+                    .then(proc_factory)
+                    .concurrency(proc_concurrency)
+                    .buffer_size(proc_buffer_size)
 
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    let _ = safe_shutdown.send(());
+                    .start();
+
+
+    // on SIGINT / SIGTERM Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done, second signal force stop
+    topology.shutdown_on_signals().await;
 
 }
 
@@ -76,9 +78,9 @@ impl Processor<ProdKafkaMessage, ()> for Layer1Process {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn handle_message(&mut self, msg: ProdKafkaMessage) ->  ProcResult<()> {
+    async fn handle_message(&mut self, msg: Message<ProdKafkaMessage>) ->  ProcResult<()> {
         
-        let key = match msg.key {
+        let key = match msg.data.key {
             Some(v) => {
                 String::from_utf8(v).unwrap()
             }
@@ -88,7 +90,7 @@ impl Processor<ProdKafkaMessage, ()> for Layer1Process {
         };
 
         // print
-        println!("==> {}-{}-{}-{}", key, msg.payload, msg.topic, msg.partition);
+        println!("==> {}-{}-{}-{}", key, msg.data.payload, msg.data.topic, msg.data.partition);
 
         // return
         ProcResult::Continue
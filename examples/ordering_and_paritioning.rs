@@ -1,29 +1,12 @@
-use blueriver::run_topology_1_with_batcher;
+use std::{collections::VecDeque, time::Duration};
 
+use tokio_sky::{async_trait, Producer, Processor, ProcResult, Message, RouterType, ProducerRouter, Terminate, Topology};
 
 
 
 #[tokio::main]
 async fn main() {
 
-    let producer_factory = || Prod;
-    let producer_concurrency = 3;
-    let producer_router = RouterType::RoundRobin;
-    let producer_buffer_pool = 100;
-
-
-    let proc1_factory = || Layer1Process;
-    let proc1_concurrency = 3;
-    let proc1_router = RouterType::Partition;
-    let proc1_buffer_size = 10;
-
-
-    let proc2_factory = || Layer2Process;
-    let proc2_concurrency = 2;
-    let proc2_buffer_size = 10;
-
-    
-
     // ***********************************
     // *
     // *   for ordering/paritioning 
@@ -38,9 +21,9 @@ async fn main() {
 
 
 
-    // 1. create X processor instances by 'proc_concurrency'
+    // 1. create X processor instances by 'concurrency'
     //
-    // 2. create X producer instances  by 'producer_concurrency'
+    // 2. create X producer instances  by 'concurrency'
     // 
     // 3. create topology and syncing
     //  
@@ -54,26 +37,29 @@ async fn main() {
     //               
 
 
-    let safe_shutdown = 
-                run_topology_1_with_batcher(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
+    let topology = 
+                Topology::producer(|| Prod)
+                    .concurrency(3)
+                    .router(ProducerRouter::RoundRobin)
+                    .buffer_pool_size(100)
                 
-                   proc1_factory,
-                   proc1_concurrency,
-                   proc1_router,
-                   proc1_buffer_size,
+                    .then(|| Layer1Process)
+                    .concurrency(3)
+                    .router(RouterType::Partition)
+                    .buffer_size(10)
+
+                    .then(|| Layer2Process)
+                    .concurrency(2)
+                    .buffer_size(10)
+
+                    .start();
 
-                   proc2_factory,
-                   proc2_concurrency,
-                   proc2_buffer_size
-                );
 
+    tokio::time::sleep(Duration::from_secs(1)).await;
     
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    safe_shutdown.send(());
+    // Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done
+    topology.shutdown().await;
 
 
 }
@@ -81,14 +67,16 @@ async fn main() {
 
 
 
+#[derive(Clone)]
 enum UserType {
     Admin,
     Client
 }
 
+#[derive(Clone)]
 struct User {
     utype: UserType,
-    resource_lock: String   
+    resource_lock: usize   
 }
 
 
@@ -99,20 +87,20 @@ impl Producer<User> for Prod {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn drain(&mut self, _buffer: VecDeque<User>) {}
+    async fn drain(&mut self, _buffer: VecDeque<Message<User>>) {}
 
 
-    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<User>, Terminate> {
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<User>>, Terminate> {
         
         Ok((0..buffer_size)
-            .into_iter()
             .map(|i| {
                 User {
                     utype: if i % 2 == 0 { UserType::Admin } else { UserType::Client },
                     resource_lock: i
                 }
             })
-            .collect::<VecDeque<User>>())
+            .map(Message::new)
+            .collect::<VecDeque<Message<User>>>())
     }
 } 
 
@@ -123,15 +111,15 @@ impl Processor<User, User> for Layer1Process {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn handle_message(&mut self, msg: User) ->  ProcResult<User> {
+    async fn handle_message(&mut self, msg: Message<User>) ->  ProcResult<User> {
 
         // Parition_key
-        let pk = match msg.utype {
+        let pk = match msg.data.utype {
             UserType::Admin => "admin".to_owned(),
             UserType::Client => "client".to_owned(),
         };
 
-        ProcResult::Dispatch(msg,  Some(pk))
+        ProcResult::Dispatch(msg.with_batch_key(pk))
     } 
 }
 
@@ -139,10 +127,10 @@ impl Processor<User, User> for Layer1Process {
 struct Layer2Process;
 impl Layer2Process {
     pub fn admin_handle(&self, msg: User) {
-        println!("==> Alyaws get admin request{}", msg);
+        println!("==> Alyaws get admin request {}", msg.resource_lock);
     }
     pub fn client_handle(&self, msg: User) {
-        println!("==> Alyaws get client request{}", msg);
+        println!("==> Alyaws get client request {}", msg.resource_lock);
     }
 }
 #[async_trait]
@@ -150,24 +138,21 @@ impl Processor<User, ()> for Layer2Process {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn handle_message(&mut self, msg: User) ->  ProcResult<()> {
+    async fn handle_message(&mut self, msg: Message<User>) ->  ProcResult<()> {
         
-        match msg.utype {
+        match msg.data.utype {
             UserType::Admin => {
                 // Guarantee all Admin tied to a given 'UserType' are processed in order and not concurrently
                 // by this instance
-                self.admin_handle(msg);
+                self.admin_handle(msg.data);
             }
             UserType::Client => {
                 // Guarantee all Client tied to a given 'UserType' are processed in order and not concurrently
                 // by this instance
-                self.client_handle(msg);
+                self.client_handle(msg.data);
             }
         }
 
         ProcResult::Continue
     } 
 }
-
-
-
@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use tokio_sky::{async_trait, BatchError, BatchKey, BatchProcessor, Message, Processor, ProcResult, Topology};
+use tokio_sky::testing::{self, TestSource};
+
+
+const BATCH_SIZE: usize = 3;
+const BATCH_TIMEOUT: Duration = Duration::from_secs(5);
+const STEP: Duration = Duration::from_millis(100);
+
+
+// clock not moves unless advanced, so flushes are deterministic
+#[tokio::main(flavor = "current_thread", start_paused = true)]
+async fn main() {
+
+    // in tests this is body of a #[tokio::test(start_paused = true)]
+
+    let mut source = TestSource::new();
+
+    let topology = 
+                Topology::producer(source.producer())
+                    .then(|| Double)
+                    .batcher(|| Store)
+                    .batch_size(BATCH_SIZE)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .start();
+
+
+    // full batch flushed without advancing clock
+    let full = source.push_batch(0..BATCH_SIZE as u64);
+
+    let elapsed = testing::advance_until_flushed(&topology, STEP).await;
+    assert_eq!(elapsed, Duration::ZERO);
+
+    for msg in full {
+        assert_eq!(source.try_outcome(msg), Some(testing::Outcome::Acked));
+    }
+
+
+    // partial batch waits for batch timeout, measured from its first message
+    let partial = source.push_batch(0..2);
+
+    let elapsed = testing::advance_until_flushed(&topology, STEP).await;
+    assert_eq!(elapsed, BATCH_TIMEOUT);
+
+    for msg in partial {
+        source.assert_acked(msg).await;
+    }
+
+
+    let batch = topology.metrics().layer(2).next().unwrap().batch.clone().unwrap();
+    assert_eq!(batch.flushed_by_size, 1);
+    assert_eq!(batch.flushed_by_timeout, 1);
+
+    println!("==> flushed by size at once, by timeout after {:?}", elapsed);
+
+
+    source.close();
+    topology.shutdown().await;
+
+}
+
+
+
+struct Double;
+#[async_trait]
+impl Processor<u64, u64> for Double {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) ->  ProcResult<u64> {
+        let n = msg.data * 2;
+        ProcResult::Dispatch(msg.replace(n))
+    } 
+}
+
+
+struct Store;
+#[async_trait]
+impl BatchProcessor<u64> for Store {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, _batch: Vec<Message<u64>>) -> Result<(), BatchError<u64>> {
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio_sky::{async_trait, Producer, Processor, ProcResult, Message, ProducerRouter, Terminate, Topology};
+
+
+#[tokio::main]
+async fn main() {
+
+    let mut topology = 
+                Topology::producer(|| Prod)
+                    .concurrency(2)
+                    .router(ProducerRouter::RoundRobin)
+                    .buffer_pool_size(100)
+                
+                    .then(|| SlowProcess)
+                    .concurrency(3)
+                    .buffer_size(50)
+                    
+                    .start();
+
+
+    // curl http://127.0.0.1:9898/metrics
+    let addr = topology.serve_metrics("127.0.0.1:9898", "example").await.unwrap();
+    println!("==> Serving metrics on http://{}/metrics", addr);
+
+
+    tokio::time::sleep(Duration::from_secs(30)).await;
+
+    // exporter stopped with topology
+    topology.shutdown().await;
+
+}
+
+
+
+struct Prod;
+#[async_trait]
+impl Producer<usize> for Prod {
+
+    async fn init(&mut self) {}
+
+    async fn terminate(&mut self) {}
+
+    async fn drain(&mut self, _buffer: VecDeque<Message<usize>>) {}
+
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<usize>>, Terminate> {
+
+        Ok((0..buffer_size).map(Message::new)
+            .collect::<VecDeque<Message<usize>>>())
+    }
+} 
+
+
+struct SlowProcess;
+#[async_trait]
+impl Processor<usize, ()> for SlowProcess {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<usize>) ->  ProcResult<()> {
+        
+        // consumer slower than producers, so layer saturated
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        ProcResult::Continue
+    } 
+}
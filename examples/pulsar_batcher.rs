@@ -1,5 +1,6 @@
+use tokio_sky::{Topology, Message};
 use pulsar::ProducerOptions;
-use tokio_sky::builtin::pulsar_processor::PulsarProcessor;
+use tokio_sky::builtin::pulsar_batcher::PulsarBatchProcessor;
 
 
 #[tokio::main]
@@ -7,7 +8,7 @@ async fn main() {
 
     let producer_factory = || Prod;
     let producer_concurrency = 3;
-    let producer_router = RouterType::RoundRobin;
+    let producer_router = ProducerRouter::RoundRobin;
     let producer_buffer_pool = 100;
 
 
@@ -33,7 +34,8 @@ async fn main() {
     };
 
 
-    let batcher_factory =  || PulsarProcessor::new(pulsar, opts, topic, pulsar_instance_name).unwrap();
+    // pulsar producer of each instance created by its init
+    let batcher_factory = move || PulsarBatchProcessor::new(pulsar.clone(), opts.clone(), topic, pulsar_instance_name);
     let batcher_concurrency = 3;
     let batcher_buffer_size = 10;
     let batcher_batch_size = 10;
@@ -47,28 +49,29 @@ async fn main() {
     //  producer-3 \                     \ 
     //              \     processor-3      -----> pulsar_batcher
 
-    let safe_shutdown = 
-                run_topology_1_with_batcher(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
-                
-                   proc_factory,
-                   proc_concurrency,
-                   proc_router,
-                   proc_buffer_size,
-
-                   batcher_factory,
-                   batcher_concurrency,
-                   batcher_buffer_size,
-                   batcher_batch_size,
-                   batcher_batch_timeout
-                );
-
-
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    safe_shutdown.send(());
+    let topology = 
+                Topology::producer(producer_factory)
+                    .concurrency(producer_concurrency)
+                    .router(producer_router)
+                    .buffer_pool_size(producer_buffer_pool)
+
+                    .then(proc_factory)
+                    .concurrency(proc_concurrency)
+                    .router(proc_router)
+                    .buffer_size(proc_buffer_size)
+
+                    .batcher(batcher_factory)
+                    .concurrency(batcher_concurrency)
+                    .buffer_size(batcher_buffer_size)
+                    .batch_size(batcher_batch_size)
+                    .batch_timeout(batcher_batch_timeout)
+
+                    .start();
+
+
+    // Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done
+    topology.shutdown().await;
 
 }
 
@@ -101,9 +104,9 @@ impl Producer<Cat> for Prod {
 
     async fn terminate(&mut self) {}
 
-    async fn drain(&mut self, _buffer: VecDeque<Cat>) {}
+    async fn drain(&mut self, _buffer: VecDeque<Message<Cat>>) {}
 
-    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Cat>, Terminate> {
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<Cat>>, Terminate> {
 
         Ok((0..buffer_size)
             .into_iter()
@@ -112,7 +115,8 @@ impl Producer<Cat> for Prod {
                     age: buffer_size % 10
                 }
             })
-            .collect::<VecDeque<Cat>>())
+            .map(Message::new)
+            .collect::<VecDeque<Message<Cat>>>())
     }
 } 
 
@@ -125,9 +129,9 @@ impl Processor<Cat, Cat> for Layer1Process {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn handle_message(&mut self, msg: Cat) ->  ProcResult<Cat> {
+    async fn handle_message(&mut self, msg: Message<Cat>) ->  ProcResult<Cat> {
 
         // Dispatch to Batcher
-        ProcResult::Dispatch(msg,  None)
+        ProcResult::Dispatch(msg)
     } 
 }
@@ -1,3 +1,4 @@
+use tokio_sky::{Topology, Message};
 use tokio_sky::builtin::pulsar_processor::PulsarProcessor;
 
 
@@ -6,7 +7,7 @@ async fn main() {
 
     let producer_factory = || Prod;
     let producer_concurrency = 3;
-    let producer_router = RouterType::RoundRobin;
+    let producer_router = ProducerRouter::RoundRobin;
     let producer_buffer_pool = 100;
 
 
@@ -25,8 +26,9 @@ async fn main() {
     };
 
 
+    // pulsar producer of each instance created by its init
     let proc_factory =  
-        || PulsarProcessor::new(pulsar, opts, topic, pulsar_instance_name).unwrap();
+        move || PulsarProcessor::new(pulsar.clone(), opts.clone(), topic, pulsar_instance_name);
 
     let proc_concurrency = 1;
     let proc_buffer_size = 100;
@@ -48,21 +50,22 @@ async fn main() {
     //              \ / 
     //               
 
-    let safe_shutdown = 
-                run_topology_1(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
-                
-                   proc_factory,
-                   proc_concurrency,
-                   proc_buffer_size,
-                );
+    let topology = 
+                Topology::producer(producer_factory)
+                    .concurrency(producer_concurrency)
+                    .router(producer_router)
+                    .buffer_pool_size(producer_buffer_pool)
 
+                    .then(proc_factory)
+                    .concurrency(proc_concurrency)
+                    .buffer_size(proc_buffer_size)
 
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    safe_shutdown.send(());
+                    .start();
+
+
+    // Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done
+    topology.shutdown().await;
 
 }
 
@@ -92,9 +95,9 @@ impl Producer<TestData> for Prod {
 
     async fn terminate(&mut self) {}
 
-    async fn drain(&mut self, _buffer: VecDeque<TestData>) {}
+    async fn drain(&mut self, _buffer: VecDeque<Message<TestData>>) {}
 
-    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<TestData>, Terminate> {
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<TestData>>, Terminate> {
 
         Ok((0..buffer_size)
             .into_iter()
@@ -103,7 +106,8 @@ impl Producer<TestData> for Prod {
                     data: format!("Pulsar !!"),
                 }
             })
-            .collect::<VecDeque<TestData>>())
+            .map(Message::new)
+            .collect::<VecDeque<Message<TestData>>>())
     }
 } 
 
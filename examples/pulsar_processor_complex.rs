@@ -1,5 +1,5 @@
-use pulsar::Error;
-use tokio_sky::builtin::kafka_processor::KafkaProcessor;
+use tokio_sky::{Topology, Message};
+use tokio_sky::builtin::pulsar_processor::{CommandSendReceipt, PulsarProcessor};
 
 
 #[tokio::main]
@@ -7,7 +7,7 @@ async fn main() {
 
     let producer_factory = || Prod;
     let producer_concurrency = 3;
-    let producer_router = RouterType::RoundRobin;
+    let producer_router = ProducerRouter::RoundRobin;
     let producer_buffer_pool = 100;
 
 
@@ -26,7 +26,8 @@ async fn main() {
     };
 
 
-    let pulsar_proc_factory = || || PulsarProcessor::new(pulsar, opts, topic, pulsar_instance_name).unwrap();
+    // pulsar producer of each instance created by its init
+    let pulsar_proc_factory = move || PulsarProcessor::new(pulsar.clone(), opts.clone(), topic, pulsar_instance_name);
     let pulsar_proc_concurrency = 1;
     let pulsar_proc_router = RouterType::RoundRobin;
     let pulsar_proc_buffer_size = 100;
@@ -53,26 +54,27 @@ async fn main() {
     //              \ / 
     //               
 
-    let safe_shutdown = 
-                run_topology_2(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
-                
-                   pulsar_proc_factory,
-                   pulsar_proc_concurrency,
-                   pulsar_proc_router,
-                   pulsar_proc_buffer_size,
+    let topology = 
+                Topology::producer(producer_factory)
+                    .concurrency(producer_concurrency)
+                    .router(producer_router)
+                    .buffer_pool_size(producer_buffer_pool)
 
-                   result_handler_proc_factory,
-                   result_handler_proc_concurrency,
-                   result_handler_proc_buffer_size
-                );
+                    .then(pulsar_proc_factory)
+                    .concurrency(pulsar_proc_concurrency)
+                    .router(pulsar_proc_router)
+                    .buffer_size(pulsar_proc_buffer_size)
 
+                    .then(result_handler_proc_factory)
+                    .concurrency(result_handler_proc_concurrency)
+                    .buffer_size(result_handler_proc_buffer_size)
 
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    safe_shutdown.send(());
+                    .start();
+
+
+    // Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done
+    topology.shutdown().await;
 
 }
 
@@ -86,9 +88,9 @@ impl Producer<ProcKafkaMessage> for Prod {
 
     async fn terminate(&mut self) {}
 
-    async fn drain(&mut self, _buffer: VecDeque<ProcKafkaMessage>) {}
+    async fn drain(&mut self, _buffer: VecDeque<Message<ProcKafkaMessage>>) {}
 
-    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<ProcKafkaMessage>, Terminate> {
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<ProcKafkaMessage>>, Terminate> {
 
         Ok((0..buffer_size)
             .into_iter()
@@ -100,7 +102,8 @@ impl Producer<ProcKafkaMessage> for Prod {
                     partition: 1,
                 }
             })
-            .collect::<VecDeque<ProcKafkaMessage>>())
+            .map(Message::new)
+            .collect::<VecDeque<Message<ProcKafkaMessage>>>())
     }
 } 
 
@@ -110,30 +113,16 @@ impl Producer<ProcKafkaMessage> for Prod {
 
 struct DeliveryHandler;
 #[async_trait]
-impl Processor<DeliveryResult, ()> for Layer2Process {
+impl Processor<CommandSendReceipt, ()> for DeliveryHandler {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn handle_message(&mut self, msg: DeliveryResult) ->  ProcResult<()> {
+    // failed sends never reach here, they fail on PulsarProcessor layer 
+    // (retried by its RetryPolicy, then passed to its failure sink)
+    async fn handle_message(&mut self, msg: Message<CommandSendReceipt>) ->  ProcResult<()> {
         
-        match msg {
-            Some(send_future) => {
-                match send_future.await {
-                    Ok(_) => (),
-                    Err(_e) => {
-                        // TODO: handle error
-                    }
-                }
-            }
-            Err(pulsar_err) => {
-                // TODO: Push to dead letter or requeue
-            }
-        }
+        println!("==> sent {:?}", msg.data.message_id);
 
         ProcResult::Continue
     } 
 }
-
-
-
-
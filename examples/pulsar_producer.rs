@@ -1,3 +1,4 @@
+use tokio_sky::{Topology, Message};
 use pulsar::SubType;
 
 
@@ -18,15 +19,15 @@ async fn main() {
     let buffer_size = 100;
 
     let producer_factory = 
-        || PulsarProducer::<TestData>::new(pulsar, 
-                                           topics, 
-                                           pulsar_instance_name, 
-                                           subscription_type, 
-                                           buffer_size, 
-                                           ProcessingType::Batch).unwrap();
+        move || PulsarProducer::<TestData>::new(pulsar.clone(), 
+                                                &topics, 
+                                                pulsar_instance_name, 
+                                                subscription_type, 
+                                                buffer_size, 
+                                                ProcessingType::Batch);
     
     let producer_concurrency = 3;
-    let producer_router = RouterType::RoundRobin;
+    let producer_router = ProducerRouter::RoundRobin;
     let producer_buffer_pool = 100;
 
 
@@ -52,21 +53,22 @@ async fn main() {
     //                     \     processor-3
     //               
 
-    let safe_shutdown = 
-                run_topology_1(
-                   producer_factory,
-                   producer_concurrency,
-                   producer_router,
-                   producer_buffer_pool,
-                
-                   proc_factory,
-                   proc_concurrency,
-                   proc_buffer_size,
-                );
+    let topology = 
+                Topology::producer(producer_factory)
+                    .concurrency(producer_concurrency)
+                    .router(producer_router)
+                    .buffer_pool_size(producer_buffer_pool)
 
+                    .then(proc_factory)
+                    .concurrency(proc_concurrency)
+                    .buffer_size(proc_buffer_size)
 
-    // Safe Shutdown from (Producer) to (Layer_X_Processor)
-    let _ = safe_shutdown.send(());
+                    .start();
+
+
+    // on SIGINT / SIGTERM Safe Shutdown from (Producer) to (Layer_X_Processor),
+    // wait until all processors and batchers job done, second signal force stop
+    topology.shutdown_on_signals().await;
 
 }
 
@@ -84,10 +86,10 @@ impl Processor<TestData, ()> for Layer1Process {
     async fn init(&mut self) {}
     async fn terminate(&mut self) {}
 
-    async fn handle_message(&mut self, msg: TestData) ->  ProcResult<()> {
+    async fn handle_message(&mut self, msg: Message<TestData>) ->  ProcResult<()> {
         
         // print
-        println!("==> {}", msg.data);
+        println!("==> {}", msg.data.data);
 
         // return
         ProcResult::Continue
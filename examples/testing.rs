@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+use tokio_sky::{async_trait, BatchError, BatchKey, BatchProcessor, Processor, ProcResult, Message, Topology, FailReason};
+use tokio_sky::testing::TestSource;
+
+
+#[tokio::main]
+async fn main() {
+
+    // in tests this is body of a #[tokio::test]
+
+    let mut source = TestSource::new();
+
+    let topology = 
+                Topology::producer(source.producer())
+                    .then(|| Parse)
+                    .concurrency(2)
+
+                    .batcher(|| Store)
+
+                    // real batcher write to database, mocked in tests
+                    .mock_batcher(|| MockStore)
+                    
+                    .start();
+
+
+    let ok = source.push("42".to_owned());
+    let bad = source.push("forty two".to_owned());
+    let batch = source.push_batch((0..10).map(|n| n.to_string()));
+
+
+    source.assert_acked(ok).await;
+
+    let reason = source.assert_failed(bad).await;
+    assert!(matches!(reason, FailReason::Error(_)));
+
+    for msg in batch {
+        source.assert_acked_within(msg, Duration::from_secs(1)).await;
+    }
+
+    println!("==> all messages reached expected outcome");
+
+
+    // producers terminate, then topology shutdown
+    source.close();
+    topology.shutdown().await;
+
+}
+
+
+
+struct Parse;
+#[async_trait]
+impl Processor<String, u64> for Parse {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<String>) ->  ProcResult<u64> {
+        match msg.data.parse::<u64>() {
+            Ok(n) => ProcResult::Dispatch(msg.replace(n)),
+            Err(e) => ProcResult::Fail(e.to_string())
+        }
+    } 
+}
+
+
+struct Store;
+#[async_trait]
+impl BatchProcessor<u64> for Store {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, _batch: Vec<Message<u64>>) -> Result<(), BatchError<u64>> {
+        unimplemented!("write to database")
+    }
+}
+
+
+struct MockStore;
+#[async_trait]
+impl BatchProcessor<u64> for MockStore {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, _batch: Vec<Message<u64>>) -> Result<(), BatchError<u64>> {
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio_sky::{async_trait, BatchError, BatchKey, BatchProcessor, Producer, Processor, ProcResult, Message, ProducerRouter, Terminate, Topology};
+
+
+#[tokio::main]
+async fn main() {
+
+    // every event printed with its spans:
+    //   stage{layer instance} > message{layer instance attempt}
+    //
+    // a message span is child of message span on previous layer,
+    // a batch span links to span of its messages (e.g. shown as references in Jaeger)
+    tracing_subscriber::fmt()
+        .with_target(false)
+        .init();
+
+
+    let topology = 
+                Topology::producer(|| Prod)
+                    .concurrency(1)
+                    .router(ProducerRouter::RoundRobin)
+                    .buffer_pool_size(4)
+                
+                    .then(|| Double)
+                    .concurrency(2)
+                    .buffer_size(10)
+
+                    .batcher(|| Print)
+                    .concurrency(1)
+                    .batch_size(4)
+                    .batch_timeout(Duration::from_millis(100))
+                    
+                    .start();
+
+
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    topology.shutdown().await;
+
+}
+
+
+
+struct Prod;
+#[async_trait]
+impl Producer<usize> for Prod {
+
+    async fn init(&mut self) {}
+
+    async fn terminate(&mut self) {}
+
+    async fn drain(&mut self, _buffer: VecDeque<Message<usize>>) {}
+
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<usize>>, Terminate> {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        Ok((0..buffer_size).map(Message::new)
+            .collect::<VecDeque<Message<usize>>>())
+    }
+} 
+
+
+struct Double;
+#[async_trait]
+impl Processor<usize, usize> for Double {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<usize>) ->  ProcResult<usize> {
+        tracing::info!(data = msg.data, "doubling");
+
+        ProcResult::Dispatch(msg.map(|n| n * 2))
+    } 
+}
+
+
+struct Print;
+#[async_trait]
+impl BatchProcessor<usize> for Print {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<usize>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, batch: Vec<Message<usize>>) -> Result<(), BatchError<usize>> {
+        let data = batch.iter().map(|m| m.data).collect::<Vec<_>>();
+        tracing::info!(?data, "batch");
+
+        Ok(())
+    }
+}
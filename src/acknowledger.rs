@@ -0,0 +1,504 @@
+use std::{any::Any, sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}};
+
+#[cfg(any(feature = "kafka", test))]
+use std::{collections::{BTreeMap, HashMap}, hash::Hash};
+
+use indexmap::IndexMap;
+
+use crate::failure::FailReason;
+
+
+
+/// Acknowledge messages to their source (e.g. commit kafka offset, ack pulsar message)
+///
+///   * producer attach an `Acknowledgement` to each message
+///   * `ack` called once message finished last processor or batcher
+///     (or returned `ProcResult::Continue` in middle of topology)
+///   * if message lost (stage aborted, batcher terminated) it never acked,
+///     so source can redeliver it (at-least-once)
+pub trait Acknowledger: Send + Sync + 'static {
+
+    /// identify a message inside source, e.g. kafka (topic, partition, offset)
+    type Ref: Send + Sync + 'static;
+
+    /// called from stage task, must not block
+    fn ack(&self, successful: Vec<Self::Ref>, failed: Vec<(Self::Ref, FailReason)>);
+}
+
+
+
+/// acknowledger reference of a message, 
+/// when message cloned (e.g. `Broadcast` dispatcher) acked after all copies finished
+pub struct Acknowledgement {
+    shared: Option<Arc<Shared>>
+}
+
+struct Shared {
+    entry: Box<dyn Entry>,
+
+    /// first failure of a copy
+    failed: Mutex<Option<FailReason>>,
+
+    /// a copy dropped without finished
+    lost: AtomicBool
+}
+
+impl Acknowledgement {
+
+    pub fn new<A>(acker: Arc<A>, reference: A::Ref) -> Self 
+    where
+        A: Acknowledger
+    {
+        Acknowledgement {
+            shared: Some(Arc::new(Shared {
+                entry: Box::new(AckEntry { acker, reference }),
+                failed: Mutex::new(None),
+                lost: AtomicBool::new(false)
+            }))
+        }
+    }
+
+
+    /// mark this copy finished, return entry if it was last copy
+    fn settle(mut self, failed: Option<FailReason>) -> Option<Settled> {
+
+        let shared = self.shared.take()?;
+
+        if let Some(reason) = failed {
+            let mut f = shared.failed.lock().unwrap_or_else(|e| e.into_inner());
+            if f.is_none() {
+                *f = Some(reason)
+            }
+        }
+
+        // exactly one copy get inner
+        let shared = Arc::into_inner(shared)?;
+
+        if shared.lost.load(Ordering::Acquire) {
+            return None
+        }
+
+        let failed = shared.failed.into_inner().unwrap_or_else(|e| e.into_inner());
+        Some((shared.entry, failed))
+    }
+}
+
+impl Clone for Acknowledgement {
+    fn clone(&self) -> Self {
+        Acknowledgement {
+            shared: self.shared.clone()
+        }
+    }
+}
+
+impl Drop for Acknowledgement {
+
+    /// copy dropped without settle, whole message not acked
+    fn drop(&mut self) {
+        if let Some(shared) = self.shared.take() {
+            shared.lost.store(true, Ordering::Release);
+        }
+    }
+}
+
+
+
+/// settle acknowledgements of finished messages,
+/// group by acknowledger so each one called once
+pub(crate) fn ack_all<I>(acks: I, failed: Option<FailReason>) 
+where
+    I: IntoIterator<Item = Option<Acknowledgement>>
+{
+    let mut groups: IndexMap<usize, Vec<Settled>> = IndexMap::new();
+
+    for ack in acks.into_iter().flatten() {
+        if let Some((entry, f)) = ack.settle(failed.clone()) {
+            groups.entry(entry.acker_id())
+                .or_default()
+                .push((entry, f));
+        }
+    }
+
+    for (_, mut group) in groups {
+        let (first, f) = group.remove(0);
+        first.ack(f, group);
+    }
+}
+
+
+/// settle acknowledgement of a finished message
+#[inline]
+pub(crate) fn ack(ack: Option<Acknowledgement>, failed: Option<FailReason>) {
+    if ack.is_some() {
+        ack_all([ack], failed)
+    }
+}
+
+
+
+/// join acknowledgements of several messages into one (e.g. output of a batch),
+/// each one settled as joined message settled
+pub(crate) fn join(acks: Vec<Option<Acknowledgement>>) -> Option<Acknowledgement> {
+    if acks.iter().all(Option::is_none) {
+        return None
+    }
+
+    Some(Acknowledgement::new(Arc::new(Joined), acks))
+}
+
+
+struct Joined;
+
+impl Acknowledger for Joined {
+    type Ref = Vec<Option<Acknowledgement>>;
+
+    fn ack(&self, successful: Vec<Self::Ref>, failed: Vec<(Self::Ref, FailReason)>) {
+        for acks in successful {
+            ack_all(acks, None);
+        }
+
+        for (acks, reason) in failed {
+            ack_all(acks, Some(reason));
+        }
+    }
+}
+
+
+
+/// acknowledgement settled as failed by `reason` once returned one settled
+/// (e.g. carried by dead letter of message), lost with it if dropped
+pub(crate) fn failed(ack: Option<Acknowledgement>, reason: FailReason) -> Option<Acknowledgement> {
+    ack.as_ref()?;
+
+    Some(Acknowledgement::new(Arc::new(Failed), (ack, reason)))
+}
+
+
+struct Failed;
+
+impl Acknowledger for Failed {
+    type Ref = (Option<Acknowledgement>, FailReason);
+
+    // source failed by its own reason, even if carrier failed too
+    fn ack(&self, successful: Vec<Self::Ref>, failed: Vec<(Self::Ref, FailReason)>) {
+        let settled = successful
+            .into_iter()
+            .chain(failed.into_iter().map(|(r, _)| r));
+
+        for (ack, reason) in settled {
+            ack_all([ack], Some(reason));
+        }
+    }
+}
+
+
+
+/// consumed positions of each partition of a source not committed yet (e.g. kafka offsets),
+/// messages finish out of order, so per partition just highest position 
+/// that every position before it finished can be committed
+#[cfg(any(feature = "kafka", test))]
+pub(crate) struct PrefixTracker<K> {
+
+    /// `true` once message finished
+    partitions: HashMap<K, BTreeMap<i64, bool>>
+}
+
+#[cfg(any(feature = "kafka", test))]
+impl<K> PrefixTracker<K>
+where
+    K: Hash + Eq + Clone
+{
+    pub fn new() -> Self {
+        PrefixTracker {
+            partitions: HashMap::new()
+        }
+    }
+
+    /// register a consumed message, before it dispatched
+    pub fn consumed(&mut self, partition: K, position: i64) {
+        self.partitions
+            .entry(partition)
+            .or_default()
+            .insert(position, false);
+    }
+
+    /// mark messages finished, returns new committable position of partitions 
+    /// their finished prefix advanced
+    pub fn finished<I>(&mut self, positions: I) -> Vec<(K, i64)>
+    where
+        I: IntoIterator<Item = (K, i64)>
+    {
+        let mut touched = Vec::new();
+
+        for (partition, position) in positions {
+            if let Some(finished) = self.partitions.get_mut(&partition).and_then(|p| p.get_mut(&position)) {
+                *finished = true;
+            }
+
+            if !touched.contains(&partition) {
+                touched.push(partition);
+            }
+        }
+
+        let mut committable = Vec::new();
+
+        for partition in touched {
+            let Some(pending) = self.partitions.get_mut(&partition) else { continue };
+
+            // finished prefix of partition, stops at first in-flight position
+            let mut highest = None;
+            while let Some(entry) = pending.first_entry() {
+                if !*entry.get() {
+                    break
+                }
+                highest = Some(entry.remove_entry().0);
+            }
+
+            if let Some(position) = highest {
+                committable.push((partition, position));
+            }
+        }
+
+        committable
+    }
+}
+
+
+
+/// finished entry with its failure (if failed)
+type Settled = (Box<dyn Entry>, Option<FailReason>);
+
+
+/// type erased (acknowledger, reference)
+trait Entry: Send + Sync {
+    fn acker_id(&self) -> usize;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+
+    /// ack this entry with rest of entries of same acknowledger
+    fn ack(self: Box<Self>, failed: Option<FailReason>, rest: Vec<Settled>);
+}
+
+struct AckEntry<A: Acknowledger> {
+    acker: Arc<A>,
+    reference: A::Ref
+}
+
+impl<A> Entry for AckEntry<A> 
+where
+    A: Acknowledger
+{
+    fn acker_id(&self) -> usize {
+        Arc::as_ptr(&self.acker) as *const () as usize
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn ack(self: Box<Self>, failed: Option<FailReason>, rest: Vec<Settled>) {
+
+        let mut successful = Vec::new();
+        let mut failures = Vec::new();
+
+        let acker = self.acker.clone();
+
+        let entries = std::iter::once((self as Box<dyn Entry>, failed)).chain(rest);
+
+        for (entry, f) in entries {
+
+            // same acker_id, so same type
+            let entry = match entry.into_any().downcast::<AckEntry<A>>() {
+                Ok(entry) => entry,
+                Err(_) => continue
+            };
+
+            match f {
+                None => successful.push(entry.reference),
+                Some(reason) => failures.push((entry.reference, reason))
+            }
+        }
+
+        acker.ack(successful, failures);
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// records every ack call
+    #[derive(Default)]
+    struct Recorder {
+        acked: Mutex<Vec<(u32, Option<FailReason>)>>,
+        calls: std::sync::atomic::AtomicUsize
+    }
+
+    impl Recorder {
+        fn acked(&self) -> Vec<(u32, Option<FailReason>)> {
+            self.acked.lock().unwrap().clone()
+        }
+    }
+
+    impl Acknowledger for Recorder {
+        type Ref = u32;
+
+        fn ack(&self, successful: Vec<u32>, failed: Vec<(u32, FailReason)>) {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+
+            let mut acked = self.acked.lock().unwrap();
+            acked.extend(successful.into_iter().map(|r| (r, None)));
+            acked.extend(failed.into_iter().map(|(r, reason)| (r, Some(reason))));
+        }
+    }
+
+    fn error() -> FailReason {
+        FailReason::Error("failed".to_owned())
+    }
+
+
+    #[test]
+    fn acked_once_after_all_copies_settled() {
+        let acker = Arc::new(Recorder::default());
+        let ack = Acknowledgement::new(acker.clone(), 1);
+        let copy = ack.clone();
+
+        self::ack(Some(copy), None);
+        assert_eq!(acker.acked(), vec![]);
+
+        self::ack(Some(ack), None);
+        assert_eq!(acker.acked(), vec![(1, None)]);
+    }
+
+    #[test]
+    fn failed_copy_fails_message() {
+        let acker = Arc::new(Recorder::default());
+        let ack = Acknowledgement::new(acker.clone(), 1);
+        let copy = ack.clone();
+
+        self::ack(Some(copy), Some(error()));
+        self::ack(Some(ack), None);
+
+        assert_eq!(acker.acked(), vec![(1, Some(error()))]);
+    }
+
+    #[test]
+    fn dropped_copy_leaves_message_lost() {
+        let acker = Arc::new(Recorder::default());
+        let ack = Acknowledgement::new(acker.clone(), 1);
+
+        drop(ack.clone());
+        self::ack(Some(ack), None);
+
+        assert_eq!(acker.acked(), vec![]);
+    }
+
+    #[test]
+    fn ack_all_call_acker_once() {
+        let acker = Arc::new(Recorder::default());
+        let acks = (0..3).map(|r| Some(Acknowledgement::new(acker.clone(), r)));
+
+        ack_all(acks, None);
+
+        assert_eq!(acker.acked(), vec![(0, None), (1, None), (2, None)]);
+        assert_eq!(acker.calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn joined_settle_every_input() {
+        let acker = Arc::new(Recorder::default());
+        let inputs = (0..2).map(|r| Some(Acknowledgement::new(acker.clone(), r))).collect();
+
+        // outputs of a batch (or DispatchMany) carry copies of joined acknowledgement
+        let joined = join(inputs).unwrap();
+        let first = joined.clone();
+
+        self::ack(Some(first), None);
+        assert_eq!(acker.acked(), vec![]);
+
+        self::ack(Some(joined), None);
+        assert_eq!(acker.acked(), vec![(0, None), (1, None)]);
+    }
+
+    #[test]
+    fn failed_output_fails_joined_inputs() {
+        let acker = Arc::new(Recorder::default());
+        let inputs = (0..2).map(|r| Some(Acknowledgement::new(acker.clone(), r))).collect();
+
+        let joined = join(inputs).unwrap();
+        let first = joined.clone();
+
+        self::ack(Some(first), Some(error()));
+        self::ack(Some(joined), None);
+
+        assert_eq!(acker.acked(), vec![(0, Some(error())), (1, Some(error()))]);
+    }
+
+    #[test]
+    fn dropped_output_leaves_joined_inputs_lost() {
+        let acker = Arc::new(Recorder::default());
+        let inputs = (0..2).map(|r| Some(Acknowledgement::new(acker.clone(), r))).collect();
+
+        let joined = join(inputs).unwrap();
+        drop(joined.clone());
+        self::ack(Some(joined), None);
+
+        assert_eq!(acker.acked(), vec![]);
+    }
+
+    #[test]
+    fn join_without_acknowledgements_is_none() {
+        assert!(join(vec![None, None]).is_none());
+    }
+
+    #[test]
+    fn failed_carrier_settle_source_by_its_reason() {
+        let acker = Arc::new(Recorder::default());
+        let ack = Some(Acknowledgement::new(acker.clone(), 1));
+
+        let carried = failed(ack, error());
+        assert_eq!(acker.acked(), vec![]);
+
+        self::ack(carried, None);
+        assert_eq!(acker.acked(), vec![(1, Some(error()))]);
+    }
+
+
+    #[test]
+    fn prefix_advance_once_every_earlier_position_finished() {
+        let mut tracker = PrefixTracker::new();
+        for offset in 0..4 {
+            tracker.consumed("p0", offset);
+        }
+
+        // out of order, 0 still in flight
+        assert_eq!(tracker.finished([("p0", 2), ("p0", 1)]), vec![]);
+
+        assert_eq!(tracker.finished([("p0", 0)]), vec![("p0", 2)]);
+        assert_eq!(tracker.finished([("p0", 3)]), vec![("p0", 3)]);
+    }
+
+    #[test]
+    fn prefix_tracked_per_partition() {
+        let mut tracker = PrefixTracker::new();
+        tracker.consumed("p0", 10);
+        tracker.consumed("p0", 11);
+        tracker.consumed("p1", 5);
+
+        let mut committable = tracker.finished([("p0", 11), ("p1", 5)]);
+        committable.sort();
+        assert_eq!(committable, vec![("p1", 5)]);
+
+        assert_eq!(tracker.finished([("p0", 10)]), vec![("p0", 11)]);
+    }
+
+    #[test]
+    fn prefix_ignore_unknown_position() {
+        let mut tracker = PrefixTracker::new();
+        tracker.consumed("p0", 0);
+
+        assert_eq!(tracker.finished([("p0", 7), ("p1", 0)]), vec![]);
+        assert_eq!(tracker.finished([("p0", 0)]), vec![("p0", 0)]);
+    }
+}
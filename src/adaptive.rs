@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+
+
+/// weight of a new full batch in smoothed throughput
+const THROUGHPUT_SMOOTHING: f64 = 0.2;
+
+
+/// what adaptive batch size aims for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdaptiveTarget {
+
+    /// largest batch `handle_batch` finish within this duration
+    Latency(Duration),
+
+    /// batch size with most messages per second handled by `handle_batch`
+    Throughput
+}
+
+
+
+/// Adaptive batch size of a batcher layer (AIMD)
+///
+///   * batch size starts from `batch_size` of layer (clamped to bounds)
+///     and stays in `min_size ..= max_size`
+///   * grows by `increase` after a full batch handled on target,
+///     shrinks by `decrease` (factor, 0.0 - 1.0) when target missed or batch failed
+///   * `Latency` target missed when `handle_batch` took longer than target,
+///     `Throughput` target missed when a full batch handled slower (messages/s) than
+///     smoothed throughput of previous full batches by more than `tolerance` (fraction, 0.0 - 1.0),
+///     so latency noise not shrink size
+///   * each instance adapts its own size, current size in `BatchSnapshot::target_size`
+///
+/// ```ignore
+/// .batcher(|| Insert)
+/// .adaptive_batch(AdaptiveBatch::latency(Duration::from_millis(200)).bounds(10, 5000))
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveBatch {
+    pub target: AdaptiveTarget,
+    pub min_size: usize,
+    pub max_size: usize,
+    pub increase: usize,
+    pub decrease: f64,
+    pub tolerance: f64
+}
+
+impl AdaptiveBatch {
+
+    pub fn latency(target: Duration) -> Self {
+        AdaptiveBatch {
+            target: AdaptiveTarget::Latency(target),
+            ..Default::default()
+        }
+    }
+
+    pub fn throughput() -> Self {
+        AdaptiveBatch {
+            target: AdaptiveTarget::Throughput,
+            ..Default::default()
+        }
+    }
+
+    pub fn bounds(mut self, min_size: usize, max_size: usize) -> Self {
+        self.min_size = min_size.max(1);
+        self.max_size = max_size.max(self.min_size);
+        self
+    }
+
+    /// messages added to batch size on each step up
+    pub fn increase(mut self, increase: usize) -> Self {
+        self.increase = increase.max(1);
+        self
+    }
+
+    /// batch size multiplied by it on each step down
+    pub fn decrease(mut self, decrease: f64) -> Self {
+        self.decrease = decrease.clamp(0.0, 1.0);
+        self
+    }
+
+    /// fraction of smoothed throughput a full batch can be slower without a step down
+    pub fn tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance.clamp(0.0, 1.0);
+        self
+    }
+}
+
+impl Default for AdaptiveBatch {
+    fn default() -> Self {
+        AdaptiveBatch {
+            target: AdaptiveTarget::Throughput,
+            min_size: 1,
+            max_size: 10_000,
+            increase: 10,
+            decrease: 0.5,
+            tolerance: 0.1
+        }
+    }
+}
+
+
+
+
+/// batch size controller of a batcher instance
+pub(crate) struct AdaptiveSize {
+    opts: AdaptiveBatch,
+    size: usize,
+
+    /// smoothed messages per second of full batches since last step down
+    throughput: Option<f64>
+}
+
+impl AdaptiveSize {
+
+    pub fn new(opts: AdaptiveBatch, batch_size: usize) -> Self {
+        AdaptiveSize {
+            opts,
+            size: batch_size.clamp(opts.min_size, opts.max_size),
+            throughput: None
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+
+    /// adapt size by a handled batch of `size` messages, returns new size
+    ///
+    /// just full batches grow size, a batch flushed by timeout
+    /// is small because of input rate not because of size
+    pub fn observe(&mut self, size: usize, duration: Duration, ok: bool) -> usize {
+        let full = size >= self.size;
+
+        let on_target = ok && match self.opts.target {
+            AdaptiveTarget::Latency(target) => duration <= target,
+            AdaptiveTarget::Throughput if !full => true,
+            AdaptiveTarget::Throughput => {
+                let throughput = size as f64 / duration.as_secs_f64().max(f64::EPSILON);
+                let smoothed = self.throughput.unwrap_or(throughput);
+
+                self.throughput = Some(smoothed + THROUGHPUT_SMOOTHING * (throughput - smoothed));
+
+                throughput >= smoothed * (1.0 - self.opts.tolerance)
+            }
+        };
+
+        if !on_target {
+            self.size = ((self.size as f64 * self.opts.decrease) as usize).max(self.opts.min_size);
+
+            // smaller batches measured from scratch
+            self.throughput = None;
+        } else if full {
+            self.size = self.size.saturating_add(self.opts.increase).min(self.opts.max_size);
+        }
+
+        self.size
+    }
+}
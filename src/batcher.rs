@@ -1,150 +1,674 @@
-use std::time::Duration;
-
 use async_trait::async_trait;
-use tokio::sync::mpsc;
+use futures::future::BoxFuture;
+use tokio::{task::JoinHandle, time::Instant};
+
+use crate::adaptive::{AdaptiveBatch, AdaptiveSize};
+use crate::batches::{Batches, GroupKey, Ready};
+
+use crate::channel::StageReceiver;
+use crate::acknowledger::{self, Acknowledgement};
+use crate::dispatcher::{Dispatcher, DispatchError, RouterType};
+use crate::failure::{catch_panic, DeadLetter, FailReason, SharedSink};
+use crate::message::{BatchKey, Message};
+use crate::metrics::FlushReason;
+use crate::processor::ProcResult;
+use crate::retry::{RetryPolicy, RetryQueue};
+use crate::shutdown_manager::StageId;
+use crate::telemetry::TelemetryEvent;
+use crate::trace::{self, MessageSpan};
+use crate::watchdog::Watch;
+
+
+
+
+pub struct BatcherTerminate<T>(pub Vec<Message<T>>);
+
+
+pub enum BatchError<T> {
+
+    /// whole batch failed, passed to `handle_failed` and failure sink of layer
+    Fail(String),
+
+    /// batcher drain returned messages and terminate
+    Terminate(BatcherTerminate<T>)
+}
+
+impl<T> From<BatcherTerminate<T>> for BatchError<T> {
+    fn from(bt: BatcherTerminate<T>) -> Self {
+        BatchError::Terminate(bt)
+    }
+}
+
+
+
+/// when a not full batch flushed by `batch_timeout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Linger {
+
+    /// `batch_timeout` after first message of batch
+    #[default]
+    Absolute,
+
+    /// `batch_timeout` after last message of batch (not received any message),
+    /// a batch receiving messages faster than timeout not timed out, flushed just when full
+    Idle
+}
+
+
+
+/// Weight of a message for `max_batch_weight`, e.g. payload bytes
+///
+/// ```ignore
+/// .batcher(|| Insert)
+/// .max_batch_weight(MAX_ALLOWED_PACKET, |m: &Message<Row>| m.data.sql.len())
+/// ```
+pub trait BatchWeigher<T>: Send + Sync + 'static {
+    fn weigh(&self, msg: &Message<T>) -> usize;
+}
+
+impl<T, F> BatchWeigher<T> for F
+where
+    F: Fn(&Message<T>) -> usize + Send + Sync + 'static
+{
+    fn weigh(&self, msg: &Message<T>) -> usize {
+        self(msg)
+    }
+}
 
 
+/// a single message heavier than `max_batch_weight`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Oversized {
 
+    /// flushed alone as a batch (after pending batch of its key)
+    #[default]
+    Alone,
 
-pub struct BatcherTerminate<T>(pub Vec<T>);
+    /// failed as `FailReason::Oversized`, passed to `handle_failed` and failure sink
+    Fail
+}
 
 
 
 #[async_trait]
-pub trait BatchProcessor<Input> {
+pub trait BatchProcessor<Input>
+where
+    Input: Send + 'static
+{
     
     async fn init(&mut self);
+
+    /// called before every attempt of `handle_batch`, outside of `Timeouts::handler`,
+    /// instance wait here until it can handle next batch (e.g. `CircuitBreaker` open)
+    async fn ready(&mut self) {}
     
-    /// if return `ProcResult::Dispatch` and Dispatcher mode is `Partition` must set `batch_key`
+    /// handle a group of messages, 
+    /// `key` is `batch_key` of all messages if batcher grouped by key (else `None`)
+    ///   * if return `Err(BatchError::Fail)` batch passed to failure sink
+    ///   * if return `Err(BatchError::Terminate)` batcher drain returned messages and terminate
+    async fn handle_batch(&mut self, key: Option<BatchKey>, batch: Vec<Message<Input>>) -> Result<(), BatchError<Input>>;
+
+    /// called before `handle_batch` of a new batch (not on retries) with what triggered flush
+    async fn on_flush(&mut self, _reason: FlushReason) {}
 
-    async fn handle_batch(&mut self, batch: Vec<Input>) -> Result<(), BatcherTerminate<Input>>;
+    /// called when handle_batch failed (returned `BatchError::Fail` or panicked) on `batch`,
+    /// reason is in `status` of each message, instance keep its state and continue with next batch,
+    /// then batch passed to failure sink of layer
+    ///
+    /// if layer has `RetryPolicy`, called just once all attempts failed
+    async fn handle_failed(&mut self, _batch: &[Message<Input>]) {}
 
-    async fn drain(&mut self, batch: Vec<Input>);
+    async fn drain(&mut self, batch: Vec<Message<Input>>);
 
     async fn terminate(&mut self);
 }
 
 
 
-pub struct Context<Input, Proc>
+
+/// outputs of a handled batch, dispatched to next layer
+pub enum BatchResult<Output> {
+
+    /// every message of batch finished here (acked)
+    Continue,
+
+    /// one result per message of batch, in same order:
+    ///   * `Continue` finish message here
+    ///   * `Dispatch` send output to next layer, use `msg.map(..)` to keep metadata of message
+    ///     (acknowledger carried by framework)
+    ///   * `DispatchMany` send outputs to next layer in order, message acked after all of them
+    ///   * `Fail` just this message passed to `handle_failed` and failure sink, not retried
+    Each(Vec<ProcResult<Output>>),
+
+    /// one output for whole batch (e.g. aggregate),
+    /// messages of batch acked once output finished
+    Batch(Message<Output>)
+}
+
+
+
+/// Batcher in middle of topology, outputs of each batch dispatched to next layer
+///
+/// e.g. enrich records by one lookup per batch, then continue processing each record
+#[async_trait]
+pub trait BatchTransformer<Input, Output>
+where
+    Input: Send + 'static
+{
+
+    async fn init(&mut self);
+
+    /// same as `BatchProcessor::ready`
+    async fn ready(&mut self) {}
+
+    /// handle a group of messages, returns its outputs,
+    /// `key` is `batch_key` of all messages if batcher grouped by key (else `None`),
+    /// `BatchResult::Each` must have one result per message, else whole batch failed
+    ///   * if return `Err(BatchError::Fail)` batch passed to failure sink
+    ///   * if return `Err(BatchError::Terminate)` batcher drain returned messages and terminate
+    async fn handle_batch(&mut self, key: Option<BatchKey>, batch: Vec<Message<Input>>) -> Result<BatchResult<Output>, BatchError<Input>>;
+
+    /// called before `handle_batch` of a new batch (not on retries) with what triggered flush
+    async fn on_flush(&mut self, _reason: FlushReason) {}
+
+    /// same as `BatchProcessor::handle_failed`, also called with a single message
+    /// if its result was `ProcResult::Fail`
+    async fn handle_failed(&mut self, _batch: &[Message<Input>]) {}
+
+    async fn drain(&mut self, batch: Vec<Message<Input>>);
+
+    async fn terminate(&mut self);
+}
+
+
+
+/// `BatchProcessor` as a transformer without outputs, every batch finished here
+pub(crate) struct Terminal<Proc>(pub Proc);
+
+#[async_trait]
+impl<Input, Proc> BatchTransformer<Input, ()> for Terminal<Proc>
+where
+    Input : Send + 'static,
+    Proc  : BatchProcessor<Input> + Send + 'static
+{
+    async fn init(&mut self) {
+        self.0.init().await
+    }
+
+    async fn ready(&mut self) {
+        self.0.ready().await
+    }
+
+    async fn handle_batch(&mut self, key: Option<BatchKey>, batch: Vec<Message<Input>>) -> Result<BatchResult<()>, BatchError<Input>> {
+        self.0.handle_batch(key, batch).await.map(|_| BatchResult::Continue)
+    }
+
+    async fn on_flush(&mut self, reason: FlushReason) {
+        self.0.on_flush(reason).await
+    }
+
+    // returns future of inner batcher, so `Input` not need to be `Sync`
+    fn handle_failed<'life0, 'life1, 'async_trait>(&'life0 mut self, 
+                                                   batch: &'life1 [Message<Input>]) -> BoxFuture<'async_trait, ()>
+    where
+        'life0 : 'async_trait,
+        'life1 : 'async_trait,
+        Self   : 'async_trait
+    {
+        self.0.handle_failed(batch)
+    }
+
+    async fn drain(&mut self, batch: Vec<Message<Input>>) {
+        self.0.drain(batch).await
+    }
+
+    async fn terminate(&mut self) {
+        self.0.terminate().await
+    }
+}
+
+
+
+pub struct Context<Input, Output, Proc>
 where
     Input: Send + 'static,
-    Proc: BatchProcessor<Input> + Send + 'static
+    Output: Send + 'static,
+    Proc: BatchTransformer<Input, Output> + Send + 'static
 {
-    recv: mpsc::Receiver<Input>,
+    id: StageId,
+    recv: StageReceiver<Message<Input>>,
+
+    /// empty if last layer of topology
+    dispatcher: Dispatcher<Output>,
     
-    batch_size: usize,
-    batch_timeout: Duration,
+    /// pending batches, one per key if grouped by key
+    batches: Batches<Input>,
+
+    proc: Proc,
+    failure_sink: SharedSink<Input>,
+
+    /// failure sink of next layer, for outputs could not delivered to it
+    undeliverable: SharedSink<Output>,
+
+    /// last layer of topology, batch finished here
+    terminal: bool,
 
-    proc: Proc
+    retry: Option<RetryPolicy>,
+
+    /// batches waiting for next attempt
+    retries: RetryQueue<(GroupKey, Vec<Message<Input>>)>,
+
+    /// wait on retry inside instance, to keep order of messages
+    ordered: bool,
+
+    /// adapt batch size by duration of `handle_batch`
+    adaptive: Option<AdaptiveSize>,
+
+    watch: Watch
 }
 
-impl<Input, Proc> Context<Input, Proc> 
+impl<Input, Output, Proc> Context<Input, Output, Proc> 
 where
     Input  : Clone + Send + 'static,
-    Proc   : BatchProcessor<Input> + Send + 'static
+    Output : Clone + Send + 'static,
+    Proc   : BatchTransformer<Input, Output> + Send + 'static
 {
     
-    pub fn new(recv: mpsc::Receiver<Input>,
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(id: StageId,
+               recv: StageReceiver<Message<Input>>,
+               dispatcher: Dispatcher<Output>,
                proc: Proc,
-               batch_size: usize,
-               batch_timeout: Duration
+               batches: Batches<Input>,
+               failure_sink: SharedSink<Input>,
+               undeliverable: SharedSink<Output>
                ) -> Self 
     {
         Context { 
+            id,
             recv, 
-            batch_size: batch_size,
-            batch_timeout,
+            terminal: dispatcher.is_empty(),
+            dispatcher,
+            batches,
             proc,  
+            failure_sink,
+            undeliverable,
+            retry: None,
+            retries: RetryQueue::new(),
+            ordered: false,
+            adaptive: None,
+            watch: Watch::default()
         }
     }
 
 
+    /// retry failed batches by policy, 
+    /// `ordered` if input of layer partitioned by upstream dispatcher
+    pub fn with_retry(mut self, retry: Option<RetryPolicy>, ordered: bool) -> Self {
+        self.ordered = ordered || matches!(self.dispatcher.router_type, RouterType::Partition);
+        self.retry = retry;
+        self
+    }
+
+
+    /// adaptive batch size, starts from `batch_size` of layer
+    pub fn with_adaptive(mut self, adaptive: Option<AdaptiveBatch>) -> Self {
+        self.adaptive = adaptive.map(|opts| {
+            let adaptive = AdaptiveSize::new(opts, self.batches.size());
+            self.batches.set_size(adaptive.size());
+            adaptive
+        });
+        self
+    }
+
+
+    /// timeouts of handlers, progress (watchdog) & metrics
+    pub(crate) fn with_watch(mut self, watch: Watch) -> Self {
+        self.watch = watch;
+        self
+    }
+
+
+    /// room for another retry inside instance
+    fn accepts(&self) -> bool {
+        self.retry.as_ref().is_none_or(|r| r.accepts(self.retries.len()))
+    }
+
+
     #[inline]
-    pub fn run(mut self) {        
+    pub fn run(mut self) -> JoinHandle<()> {        
+        let id = self.id;
+
         // spawn
-        tokio::spawn(async move {            
+        tokio::spawn(trace::stage(id, async move {            
+
+            let _stage = self.watch.telemetry.stage(self.id);
 
             // init Processor
-            self.proc.init().await;
+            self.watch.init(self.id, self.proc.init()).await;
             
-
-            // create time for timeout
-            let sleep = tokio::time::sleep(self.batch_timeout);
-            tokio::pin!(sleep);
-        
-
-            // create batch empty
-            let mut batch = Vec::with_capacity(self.batch_size); 
+            self.watch.metrics.target_size(self.batches.size());
 
             loop {
+                self.watch.metrics.pending(self.batches.len());
+                self.watch.metrics.retrying(self.retries.len());
+
                 tokio::select! {
-                    _ = &mut sleep => {
-                        
-                        // if batch was not empty 
-                        if batch.len() > 0 {
-
-                            // handle_batch
-                            if let Err(bt) = self.proc.handle_batch(batch).await {
-                                
-                                // drain
-                                self.proc.drain(bt.0).await;
-
-                                self.proc.terminate().await;
-                            }
+                    Some(p) = self.retries.next() => {
+                        let (key, batch) = p.item;
 
-                            // create batch empty
-                            batch = Vec::with_capacity(self.batch_size);
+                        if self.flush(key, batch, p.attempt).await {
+                            return
                         }
                     }
-                    res = self.recv.recv() => {
+
+                    // batch not full before its deadline
+                    (key, batch) = self.batches.expired() => {
+                        if self.flush_by(FlushReason::Timeout, key, batch).await {
+                            return
+                        }
+                    }
+                    // retries at `max_retrying`, not receive until one finished (backpressure)
+                    res = self.recv.recv(), if self.accepts() => {
                         match res {
                             Some(msg) => {
-                                // push to batch
-                                batch.push(msg);
-
-                                // if batch was full
-                                if batch.len() == self.batch_size {
-                                    
-                                    // handle batch
-                                    let _ = self.proc.handle_batch(batch).await;
+                                self.watch.metrics.received(1);
 
-                                    // create batch empty
-                                    batch = Vec::with_capacity(self.batch_size);
+                                // push to batch of its key, then flush batches reached size or weight
+                                self.batches.push(msg);
 
+                                if self.flush_ready().await {
+                                    return
                                 }
                             }
                             None => {
-                                if batch.len() > 0 {
+                                while let Some((key, batch)) = self.batches.pop() {
+                                    self.watch.metrics.pending(self.batches.len());
+
+                                    if self.flush_by(FlushReason::Shutdown, key, batch).await {
+                                        return
+                                    }
+                                }
 
-                                    // handle_batch
-                                    if let Err(bt) = self.proc.handle_batch(batch).await {
+                                // finish batches waiting for retry
+                                while let Some(p) = self.retries.next().await {
+                                    let (key, batch) = p.item;
 
-                                        // drain
-                                        self.proc.drain(bt.0).await;
-                                    
+                                    if self.flush(key, batch, p.attempt).await {
+                                        return
                                     }
+                                    self.watch.metrics.retrying(self.retries.len());
+                                }
 
-                                    // call terminate
-                                    self.proc.terminate().await;
+                                // call terminate
+                                self.watch.terminate(self.id, self.proc.terminate()).await;
 
-                                    return
+                                return
+                            }
+                        }
+                    }
+                }
+            }
 
-                                } else {
+        }))
+    }
 
-                                    // call terminate
-                                    self.proc.terminate().await;
 
-                                    return
-                                }
-                            }
+    /// flush batches removed by push, returns true if batcher terminated
+    async fn flush_ready(&mut self) -> bool {
+        while let Some(ready) = self.batches.next_ready() {
+            match ready {
+                Ready::Batch(reason, key, batch) => {
+                    if self.flush_by(reason, key, batch).await {
+                        return true
+                    }
+                }
+                Ready::Oversized(mut msg, weight) => {
+                    let max = self.batches.max_weight();
+                    let ack = msg.acknowledger.take();
+
+                    self.fail(vec![msg], vec![ack], FailReason::Oversized { weight, max }, 1).await;
+                }
+            }
+        }
+
+        false
+    }
+
+
+    /// flush a new batch (not a retry)
+    async fn flush_by(&mut self, reason: FlushReason, key: GroupKey, batch: Vec<Message<Input>>) -> bool {
+        self.watch.metrics.flush(reason, batch.len());
+        self.proc.on_flush(reason).await;
+        self.flush(key, batch, 1).await
+    }
+
+
+    /// handle batch, returns true if batcher terminated 
+    /// (same on every flush path: full, timeout, channel closed)
+    async fn flush(&mut self, key: GroupKey, batch: Vec<Message<Input>>, attempt: usize) -> bool {
+
+        // each attempt is a new trace, linked to span of messages
+        let span = MessageSpan::batch(self.id, &batch, attempt);
+
+        match span.instrument(self.handle_batch(key, batch, attempt, span.clone())).await {
+            Ok(()) => false,
+            Err(bt) => {
+
+                // drain
+                self.proc.drain(bt.0).await;
+
+                // pending batches of other keys and batches waiting for retry also drained, not acked
+                for (_, batch) in self.batches.take_all() {
+                    self.proc.drain(batch).await;
+                }
+
+                for p in self.retries.take_all() {
+                    self.proc.drain(p.item.1).await;
+                }
+
+                self.watch.metrics.pending(0);
+
+                self.watch.terminate(self.id, self.proc.terminate()).await;
+
+                true
+            }
+        }
+    }
+
+
+    /// a failed batch (or panic inside handle_batch) not crash instance,
+    /// batch retried by retry policy, if not retried (or all attempts failed)
+    /// passed to handle_failed and failure sink
+    ///
+    /// messages acked after batch handled (or its outputs finished), 
+    /// if `BatcherTerminate` returned whole batch not acked, so source can redeliver it
+    async fn handle_batch(&mut self, 
+                          key: GroupKey,
+                          mut batch: Vec<Message<Input>>, 
+                          mut attempt: usize, 
+                          span: MessageSpan) -> Result<(), BatcherTerminate<Input>> {
+
+        // acknowledgers carried by framework, not by batch processor
+        let acks = batch.iter_mut()
+            .map(|m| m.acknowledger.take())
+            .collect::<Vec<_>>();
+
+        let (id, size) = (self.id, acks.len());
+
+        let (copy, reason) = loop {
+
+            let copy = batch.clone();
+
+            self.watch.ready(self.proc.ready()).await;
+
+            self.watch.telemetry.emit(|| TelemetryEvent::BatchStart { id, size, attempt });
+            let start = Instant::now();
+
+            let res = match catch_panic(self.watch.handler(self.proc.handle_batch(key.clone(), batch))).await {
+                Ok(Ok(Ok(BatchResult::Each(results)))) if results.len() != size => {
+                    Err(FailReason::Error(format!("handle_batch returned {} results for {} messages", results.len(), size)))
+                }
+                Ok(Ok(Ok(out))) => Ok(Ok(out)),
+                Ok(Ok(Err(BatchError::Terminate(bt)))) => Ok(Err(bt)),
+                Ok(Ok(Err(BatchError::Fail(e)))) => Err(FailReason::Error(e)),
+                Ok(Err(timeout)) => Err(FailReason::TimedOut(timeout)),
+                Err(reason) => Err(reason)
+            };
+
+            let duration = start.elapsed();
+            self.watch.telemetry.emit(|| TelemetryEvent::BatchStop { id, size, attempt, duration, failed: res.as_ref().err().cloned() });
+
+            if let Some(adaptive) = &mut self.adaptive {
+                let target = adaptive.observe(size, duration, res.is_ok());
+                self.batches.set_size(target);
+                self.watch.metrics.target_size(target);
+            }
+
+            let reason = match res {
+                Ok(Ok(out)) => {
+                    self.emit(out, copy, acks, attempt, span).await;
+                    return Ok(())
+                }
+                Ok(Err(bt)) => return Err(bt),
+                Err(reason) => reason
+            };
+
+            let delay = self.retry.as_ref().and_then(|r| r.delay(&reason, attempt));
+
+            match delay {
+                Some(delay) if self.ordered => {
+
+                    // waiting inside instance also counted as retrying
+                    self.watch.metrics.retrying(self.retries.len() + 1);
+                    tokio::time::sleep(delay).await;
+                    self.watch.metrics.retrying(self.retries.len());
+
+                    batch = copy;
+                    attempt += 1;
+                }
+                Some(delay) => {
+
+                    // acknowledgers wait with batch
+                    let mut copy = copy;
+                    copy.iter_mut()
+                        .zip(acks)
+                        .for_each(|(m, ack)| m.acknowledger = ack);
+
+                    self.retries.push((key, copy), attempt + 1, delay);
+                    return Ok(())
+                }
+                None => break (copy, reason)
+            }
+        };
+
+        self.fail(copy, acks, reason, attempt).await;
+        Ok(())
+    }
+
+
+    /// dispatch outputs of a handled batch, messages finished here acked
+    async fn emit(&mut self, 
+                  out: BatchResult<Output>, 
+                  batch: Vec<Message<Input>>, 
+                  acks: Vec<Option<Acknowledgement>>, 
+                  attempt: usize,
+                  span: MessageSpan) {
+
+        match out {
+            BatchResult::Batch(m) if !self.terminal => {
+                let size = acks.len();
+                self.dispatch(vec![m], acknowledger::join(acks), size, &span).await;
+            }
+            BatchResult::Each(results) => {
+                for ((res, msg), ack) in results.into_iter().zip(batch).zip(acks) {
+                    match res {
+                        ProcResult::Dispatch(m) if !self.terminal => {
+                            self.dispatch(vec![m], ack, 1, &span).await;
+                        }
+                        ProcResult::DispatchMany(outputs) if !self.terminal && !outputs.is_empty() => {
+                            self.dispatch(outputs, ack, 1, &span).await;
+                        }
+                        ProcResult::Continue | ProcResult::Dispatch(_) | ProcResult::DispatchMany(_) => {
+                            self.watch.metrics.sent(1);
+                            acknowledger::ack(ack, None);
+                        }
+                        ProcResult::Fail(e) => {
+                            self.fail(vec![msg], vec![ack], FailReason::Error(e), attempt).await;
                         }
                     }
                 }
             }
+            BatchResult::Continue | BatchResult::Batch(_) => {
+                self.watch.metrics.sent(acks.len());
+                acknowledger::ack_all(acks, None);
+            }
+        }
+    }
 
-        });
+
+    /// dispatch outputs in order, each one carrying a copy of acknowledger of `size` messages
+    /// (acked after all outputs finished), outputs wait for room in next layer one by one,
+    /// if next layer not exist any instance (all terminated) passed to failure sink of next layer
+    async fn dispatch(&mut self, 
+                      outputs: Vec<Message<Output>>, 
+                      mut ack: Option<Acknowledgement>, 
+                      size: usize, 
+                      span: &MessageSpan) {
+
+        let last = outputs.len().saturating_sub(1);
+        let mut delivered = true;
+
+        for (i, mut m) in outputs.into_iter().enumerate() {
+
+            // original acknowledgement kept until last output
+            m.acknowledger = if i == last { ack.take() } else { ack.clone() };
+
+            // span of output on next layer become child of batch span
+            m.span = span.clone();
+
+            let mut m = match self.dispatcher.dispatch(m).await {
+                Ok(_) => continue,
+                Err(DispatchError::NotExist(m)) | Err(DispatchError::NotFound(m)) => m
+            };
+
+            delivered = false;
+
+            let ack = m.acknowledger.take();
+
+            let dl = DeadLetter::new(m, FailReason::Undeliverable, self.id, 1);
+            self.undeliverable.sink(vec![(dl, ack)]).await;
+        }
+
+        if delivered {
+            self.watch.metrics.sent(size);
+        } else {
+            self.watch.metrics.failed(size);
+        }
     }
 
 
+    /// failed messages passed to handle_failed and failure sink, which ack them as failed
+    async fn fail(&mut self, 
+                  batch: Vec<Message<Input>>, 
+                  acks: Vec<Option<Acknowledgement>>, 
+                  reason: FailReason, 
+                  attempt: usize) {
+
+        let failed = batch.into_iter()
+            .map(|m| m.failed(reason.clone()))
+            .collect::<Vec<_>>();
+
+        self.proc.handle_failed(&failed).await;
+
+        self.watch.metrics.failed(acks.len());
+
+        // sources acked once failure sink took dead letters
+        let failed = failed.into_iter()
+            .map(|m| DeadLetter::new(m, reason.clone(), self.id, attempt))
+            .zip(acks)
+            .collect();
+
+        self.failure_sink.sink(failed).await;
+    }
 }
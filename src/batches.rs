@@ -0,0 +1,273 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, VecDeque}, sync::Arc, time::Duration};
+
+use indexmap::IndexMap;
+use tokio::time::Instant;
+
+use crate::{
+    batcher::{BatchWeigher, Linger, Oversized},
+    message::{BatchKey, Message},
+    metrics::FlushReason
+};
+
+
+
+/// key of a pending batch, `None` if batcher not grouped by key
+/// (or message not have `batch_key`)
+pub(crate) type GroupKey = Option<BatchKey>;
+
+
+/// when pending batches flushed
+pub(crate) struct BatchLimits<T> {
+    pub size: usize,
+    pub timeout: Duration,
+    pub linger: Linger,
+
+    /// pending batch per key
+    pub by_key: bool,
+
+    /// max total weight of a batch and weigher of messages
+    pub weight: Option<(usize, Arc<dyn BatchWeigher<T>>)>,
+    pub oversized: Oversized
+}
+
+impl<T> Clone for BatchLimits<T> {
+    fn clone(&self) -> Self {
+        BatchLimits {
+            weight: self.weight.clone(),
+            ..*self
+        }
+    }
+}
+
+
+/// removed by `push`, must be flushed (or failed) by batcher in order
+pub(crate) enum Ready<T> {
+    Batch(FlushReason, GroupKey, Vec<Message<T>>),
+
+    /// message heavier than max weight with its weight (`Oversized::Fail`)
+    Oversized(Message<T>, usize)
+}
+
+
+/// pending batch of a key
+struct Batch<T> {
+    messages: Vec<Message<T>>,
+    weight: usize,
+    deadline: Instant,
+
+    /// deadline of its entry in heap, behind `deadline` if idle linger moved it
+    scheduled: Instant
+}
+
+
+/// pending batches of a batcher instance, one per key (just one if not grouped by key),
+/// each one flushed by its own size, weight and deadline
+///
+/// a batch created by its first message and removed when flushed,
+/// so keys not seen for a while not hold any memory
+pub(crate) struct Batches<T> {
+    batches: IndexMap<GroupKey, Batch<T>>,
+
+    /// one entry per batch, entry is stale if its batch flushed (skipped when reached),
+    /// if idle linger moved deadline of batch entry pushed again when reached
+    deadlines: BinaryHeap<Reverse<(Instant, GroupKey)>>,
+
+    ready: VecDeque<Ready<T>>,
+
+    limits: BatchLimits<T>,
+
+    /// messages of all batches
+    len: usize
+}
+
+impl<T> Batches<T>
+where
+    T: 'static
+{
+
+    pub fn new(limits: BatchLimits<T>) -> Self {
+        Batches {
+            batches: IndexMap::new(),
+            deadlines: BinaryHeap::new(),
+            ready: VecDeque::new(),
+            limits,
+            len: 0
+        }
+    }
+
+
+    /// add message to batch of its key, batches reached
+    /// their size or weight can be taken by `next_ready`
+    pub fn push(&mut self, msg: Message<T>) {
+        let key = if self.limits.by_key { msg.batch_key.clone() } else { None };
+
+        let (weight, max_weight) = match &self.limits.weight {
+            Some((max, weigher)) => (weigher.weigh(&msg), *max),
+            None => (0, usize::MAX)
+        };
+
+        if weight > max_weight {
+
+            // pending messages of key flushed first, to keep order
+            self.ready_batch(FlushReason::Weight, &key);
+
+            let ready = match self.limits.oversized {
+                Oversized::Alone => Ready::Batch(FlushReason::Weight, key, vec![msg]),
+                Oversized::Fail  => Ready::Oversized(msg, weight)
+            };
+
+            self.ready.push_back(ready);
+            return
+        }
+
+        // next message not fit in pending batch
+        if self.batches.get(&key).is_some_and(|b| b.weight + weight > max_weight) {
+            self.ready_batch(FlushReason::Weight, &key);
+        }
+
+        let deadline = Instant::now() + self.limits.timeout;
+
+        let batch = self.batches
+            .entry(key.clone())
+            .or_insert_with(|| Batch {
+                messages: Vec::new(),
+                weight: 0,
+                deadline,
+                scheduled: deadline
+            });
+
+        // first message start deadline, on idle linger every message move it
+        if batch.messages.is_empty() {
+            self.deadlines.push(Reverse((deadline, key.clone())));
+        } else if self.limits.linger == Linger::Idle {
+            batch.deadline = deadline;
+        }
+
+        batch.messages.push(msg);
+        batch.weight += weight;
+        self.len += 1;
+
+        if batch.messages.len() >= self.limits.size {
+            self.ready_batch(FlushReason::Size, &key);
+        } else if batch.weight == max_weight {
+            self.ready_batch(FlushReason::Weight, &key);
+        }
+    }
+
+
+    /// next batch (or oversized message) removed by `push`
+    pub fn next_ready(&mut self) -> Option<Ready<T>> {
+        self.ready.pop_front()
+    }
+
+
+    /// wait until deadline of a batch reached, returns that batch,
+    /// never finish if not exist any batch
+    ///
+    /// cancel safe, deadline removed only after reached
+    pub async fn expired(&mut self) -> (GroupKey, Vec<Message<T>>) {
+        loop {
+            let deadline = match self.deadlines.peek() {
+                Some(Reverse((deadline, _))) => *deadline,
+                None => return std::future::pending().await
+            };
+
+            tokio::time::sleep_until(deadline).await;
+
+            let Some(Reverse((deadline, key))) = self.deadlines.pop() else { continue };
+
+            let Some(batch) = self.batches.get_mut(&key) else { continue };
+
+            // entry of a flushed batch of same key
+            if batch.scheduled != deadline {
+                continue
+            }
+
+            // idle linger moved deadline after entry pushed
+            if batch.deadline > deadline {
+                batch.scheduled = batch.deadline;
+                self.deadlines.push(Reverse((batch.deadline, key)));
+                continue
+            }
+
+            let messages = self.remove(&key);
+            return (key, messages)
+        }
+    }
+
+
+    /// remove a batch (input closed)
+    pub fn pop(&mut self) -> Option<(GroupKey, Vec<Message<T>>)> {
+        let (key, batch) = self.batches.pop()?;
+        self.len -= batch.messages.len();
+
+        if self.batches.is_empty() {
+            self.deadlines.clear();
+        }
+
+        Some((key, batch.messages))
+    }
+
+
+    /// remove all batches and ready ones (instance terminated)
+    pub fn take_all(&mut self) -> Vec<(GroupKey, Vec<Message<T>>)> {
+        self.deadlines.clear();
+        self.len = 0;
+
+        let ready = self.ready
+            .drain(..)
+            .map(|r| match r {
+                Ready::Batch(_, key, messages) => (key, messages),
+                Ready::Oversized(msg, _) => (msg.batch_key.clone(), vec![msg])
+            });
+
+        let pending = self.batches
+            .drain(..)
+            .map(|(key, b)| (key, b.messages));
+
+        ready.chain(pending).collect()
+    }
+
+
+    /// messages of all batches
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+
+    /// max messages in a batch
+    pub fn size(&self) -> usize {
+        self.limits.size
+    }
+
+    /// change max messages in a batch (adaptive batch size),
+    /// a pending batch reached new size flushed by its next message
+    pub fn set_size(&mut self, size: usize) {
+        self.limits.size = size.max(1);
+    }
+
+
+    /// `max_batch_weight`, `usize::MAX` if not set
+    pub fn max_weight(&self) -> usize {
+        self.limits.weight.as_ref().map_or(usize::MAX, |(max, _)| *max)
+    }
+
+
+    fn ready_batch(&mut self, reason: FlushReason, key: &GroupKey) {
+        let messages = self.remove(key);
+
+        if !messages.is_empty() {
+            self.ready.push_back(Ready::Batch(reason, key.clone(), messages));
+        }
+    }
+
+    fn remove(&mut self, key: &GroupKey) -> Vec<Message<T>> {
+        let messages = self.batches
+            .swap_remove(key)
+            .map(|b| b.messages)
+            .unwrap_or_default();
+
+        self.len -= messages.len();
+        messages
+    }
+}
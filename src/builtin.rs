@@ -6,6 +6,11 @@ pub mod collector;
 
 
 
+#[cfg(feature = "dead_letter")]
+pub mod dead_letter;
+
+
+
 #[cfg(feature = "kafka")]
 pub mod kafka_producer;
 
@@ -1,9 +1,9 @@
-use std::{collections::VecDeque, time::Duration};
+use std::collections::VecDeque;
 
 use async_trait::async_trait;
-use tokio::sync::mpsc::Receiver;
+use tokio::{sync::mpsc::Receiver, time::Instant};
 
-use crate::{Producer, producer::Terminate};
+use crate::{Producer, producer::Terminate, message::Message};
 
 
 use crate::topology:: {
@@ -18,9 +18,8 @@ use crate::topology:: {
 /// 
 /// listen on channel get and push to buffer
 /// 
-///     * when buffer was full then dispatch 
-///     * or timeout happen, then dispatch( if buffer.len > 0) 
-
+///   * when buffer was full then dispatch 
+///   * or timeout happen, then dispatch( if buffer.len > 0) 
 pub struct Collector<Input> {
     recv: Receiver<Input>,
     tp: ProcessingType
@@ -47,18 +46,20 @@ where
 
     async fn terminate(&mut self) { }
 
-    async fn drain(&mut self, _buffer: VecDeque<Input>) { }
+    async fn drain(&mut self, _buffer: VecDeque<Message<Input>>) { }
 
-    async fn fill_buffer(&mut self, buffer_size:usize) ->  Result<VecDeque<Input>, Terminate> {
+    async fn fill_buffer(&mut self, buffer_size:usize) ->  Result<VecDeque<Message<Input>>, Terminate> {
 
         let mut buffer = VecDeque::with_capacity(buffer_size);
 
-        let sleep = match self.tp {
+        let timeout = match self.tp {
             ProcessingType::RealTime => PRODUCER_FILLBUFFER_TIMEOUT_REALTIME,
             ProcessingType::Batch    => PRODUCER_FILLBUFFER_TIMEOUT_BATCH,
             ProcessingType::CustomTimeout(d) => d
         };
 
+        let sleep = tokio::time::sleep(timeout);
+
         tokio::pin!(sleep);
         
         loop {
@@ -66,23 +67,26 @@ where
                 _ = &mut sleep => {
 
                     // if was not empty
-                    if buffer.len() > 0 {
+                    if !buffer.is_empty() {
 
                         // return buffer
                         return Ok(buffer)
                     }
+
+                    // wait another timeout, an elapsed sleep is always ready
+                    sleep.as_mut().reset(Instant::now() + timeout);
                 }
                 res = self.recv.recv() => {
                     match res {
                         Some(msg) => {
-                            buffer.push_back(msg);
+                            buffer.push_back(Message::new(msg));
                             if buffer.len() == buffer_size {
                                 return Ok(buffer)
                             }
                         }
                         None => {
                             // not exist any sender, shutdown
-                            if buffer.len() > 0 {
+                            if !buffer.is_empty() {
                                 
                                 // return buffer
                                 return Ok(buffer)
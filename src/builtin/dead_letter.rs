@@ -0,0 +1,134 @@
+use std::{fmt::Debug, path::PathBuf};
+
+use async_trait::async_trait;
+use tokio::{fs::{File, OpenOptions}, io::AsyncWriteExt, sync::mpsc::Sender};
+
+use crate::{
+    BatchProcessor,
+    BatchError,
+    Processor,
+    ProcResult,
+    failure::DeadLetter,
+    message::{BatchKey, Message},
+    trace
+};
+
+
+
+
+/// This module provide dead letter stages
+///
+///   * FileDeadLetter append dead letters to a file (one line per message)
+///   * ChannelDeadLetter send dead letters to a channel
+///
+/// ```ignore
+/// .dead_letter_batcher(|| FileDeadLetter::new("dead_letters.log"))
+///
+/// .dead_letter(move || ChannelDeadLetter::new(sender.clone()))
+/// ```
+pub struct FileDeadLetter {
+    path: PathBuf,
+    file: Option<File>
+}
+
+impl FileDeadLetter {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileDeadLetter {
+            path: path.into(),
+            file: None
+        }
+    }
+
+
+    async fn write<T>(&mut self, batch: &[Message<DeadLetter<T>>]) -> std::io::Result<()>
+    where
+        T: Debug
+    {
+        let file = match self.file {
+            Some(ref mut file) => file,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path).await?;
+
+                self.file.insert(file)
+            }
+        };
+
+        let mut lines = String::new();
+        for msg in batch {
+            let dl = &msg.data;
+            lines.push_str(&format!("{} attempts={} reason={:?} message={:?}\n",
+                                    dl.stage,
+                                    dl.attempts,
+                                    dl.reason,
+                                    dl.message));
+        }
+
+        file.write_all(lines.as_bytes()).await?;
+        file.flush().await
+    }
+}
+
+
+#[async_trait]
+impl<T> BatchProcessor<DeadLetter<T>> for FileDeadLetter
+where
+    T: Debug + Send + Sync + 'static
+{
+    async fn init(&mut self) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, batch: Vec<Message<DeadLetter<T>>>) -> Result<(), BatchError<DeadLetter<T>>> {
+
+        self.write(&batch).await
+            .map_err(|e| BatchError::Fail(e.to_string()))
+    }
+
+    async fn drain(&mut self, batch: Vec<Message<DeadLetter<T>>>) {
+        if let Err(e) = self.write(&batch).await {
+            trace::error!("FileDeadLetter {:?} drain failed: {}", self.path, e);
+        }
+    }
+
+    async fn terminate(&mut self) {
+        if let Some(ref mut file) = self.file {
+            let _ = file.sync_all().await;
+        }
+    }
+}
+
+
+
+
+pub struct ChannelDeadLetter<T> {
+    sender: Sender<DeadLetter<T>>
+}
+
+impl<T> ChannelDeadLetter<T> {
+    pub fn new(sender: Sender<DeadLetter<T>>) -> Self {
+        ChannelDeadLetter {
+            sender
+        }
+    }
+}
+
+
+#[async_trait]
+impl<T> Processor<DeadLetter<T>, ()> for ChannelDeadLetter<T>
+where
+    T: Send + 'static
+{
+    async fn init(&mut self) {}
+
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<DeadLetter<T>>) -> ProcResult<()> {
+
+        // receiver dropped, keep dead letter in failure sink of dead letter stage
+        match self.sender.send(msg.data).await {
+            Ok(_) => ProcResult::Continue,
+            Err(_) => ProcResult::Fail("==> ChannelDeadLetter receiver closed".to_owned())
+        }
+    }
+}
@@ -3,7 +3,7 @@ use std::time::Duration;
 use async_trait::async_trait;
 use rdkafka::{producer::{FutureProducer, FutureRecord}, ClientConfig, error::KafkaError, util::Timeout};
 
-use crate::{ProcResult, Processor};
+use crate::{ProcResult, Processor, message::Message};
 
 
 #[derive(Clone)]
@@ -55,13 +55,13 @@ impl Processor<ProcKafkaMessage, OwnedDeliveryResult> for KafkaProcessor {
 
     async fn terminate(&mut self) { }
 
-    async fn handle_message(&mut self, msg: ProcKafkaMessage) -> ProcResult<OwnedDeliveryResult> {
+    async fn handle_message(&mut self, msg: Message<ProcKafkaMessage>) -> ProcResult<OwnedDeliveryResult> {
 
         let rec = 
             FutureRecord::to(&self.topic_name) 
-                    .payload(&msg.payload)
-                    .key(&msg.key)
-                    .partition(msg.partition);
+                    .payload(&msg.data.payload)
+                    .key(&msg.data.key)
+                    .partition(msg.data.partition);
         
 
         let res = self.fut_producer.send(rec, Timeout::Never).await;
@@ -71,12 +71,12 @@ impl Processor<ProcKafkaMessage, OwnedDeliveryResult> for KafkaProcessor {
                     Ok((Partition(p), Offset(o)))
                 }   
                 Err((ke, _)) => {
-                    Err((ke, msg))
+                    Err((ke, msg.data.clone()))
                 }         
             };
         
         
-        ProcResult::Dispatch(delivery, None)
+        ProcResult::Dispatch(msg.replace(delivery))
     }
     
 }
@@ -1,9 +1,16 @@
-use std::{collections::VecDeque, time::Duration};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration
+};
 
 use async_trait::async_trait;
-use rdkafka::{ClientConfig, config::RDKafkaLogLevel, ClientContext, consumer::{ConsumerContext, Rebalance, StreamConsumer, Consumer, CommitMode}, error::KafkaResult, TopicPartitionList, Message};
+use tokio::time::Instant;
+use rdkafka::{ClientConfig, config::RDKafkaLogLevel, ClientContext, consumer::{ConsumerContext, Rebalance, StreamConsumer, Consumer}, error::KafkaResult, TopicPartitionList, Message};
 
-use crate::{Producer, producer::Terminate};
+use crate::{Producer, producer::Terminate, message::Message as SkyMessage, FailReason};
+use crate::acknowledger::{Acknowledger, Acknowledgement, PrefixTracker};
+use crate::trace;
 
 use crate::topology:: {
     ProcessingType,
@@ -50,8 +57,65 @@ impl ConsumerContext for CustomContext {
 }
 
 
+/// Position of a consumed message
+#[derive(Debug, Clone)]
+pub struct KafkaOffset {
+    pub topic     : String,
+    pub partition : i32,
+    pub offset    : i64,
+}
+
+
+/// store offset of finished messages, stored offsets committed by auto commit,
+/// so offset committed only after message finished last stage (at-least-once)
+///
+/// messages finish out of order, so per partition just highest offset 
+/// that every message before it finished is stored
+///
+/// failed messages also stored (kafka commit by partition offset),
+/// handle them by `handle_failed`
+pub struct KafkaAcker {
+    kafka_consumer: Arc<LoggingConsumer>,
+    in_flight: Mutex<PrefixTracker<(String, i32)>>
+}
+
+impl KafkaAcker {
+
+    /// register a consumed message, before it dispatched
+    fn consumed(&self, o: &KafkaOffset) {
+        self.lock().consumed((o.topic.clone(), o.partition), o.offset);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PrefixTracker<(String, i32)>> {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Acknowledger for KafkaAcker {
+    type Ref = KafkaOffset;
+
+    fn ack(&self, successful: Vec<KafkaOffset>, failed: Vec<(KafkaOffset, FailReason)>) {
+
+        let offsets = successful
+            .into_iter()
+            .chain(failed.into_iter().map(|(o, _)| o))
+            .map(|o| ((o.topic, o.partition), o.offset));
+
+        let committable = self.lock().finished(offsets);
+
+        for ((topic, partition), offset) in committable {
+            if let Err(e) = self.kafka_consumer.store_offset(&topic, partition, offset) {
+                trace::error!("Kafka store offset error: {}", e);
+            }
+        }
+    }
+}
+
+
+
 pub struct KafkaProducer {
-    kafka_consumer: LoggingConsumer,
+    kafka_consumer: Arc<LoggingConsumer>,
+    acker: Arc<KafkaAcker>,
     tp: ProcessingType
 }
 
@@ -72,6 +136,7 @@ impl KafkaProducer {
             .set("enable.partition.eof", format!("{}",enable_partition_eof))
             .set("session.timeout.ms", session_timeout_ms)
             .set("enable.auto.commit", "true")
+            .set("enable.auto.offset.store", "false")
             .set("auto.offset.reset", auto_offset_reset)
             .set_log_level(RDKafkaLogLevel::Debug)
             .create_with_context(context)
@@ -81,7 +146,13 @@ impl KafkaProducer {
             .subscribe(&topics.to_vec())
             .expect("Can't subscribe to specified topics");
     
+        let consumer = Arc::new(consumer);
+
         KafkaProducer {
+            acker: Arc::new(KafkaAcker { 
+                kafka_consumer: consumer.clone(),
+                in_flight: Mutex::new(PrefixTracker::new())
+            }),
             kafka_consumer: consumer,
             tp
         }
@@ -95,21 +166,23 @@ impl Producer<ProdKafkaMessage> for KafkaProducer {
 
     async fn terminate(&mut self) {}
 
-    async fn drain(&mut self, _buffer: VecDeque<ProdKafkaMessage>) { }
+    async fn drain(&mut self, _buffer: VecDeque<SkyMessage<ProdKafkaMessage>>) { }
 
     
 
-    async fn fill_buffer(&mut self, buffer_size:usize) ->  Result<VecDeque<ProdKafkaMessage>, Terminate> {
+    async fn fill_buffer(&mut self, buffer_size:usize) ->  Result<VecDeque<SkyMessage<ProdKafkaMessage>>, Terminate> {
 
         // create buffer
         let mut buffer = VecDeque::with_capacity(buffer_size);
 
 
-        let sleep = match self.tp {
+        let timeout = match self.tp {
             ProcessingType::RealTime => PRODUCER_FILLBUFFER_TIMEOUT_REALTIME,
             ProcessingType::Batch    => PRODUCER_FILLBUFFER_TIMEOUT_BATCH,
             ProcessingType::CustomTimeout(d) => d
         };
+
+        let sleep = tokio::time::sleep(timeout);
         
         tokio::pin!(sleep);
         
@@ -123,16 +196,19 @@ impl Producer<ProdKafkaMessage> for KafkaProducer {
                         // return buffer
                         return Ok(buffer)
                     }
+
+                    // wait another timeout, an elapsed sleep is always ready
+                    sleep.as_mut().reset(Instant::now() + timeout);
                 }
                 res = self.kafka_consumer.recv() => {
                     match res {
-                        Err(e) => eprintln!("Kafka error: {}", e),
+                        Err(e) => trace::error!("Kafka error: {}", e),
                         Ok(m) => {
                             let payload = match m.payload_view::<str>() {
                                 None => "".to_owned(),
                                 Some(Ok(s)) => s.to_owned(),
                                 Some(Err(e)) => {
-                                    eprintln!("Error while deserializing message payload: {:?}", e);                                    
+                                    trace::warning!("Error while deserializing message payload: {:?}", e);                                    
                                     "".to_owned()
                                 }
                             };       
@@ -154,6 +230,20 @@ impl Producer<ProdKafkaMessage> for KafkaProducer {
                                 topic: m.topic().to_owned(), 
                                 partition: m.partition()
                             };
+
+                            // offset stored when message and every message before it finished
+                            let offset = KafkaOffset {
+                                topic: m.topic().to_owned(),
+                                partition: m.partition(),
+                                offset: m.offset()
+                            };
+                            self.acker.consumed(&offset);
+
+                            let kmsg = SkyMessage::new(kmsg)
+                                .with_metadata("topic", m.topic())
+                                .with_metadata("partition", m.partition().to_string())
+                                .with_metadata("offset", m.offset().to_string())
+                                .with_acknowledger(Acknowledgement::new(self.acker.clone(), offset));
                             
                             // push to buffer
                             buffer.push_back(kmsg);
@@ -162,9 +252,6 @@ impl Producer<ProdKafkaMessage> for KafkaProducer {
                             if buffer.len() == buffer_size {
                                 return Ok(buffer)
                             }
-
-            
-                            self.kafka_consumer.commit_message(&m, CommitMode::Async).unwrap();
                         }
                     }
                 }
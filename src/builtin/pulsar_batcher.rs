@@ -1,83 +1,109 @@
-use std::{time::Duration, sync::{Arc, atomic::{AtomicI32, Ordering}}};
+use std::sync::atomic::{AtomicI32, Ordering};
 
 use async_trait::async_trait;
-use pulsar::{Producer, Pulsar};
+use futures::future::try_join_all;
+use pulsar::{Producer, Pulsar, TokioExecutor};
 
 pub use pulsar::{
     SerializeMessage,
     Error,
-    ProducerOptions,
-    producer::SendFuture
+    ProducerOptions
 };
 
 
-use crate::{ProcResult, Processor};
+use crate::{BatchError, BatchProcessor, message::{BatchKey, Message}};
 
 
-static GLOBAL_PULSAR_PROCESSOR_INSTANCE_COUNTER: Arc<AtomicI32> = Arc::new(AtomicI32::new(1));
+static GLOBAL_PULSAR_BATCHER_INSTANCE_COUNTER: AtomicI32 = AtomicI32::new(1);
 
 
 
+/// send each batch to a pulsar topic,
+/// producer created by `init` (each instance has a unique producer name)
+///
+/// batch finished once pulsar received every message of it,
+/// a failed send fail whole batch (retried by `RetryPolicy` of layer)
 pub struct PulsarBatchProcessor {
-    pulsar_producer: Producer<_>
+    pulsar: Pulsar<TokioExecutor>,
+    opts: ProducerOptions,
+    topic: String,
+    producer_name: String,
+
+    /// set by `init`
+    pulsar_producer: Option<Producer<TokioExecutor>>
 }
 
 impl PulsarBatchProcessor {
-    pub fn new(pulsar: Pulsar<_>, 
+    pub fn new(pulsar: Pulsar<TokioExecutor>,
                opts: ProducerOptions,
-               topic: &str, 
-               pulsar_instance_name: &str,
-            ) -> Result<Self, Error> {
-        
+               topic: &str,
+               pulsar_instance_name: &str) -> Self
+    {
         let new_id = get_new_id();
 
-
-        let producer = pulsar
-        .producer()
-        .with_topic(topic)
-        .with_name(format!("{}-{}", pulsar_instance_name, new_id))
-        .with_options(opts)
-        .build()
-        .await?;
-
-
-        Ok(PulsarBatchProcessor {
-            pulsar_producer: producer
-        })
-    }    
+        PulsarBatchProcessor {
+            pulsar,
+            opts,
+            topic: topic.to_owned(),
+            producer_name: format!("{}-{}", pulsar_instance_name, new_id),
+            pulsar_producer: None
+        }
+    }
 }
 
 
 
 #[async_trait]
-impl BatchProcessor<Input> for PulsarBatchProcessor 
+impl<Input> BatchProcessor<Input> for PulsarBatchProcessor
 where
-    Input: Send + SerializeMessage
+    Input: SerializeMessage + Clone + Send + 'static
 {
-    
-    async fn init(&mut self) { }
 
-    async fn terminate(&mut self) { }
+    async fn init(&mut self) {
+
+        let producer = self.pulsar
+            .producer()
+            .with_topic(self.topic.clone())
+            .with_name(self.producer_name.clone())
+            .with_options(self.opts.clone())
+            .build()
+            .await
+            .unwrap_or_else(|e| panic!("==> Pulsar producer {} creation failed: {}", self.producer_name, e));
 
-    async fn drain(&mut self, batch: Vec<Input>) {}
+        self.pulsar_producer = Some(producer);
+    }
 
+    async fn terminate(&mut self) {
+        self.pulsar_producer = None;
+    }
 
-    async fn handle_batch(&mut self, batch: Vec<Input>) -> ProcResult<DeliveryResult> {
+    // not acked, redelivered by source
+    async fn drain(&mut self, _batch: Vec<Message<Input>>) {}
 
-        let result = 
-            self.pulsar_producer
-                .send_all(batch)
-                .await;
-    
-        ProcResult::Dispatch(delivery, None)
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, batch: Vec<Message<Input>>) -> Result<(), BatchError<Input>> {
+
+        let producer = self.pulsar_producer
+            .as_mut()
+            .expect("==> PulsarBatchProcessor handle_batch called before init");
+
+        let batch = batch.into_iter().map(|m| m.data);
+
+        // wait for receipts, so batch acked just after pulsar received all of it
+        let receipts = match producer.send_all(batch).await {
+            Ok(deliveries) => try_join_all(deliveries).await.map(drop),
+            Err(e) => Err(e)
+        };
+
+        receipts.map_err(|e| BatchError::Fail(format!("Pulsar send error: {}", e)))
     }
-    
+
 }
 
 
 
 
 fn get_new_id() -> i32 {
-    GLOBAL_PULSAR_PROCESSOR_INSTANCE_COUNTER
+    GLOBAL_PULSAR_BATCHER_INSTANCE_COUNTER
         .fetch_add(1, Ordering::SeqCst)
-}
\ No newline at end of file
+}
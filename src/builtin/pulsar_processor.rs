@@ -1,87 +1,100 @@
-use std::{time::Duration, sync::{Arc, atomic::{AtomicI32, Ordering}}};
+use std::sync::atomic::{AtomicI32, Ordering};
 
 use async_trait::async_trait;
-use pulsar::{Producer, Pulsar};
+use pulsar::{Producer, Pulsar, TokioExecutor};
 
 pub use pulsar::{
     SerializeMessage,
     Error,
     ProducerOptions,
-    producer::SendFuture
+    message::proto::CommandSendReceipt
 };
 
 
-use crate::{ProcResult, Processor};
+use crate::{ProcResult, Processor, message::Message};
 
 
-static GLOBAL_PULSAR_PROCESSOR_INSTANCE_COUNTER: Arc<AtomicI32> = Arc::new(AtomicI32::new(1));
+static GLOBAL_PULSAR_PROCESSOR_INSTANCE_COUNTER: AtomicI32 = AtomicI32::new(1);
 
 
 
+/// send each message to a pulsar topic,
+/// producer created by `init` (each instance has a unique producer name)
+///
+/// message dispatched with its `CommandSendReceipt` once pulsar received it,
+/// a failed send returned as `ProcResult::Fail` (retried by `RetryPolicy` of layer)
 pub struct PulsarProcessor {
-    pulsar_producer: Producer<_>
+    pulsar: Pulsar<TokioExecutor>,
+    opts: ProducerOptions,
+    topic: String,
+    producer_name: String,
+
+    /// set by `init`
+    pulsar_producer: Option<Producer<TokioExecutor>>
 }
 
 impl PulsarProcessor {
-    pub fn new(pulsar: Pulsar<_>, 
+    pub fn new(pulsar: Pulsar<TokioExecutor>,
                opts: ProducerOptions,
-               topic: &str, 
-               pulsar_instance_name: &str,
-            ) -> Result<Self, Error> {
-        
+               topic: &str,
+               pulsar_instance_name: &str) -> Self
+    {
         let new_id = get_new_id();
 
-
-        let producer = pulsar
-        .producer()
-        .with_topic(topic)
-        .with_name(format!("{}-{}", pulsar_instance_name, new_id))
-        .with_options(opts)
-        .build()
-        .await?;
-
-
-        Ok(PulsarProcessor {
-            pulsar_producer: producer
-        })
-    }    
+        PulsarProcessor {
+            pulsar,
+            opts,
+            topic: topic.to_owned(),
+            producer_name: format!("{}-{}", pulsar_instance_name, new_id),
+            pulsar_producer: None
+        }
+    }
 }
 
 
 
+#[async_trait]
+impl<Input> Processor<Input, CommandSendReceipt> for PulsarProcessor
+where
+    Input: SerializeMessage + Clone + Send + 'static
+{
 
-/// DeliveryResult returns a SendFuture or Error because the receipt can come long after this function 
-/// was called, for various reasons:
-/// 
-/// 1.  the message was sent successfully but Pulsar did not send the receipt yet
-/// 
-/// 2.  the producer is batching messages, so this function must return immediately, 
-///      and the receipt will come when the batched messages are actually sent
+    async fn init(&mut self) {
 
-pub type DeliveryResult = Result<SendFuture, Error>;
+        let producer = self.pulsar
+            .producer()
+            .with_topic(self.topic.clone())
+            .with_name(self.producer_name.clone())
+            .with_options(self.opts.clone())
+            .build()
+            .await
+            .unwrap_or_else(|e| panic!("==> Pulsar producer {} creation failed: {}", self.producer_name, e));
 
+        self.pulsar_producer = Some(producer);
+    }
 
-#[async_trait]
-impl Processor<Input, DeliveryResult> for PulsarProcessor 
-where
-    Input: Send + SerializeMessage
-{
-    
-    async fn init(&mut self) { }
+    async fn terminate(&mut self) {
+        self.pulsar_producer = None;
+    }
 
-    async fn terminate(&mut self) { }
+    async fn handle_message(&mut self, msg: Message<Input>) -> ProcResult<CommandSendReceipt> {
 
-    async fn handle_message(&mut self, msg: Input) -> ProcResult<DeliveryResult> {
+        let producer = self.pulsar_producer
+            .as_mut()
+            .expect("==> PulsarProcessor handle_message called before init");
 
-        let result = 
-            self.pulsar_producer
-                .send(msg)
-                .await;
+        // wait for receipt, so message acked just after pulsar received it
+        let receipt = match producer.send(msg.data.clone()).await {
+            Ok(delivery) => delivery.await,
+            Err(e) => Err(e)
+        };
 
-    
-        ProcResult::Dispatch(delivery, None)
+        match receipt {
+            Ok(receipt) => ProcResult::Dispatch(msg.replace(receipt)),
+            Err(e) => ProcResult::Fail(format!("Pulsar send error: {}", e))
+        }
     }
-    
+
 }
 
 
@@ -90,4 +103,4 @@ where
 fn get_new_id() -> i32 {
     GLOBAL_PULSAR_PROCESSOR_INSTANCE_COUNTER
         .fetch_add(1, Ordering::SeqCst)
-}
\ No newline at end of file
+}
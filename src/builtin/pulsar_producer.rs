@@ -1,12 +1,13 @@
 
-use std::{sync::{Arc, atomic::{AtomicI32, Ordering}}, collections::VecDeque};
+use std::{sync::atomic::{AtomicI32, Ordering}, collections::{HashMap, VecDeque}, fmt::Debug, sync::Arc};
 
 use async_trait::async_trait;
 
 use futures::StreamExt;
+use tokio::time::Instant;
 
 use pulsar::{
-    message::Payload, 
+    consumer::Message as PulsarMessage,
     Consumer
 };
 
@@ -18,82 +19,138 @@ pub use pulsar::{
     Error
 };
 
-use tokio::select;
+use tokio::{select, sync::mpsc};
 
 
-static GLOBAL_PULASR_PRODUCER_INSTANCE_COUNTER: Arc<AtomicI32> = Arc::new(Atomic::new(1));
+static GLOBAL_PULASR_PRODUCER_INSTANCE_COUNTER: AtomicI32 = AtomicI32::new(1);
 
 
 use crate::{topology:: {
     ProcessingType,
     PRODUCER_FILLBUFFER_TIMEOUT_BATCH,
     PRODUCER_FILLBUFFER_TIMEOUT_REALTIME
-}, dispatcher::Dispatcher};
+}, message::Message, producer::{Producer, Terminate}, FailReason};
+use crate::acknowledger::{Acknowledger, Acknowledgement};
+use crate::trace;
+
+/// pulsar consumer owned by consumer task of producer, so acker send acks to that task,
+/// task ack (or nack failed messages for redelivery) even when producer is waiting
+/// for room in next layer or terminated
+pub struct PulsarAcker {
+    sender: mpsc::UnboundedSender<(u64, bool)>
+}
+
+impl Acknowledger for PulsarAcker {
+    type Ref = u64;
+
+    fn ack(&self, successful: Vec<u64>, failed: Vec<(u64, FailReason)>) {
+        for id in successful {
+            let _ = self.sender.send((id, true));
+        }
+        for (id, _) in failed {
+            let _ = self.sender.send((id, false));
+        }
+    }
+}
+
 
-/// PulsarProducer when created 
-/// need to unique name for each instance 
+
+/// PulsarProducer when created
+/// need to unique name for each instance
 /// this module privade a Global Atomic Counter for PulsarProducer
-/// 
-struct PulsarProducer<Output> {
-    pulsar_consumer: Consumer<Output, _>,
-    tp: ProcessingType
+///
+/// consumer created by `init` and owned by a task, task receive messages
+/// (just while producer has room for them) and ack finished messages
+pub struct PulsarProducer<Output> {
+    pulsar: Pulsar<TokioExecutor>,
+    topics: Vec<String>,
+    consumer_name: String,
+    subscription: String,
+    subscription_type: SubType,
+    batch_size: u32,
+    tp: ProcessingType,
+
+    /// messages received by consumer task, set by `init`
+    messages: Option<mpsc::Receiver<Message<Output>>>
 }
-impl<Output> PulsarProducer<Output> 
-where
-    Output: DeserializeMessage
-{
-    pub fn new(pulsar: Pulsar<_>,
+impl<Output> PulsarProducer<Output> {
+    pub fn new(pulsar: Pulsar<TokioExecutor>,
                topics: &[&str],
                pulsar_instance_name: &str,
                subscription_type: SubType,
-               buffer_size: u32, 
+               batch_size: u32,
                tp: ProcessingType
-               ) -> Result<Self, Error> 
+               ) -> Self
     {
         let new_id = get_new_id();
 
-        // Create new Consumer
-        let mut consumer: Consumer<TestData, _> = pulsar
-            .consumer()
-            .with_topics(topics)
-            .with_batch_size(batch_size)
-            .with_consumer_name(format!("{}-{}", pulsar_instance_name, new_id))
-            .with_subscription_type(subscription_type)
-            .with_subscription(format!("{}-{}_subscription", pulsar_instance_name, new_id))
-            .build()
-            .await?;
-        
-        Ok(PulsarProducer {
-            pulsar_consumer: consumer,
-            tp
-        })
+        PulsarProducer {
+            pulsar,
+            topics: topics.iter().map(|t| t.to_string()).collect(),
+            consumer_name: format!("{}-{}", pulsar_instance_name, new_id),
+            subscription: format!("{}-{}_subscription", pulsar_instance_name, new_id),
+            subscription_type,
+            batch_size,
+            tp,
+            messages: None
+        }
     }
 }
 
 #[async_trait]
-impl<Output> Producer<Output> for PulsarProducer<Output>
+impl<Output, E> Producer<Output> for PulsarProducer<Output>
 where
-    Output: DeserializeMessage 
+    Output : DeserializeMessage<Output = Result<Output, E>> + Send + 'static,
+    E      : Debug + Send + 'static
 {
 
-    async fn init(&mut self) {}
-    
-    async fn terminate(&mut self) {}
+    async fn init(&mut self) {
+
+        // Create new Consumer
+        let consumer: Consumer<Output, TokioExecutor> = self.pulsar
+            .consumer()
+            .with_topics(self.topics.clone())
+            .with_batch_size(self.batch_size)
+            .with_consumer_name(self.consumer_name.clone())
+            .with_subscription_type(self.subscription_type)
+            .with_subscription(self.subscription.clone())
+            .build()
+            .await
+            .unwrap_or_else(|e| panic!("==> Pulsar consumer {} creation failed: {}", self.consumer_name, e));
+
+        let (sender, messages) = mpsc::channel(self.batch_size.max(1) as usize);
+
+        self.messages = Some(messages);
+        tokio::spawn(consume(consumer, sender));
+    }
+
+    // consumer task keep running until messages still in topology finished
+    async fn terminate(&mut self) {
+        self.messages = None;
+    }
+
+    // not acked, redelivered by pulsar
+    async fn drain(&mut self, _buffer: VecDeque<Message<Output>>) {}
+
+    // cancel safe, messages wait in channel of consumer task
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<Output>>, Terminate> {
 
-    async fn drain(&mut self, buffer: VecDeque<Output>) {}
+        let messages = self.messages
+            .as_mut()
+            .expect("==> PulsarProducer fill_buffer called before init");
 
-    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Output>> {
-        
         // create buffer
         let mut buffer = VecDeque::with_capacity(buffer_size);
 
 
-        let sleep = match self.tp {
+        let timeout = match self.tp {
             ProcessingType::RealTime => PRODUCER_FILLBUFFER_TIMEOUT_REALTIME,
             ProcessingType::Batch    => PRODUCER_FILLBUFFER_TIMEOUT_BATCH,
             ProcessingType::CustomTimeout(d) => d
         };
 
+        let sleep = tokio::time::sleep(timeout);
+
         tokio::pin!(sleep);
 
         loop {
@@ -101,62 +158,151 @@ where
             select! {
 
                 _ = &mut sleep => {
-                    
+
                     // if buffer was not empty
-                    if buffer.len() > 0 {
+                    if !buffer.is_empty() {
 
                         // return buffer
                         return Ok(buffer)
                     }
+
+                    // wait another timeout, an elapsed sleep is always ready
+                    sleep.as_mut().reset(Instant::now() + timeout);
                 }
 
-                res = self.pulsar_consumer.next() => {
+                res = messages.recv() => {
                     match res {
                         Some(msg) => {
-                            self.pulsar_consumer.ack(&msg).await;
-                            let data = match msg.deserialize() {
-                                Ok(data) => {
-                                    buffer.push_back(data);
-                                    if buffer.len() == buffer_size {
-                                        return Ok(buffer)
-                                    }
-                                }
-                                Err(e) => {
+                            buffer.push_back(msg);
 
-                                    // TODO: this change to tracing or anothr logger in next vsn
-                                    
-                                    eprintln!("could not deserialize message: {:?}", e);
-                                    
-                                }
-                            };
-                        
-                            if data.data.as_str() != "data" {
-                                log::error!("Unexpected payload: {}", &data.data);
-                                break;
+                            if buffer.len() == buffer_size {
+                                return Ok(buffer)
                             }
-                            
-                            
                         }
                         None => {
-                            // not exist any sender, shutdown
-                            if buffer.len() > 0 {
+                            // consumer closed, shutdown
+                            if !buffer.is_empty() {
 
                                 // return buffer
                                 return Ok(buffer)
-                            } 
+                            }
 
                             return Err(Terminate);
-
                         }
-
                     }
                 }
+            }
+        }
+    }
+}
+
+
+
+
+/// owns consumer, receive next message just when channel of producer has room,
+/// and ack (or nack) finished messages meanwhile
+///
+/// after producer terminated, keep acking until every pending message finished
+async fn consume<Output, E>(mut consumer: Consumer<Output, TokioExecutor>,
+                            messages: mpsc::Sender<Message<Output>>)
+where
+    Output : DeserializeMessage<Output = Result<Output, E>> + Send + 'static,
+    E      : Debug
+{
+    let (sender, mut acks) = mpsc::unbounded_channel();
+    let acker = Arc::new(PulsarAcker { sender });
+
+    // received messages not acked yet
+    let mut pending: HashMap<u64, PulsarMessage<Output>> = HashMap::new();
+    let mut next_id = 0;
+
+    let mut permit = None;
+
+    loop {
+        select! {
+            biased;
+
+            Some((id, ok)) = acks.recv() => {
+                settle(&mut consumer, &mut pending, id, ok).await;
+            }
+
+            // room for next message in producer
+            res = messages.reserve(), if permit.is_none() => {
+                match res {
+                    Ok(p) => permit = Some(p),
+
+                    // producer terminated
+                    Err(_) => break
+                }
+            }
+
+            // producer terminated while waiting for next message
+            _ = messages.closed(), if permit.is_some() => break,
+
+            res = consumer.next(), if permit.is_some() => {
+                match res {
+                    Some(Ok(msg)) => {
+                        match msg.deserialize() {
+                            Ok(data) => {
+
+                                // acked when message finished
+                                let id = next_id;
+                                next_id += 1;
+                                pending.insert(id, msg);
+
+                                let ack = Acknowledgement::new(acker.clone(), id);
+                                if let Some(p) = permit.take() {
+                                    p.send(Message::new(data).with_acknowledger(ack));
+                                }
+                            }
+                            Err(e) => {
+                                trace::warning!("could not deserialize message: {:?}", e);
+                            }
+                        }
+                    }
+                    Some(Err(e)) => trace::error!("Pulsar consumer error: {}", e),
 
+                    // consumer closed
+                    None => break
+                }
             }
+        }
+    }
+
+    drop(permit);
+    drop(messages);
+
+    // acks channel closed once every message in topology finished or dropped (drained)
+    drop(acker);
+
+    while let Some((id, ok)) = acks.recv().await {
+        settle(&mut consumer, &mut pending, id, ok).await;
 
+        if pending.is_empty() {
+            break
         }
-        
+    }
+}
+
+
+/// message finished last stage
+async fn settle<Output>(consumer: &mut Consumer<Output, TokioExecutor>,
+                        pending: &mut HashMap<u64, PulsarMessage<Output>>,
+                        id: u64,
+                        ok: bool)
+where
+    Output: DeserializeMessage
+{
+    if let Some(msg) = pending.remove(&id) {
+        let res = if ok {
+            consumer.ack(&msg).await
+        } else {
+            consumer.nack(&msg).await
+        };
 
+        if let Err(e) = res {
+            trace::error!("Pulsar ack error: {}", e);
+        }
     }
 }
 
@@ -167,4 +313,4 @@ where
 fn get_new_id() -> i32 {
     GLOBAL_PULASR_PRODUCER_INSTANCE_COUNTER
         .fetch_add(1, Ordering::SeqCst)
-}
\ No newline at end of file
+}
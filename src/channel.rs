@@ -0,0 +1,108 @@
+use std::{sync::{Arc, Mutex, MutexGuard}, future::poll_fn};
+
+use tokio::sync::mpsc;
+
+
+
+/// Receiver side of a stage input channel
+///
+/// receiver is shared with topology handle (by `QueueGauge`)
+/// for reading number of queued messages even when stage is stuck,
+/// lock is held only while polling, never across await
+pub struct StageReceiver<T> {
+    inner: Arc<Mutex<mpsc::Receiver<T>>>,
+
+    /// close channel when dropped
+    owner: bool
+}
+
+impl<T> StageReceiver<T>
+where
+    T: Send + 'static
+{
+
+    pub fn new(recv: mpsc::Receiver<T>) -> Self {
+        StageReceiver {
+            inner: Arc::new(Mutex::new(recv)),
+            owner: true
+        }
+    }
+
+
+    /// same channel, but dropping it not close channel,
+    /// used by supervisor for running instance and keep channel between restarts
+    pub fn share(&self) -> Self {
+        StageReceiver {
+            inner: self.inner.clone(),
+            owner: false
+        }
+    }
+
+
+    #[inline]
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| lock(&self.inner).poll_recv(cx)).await
+    }
+
+
+    /// gauge for reading queued messages of this channel
+    pub fn gauge(&self) -> QueueGauge {
+        QueueGauge(self.inner.clone())
+    }
+}
+
+
+impl<T> Drop for StageReceiver<T> {
+
+    /// stage exited (or panicked) close channel,
+    /// so upstream dispatchers detect it and remove from channels
+    fn drop(&mut self) {
+        if self.owner {
+            lock(&self.inner).close();
+        }
+    }
+}
+
+
+
+trait Queued: Send + Sync {
+    fn queued(&self) -> usize;
+    fn capacity(&self) -> usize;
+}
+
+impl<T> Queued for Mutex<mpsc::Receiver<T>>
+where
+    T: Send
+{
+    fn queued(&self) -> usize {
+        lock(self).len()
+    }
+
+    fn capacity(&self) -> usize {
+        lock(self).max_capacity()
+    }
+}
+
+
+/// number of messages still queued in a stage input channel
+#[derive(Clone)]
+pub struct QueueGauge(Arc<dyn Queued>);
+
+impl QueueGauge {
+    pub fn queued(&self) -> usize {
+        self.0.queued()
+    }
+
+    /// bound of channel
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+
+
+#[inline]
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    // a panic while holding lock not corrupt receiver
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
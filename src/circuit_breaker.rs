@@ -0,0 +1,450 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration
+};
+
+use async_trait::async_trait;
+use tokio::{sync::Notify, time::Instant};
+
+use crate::{
+    batcher::{BatchProcessor, BatchError, BatchResult, BatchTransformer},
+    processor::{Processor, ProcResult},
+    message::{BatchKey, Message},
+    metrics::FlushReason
+};
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+
+    /// calls pass through, consecutive failures are counted
+    Closed,
+
+    /// calls wait until `open_timeout` elapsed
+    Open,
+
+    /// one trial call pass through, others wait for its result
+    HalfOpen
+}
+
+
+/// state change of a circuit breaker
+#[derive(Debug, Clone)]
+pub struct BreakerEvent {
+    pub breaker: String,
+    pub from: BreakerState,
+    pub to: BreakerState,
+
+    /// consecutive failures when breaker opened
+    pub failures: usize
+}
+
+
+type Listener = Arc<dyn Fn(&BreakerEvent) + Send + Sync>;
+
+
+
+/// Circuit breaker shared by all instances of a layer (or several layers)
+///
+///   * `Closed` - after `failure_threshold` consecutive failures breaker opens
+///   * `Open` - instances wait inside `ready` (not call handler, not fail messages,
+///     not counted by `Timeouts::handler`), so stage channel
+///     get full and backpressure reach producers, after `open_timeout` half-open
+///   * `HalfOpen` - one call is trial, success close breaker and failure open it again
+///
+/// `ProcResult::Fail`, `BatchError` and panics count as failure
+///
+/// ```ignore
+/// let breaker = CircuitBreaker::new("mysql")
+///     .failure_threshold(5)
+///     .open_timeout(Duration::from_secs(10))
+///     .on_state_change(|e| println!("==> {} {:?} -> {:?}", e.breaker, e.from, e.to));
+///
+/// Topology::producer(|| Prod)
+///     .then(|| Layer1Process)
+///     .batcher(move || breaker.wrap(MysqlBatcher::new(pool.clone())))
+///     .start();
+/// ```
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    shared: Arc<Shared>
+}
+
+
+struct Shared {
+    name: String,
+    failure_threshold: usize,
+    open_timeout: Duration,
+    listener: Option<Listener>,
+
+    state: Mutex<Inner>,
+
+    /// wake waiting instances on state change
+    notify: Notify
+}
+
+
+struct Inner {
+    state: BreakerState,
+    failures: usize,
+    open_until: Instant
+}
+
+
+impl CircuitBreaker {
+
+    pub fn new(name: impl Into<String>) -> Self {
+        CircuitBreaker {
+            shared: Arc::new(Shared {
+                name: name.into(),
+                failure_threshold: 5,
+                open_timeout: Duration::from_secs(5),
+                listener: None,
+                state: Mutex::new(Inner {
+                    state: BreakerState::Closed,
+                    failures: 0,
+                    open_until: Instant::now()
+                }),
+                notify: Notify::new()
+            })
+        }
+    }
+
+    /// consecutive failures to open breaker (default 5)
+    pub fn failure_threshold(self, failure_threshold: usize) -> Self {
+        self.configure(|s| s.failure_threshold = failure_threshold.max(1))
+    }
+
+    /// time breaker stay open before trial call (default 5 seconds)
+    pub fn open_timeout(self, open_timeout: Duration) -> Self {
+        self.configure(|s| s.open_timeout = open_timeout)
+    }
+
+    /// called on every state change
+    pub fn on_state_change<F>(self, listener: F) -> Self
+    where
+        F: Fn(&BreakerEvent) + Send + Sync + 'static
+    {
+        self.configure(|s| s.listener = Some(Arc::new(listener)))
+    }
+
+    /// wrap a `Processor`, `BatchProcessor` or `BatchTransformer`,
+    /// usually called inside layer factory
+    pub fn wrap<P>(&self, inner: P) -> BreakerStage<P> {
+        BreakerStage {
+            breaker: self.clone(),
+            inner,
+            permit: None
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        self.shared.lock().state
+    }
+
+
+    fn configure<F>(mut self, f: F) -> Self
+    where
+        F: FnOnce(&mut Shared)
+    {
+        let shared = Arc::get_mut(&mut self.shared)
+            .expect("==> CircuitBreaker must be configured before cloned");
+        f(shared);
+        self
+    }
+
+
+    /// wait until a call allowed
+    async fn acquire(&self) -> Permit {
+        loop {
+            let notified = self.shared.notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let open_until = {
+                let mut inner = self.shared.lock();
+
+                match inner.state {
+                    BreakerState::Closed => {
+                        return Permit { breaker: self.clone(), trial: false, settled: false }
+                    }
+                    BreakerState::Open if Instant::now() >= inner.open_until => {
+                        let event = self.shared.transition(&mut inner, BreakerState::HalfOpen);
+                        drop(inner);
+
+                        self.shared.emit(event);
+                        return Permit { breaker: self.clone(), trial: true, settled: false }
+                    }
+                    BreakerState::Open => Some(inner.open_until),
+
+                    // wait for trial result
+                    BreakerState::HalfOpen => None
+                }
+            };
+
+            match open_until {
+                Some(deadline) => {
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(deadline) => (),
+                        _ = &mut notified => ()
+                    }
+                }
+                None => notified.await
+            }
+        }
+    }
+}
+
+impl fmt::Debug for CircuitBreaker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitBreaker")
+            .field("name", &self.shared.name)
+            .field("state", &self.state())
+            .finish()
+    }
+}
+
+
+impl Shared {
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// change state, returns event must be emitted after lock released
+    fn transition(&self, inner: &mut Inner, to: BreakerState) -> Option<BreakerEvent> {
+
+        let from = inner.state;
+        if from == to {
+            return None
+        }
+
+        if to == BreakerState::Open {
+            inner.open_until = Instant::now() + self.open_timeout;
+        }
+
+        let event = BreakerEvent {
+            breaker: self.name.clone(),
+            from,
+            to,
+            failures: inner.failures
+        };
+
+        inner.state = to;
+
+        if to == BreakerState::Closed {
+            inner.failures = 0;
+        }
+
+        Some(event)
+    }
+
+    fn emit(&self, event: Option<BreakerEvent>) {
+        if let Some(event) = event {
+
+            if let Some(ref listener) = self.listener {
+                listener(&event);
+            }
+
+            self.notify.notify_waiters();
+        }
+    }
+
+    fn record(&self, trial: bool, success: bool) {
+        let event = {
+            let mut inner = self.lock();
+
+            if success {
+                inner.failures = 0;
+                trial.then(|| self.transition(&mut inner, BreakerState::Closed)).flatten()
+            } else {
+                inner.failures += 1;
+
+                let trip = trial || 
+                    (inner.state == BreakerState::Closed && inner.failures >= self.failure_threshold);
+
+                trip.then(|| self.transition(&mut inner, BreakerState::Open)).flatten()
+            }
+        };
+
+        self.emit(event);
+    }
+}
+
+
+
+/// a call allowed by breaker,
+/// dropped without settled (handler panicked or aborted) count as failure
+struct Permit {
+    breaker: CircuitBreaker,
+    trial: bool,
+    settled: bool
+}
+
+impl Permit {
+    fn settle(mut self, success: bool) {
+        self.settled = true;
+        self.breaker.shared.record(self.trial, success);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.breaker.shared.record(self.trial, false);
+        }
+    }
+}
+
+
+
+
+/// `Processor`, `BatchProcessor` or `BatchTransformer` guarded by a `CircuitBreaker`,
+/// permit taken by `ready` and settled by next handler call
+pub struct BreakerStage<P> {
+    breaker: CircuitBreaker,
+    inner: P,
+    permit: Option<Permit>
+}
+
+impl<P> BreakerStage<P> {
+
+    async fn reserve(&mut self) {
+        if self.permit.is_none() {
+            self.permit = Some(self.breaker.acquire().await);
+        }
+    }
+
+    /// permit taken by `ready`, or wait for one if `ready` not called
+    async fn permit(&mut self) -> Permit {
+        match self.permit.take() {
+            Some(permit) => permit,
+            None => self.breaker.acquire().await
+        }
+    }
+}
+
+
+#[async_trait]
+impl<Input, Output, P> Processor<Input, Output> for BreakerStage<P>
+where
+    Input  : Send + Sync + 'static,
+    Output : Send + 'static,
+    P      : Processor<Input, Output> + Send
+{
+    async fn init(&mut self) {
+        self.inner.init().await
+    }
+
+    async fn ready(&mut self) {
+        self.inner.ready().await;
+        self.reserve().await
+    }
+
+    async fn handle_message(&mut self, msg: Message<Input>) -> ProcResult<Output> {
+
+        let permit = self.permit().await;
+
+        let res = self.inner.handle_message(msg).await;
+
+        permit.settle(!matches!(res, ProcResult::Fail(_)));
+        res
+    }
+
+    async fn handle_failed(&mut self, msg: &Message<Input>) {
+        self.inner.handle_failed(msg).await
+    }
+
+    async fn terminate(&mut self) {
+        self.inner.terminate().await
+    }
+}
+
+
+#[async_trait]
+impl<Input, P> BatchProcessor<Input> for BreakerStage<P>
+where
+    Input : Send + Sync + 'static,
+    P     : BatchProcessor<Input> + Send
+{
+    async fn init(&mut self) {
+        self.inner.init().await
+    }
+
+    async fn ready(&mut self) {
+        self.inner.ready().await;
+        self.reserve().await
+    }
+
+    async fn handle_batch(&mut self, key: Option<BatchKey>, batch: Vec<Message<Input>>) -> Result<(), BatchError<Input>> {
+
+        let permit = self.permit().await;
+
+        let res = self.inner.handle_batch(key, batch).await;
+
+        permit.settle(res.is_ok());
+        res
+    }
+
+    async fn on_flush(&mut self, reason: FlushReason) {
+        self.inner.on_flush(reason).await
+    }
+
+    async fn handle_failed(&mut self, batch: &[Message<Input>]) {
+        self.inner.handle_failed(batch).await
+    }
+
+    async fn drain(&mut self, batch: Vec<Message<Input>>) {
+        self.inner.drain(batch).await
+    }
+
+    async fn terminate(&mut self) {
+        self.inner.terminate().await
+    }
+}
+
+
+#[async_trait]
+impl<Input, Output, P> BatchTransformer<Input, Output> for BreakerStage<P>
+where
+    Input  : Send + Sync + 'static,
+    Output : Send + 'static,
+    P      : BatchTransformer<Input, Output> + Send
+{
+    async fn init(&mut self) {
+        self.inner.init().await
+    }
+
+    async fn ready(&mut self) {
+        self.inner.ready().await;
+        self.reserve().await
+    }
+
+    async fn handle_batch(&mut self, key: Option<BatchKey>, batch: Vec<Message<Input>>) -> Result<BatchResult<Output>, BatchError<Input>> {
+
+        let permit = self.permit().await;
+
+        let res = self.inner.handle_batch(key, batch).await;
+
+        permit.settle(res.is_ok());
+        res
+    }
+
+    async fn on_flush(&mut self, reason: FlushReason) {
+        self.inner.on_flush(reason).await
+    }
+
+    async fn handle_failed(&mut self, batch: &[Message<Input>]) {
+        self.inner.handle_failed(batch).await
+    }
+
+    async fn drain(&mut self, batch: Vec<Message<Input>>) {
+        self.inner.drain(batch).await
+    }
+
+    async fn terminate(&mut self) {
+        self.inner.terminate().await
+    }
+}
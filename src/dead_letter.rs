@@ -0,0 +1,66 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    acknowledger::{self, Acknowledgement},
+    failure::DeadLetter,
+    message::Message,
+    trace
+};
+
+
+
+/// failure sink of a layer, send failed messages to its dead letter stage
+/// (round robin between dead letter instances)
+pub(crate) struct DeadLetterSink<T> {
+    channels: Vec<mpsc::Sender<Message<DeadLetter<T>>>>,
+    next: AtomicUsize
+}
+
+impl<T> DeadLetterSink<T> {
+    pub fn new(channels: Vec<mpsc::Sender<Message<DeadLetter<T>>>>) -> Self {
+        DeadLetterSink {
+            channels,
+            next: AtomicUsize::new(0)
+        }
+    }
+}
+
+
+impl<T> DeadLetterSink<T> 
+where
+    T: Send + 'static
+{
+    /// each dead letter carry acknowledgement of its source message, 
+    /// settled as failed once dead letter stage finished (or failed) it
+    pub async fn send(&self, failed: Vec<(DeadLetter<T>, Option<Acknowledgement>)>) {
+
+        'letters: for (dl, ack) in failed {
+
+            let ack = acknowledger::failed(ack, dl.reason.clone());
+
+            let mut msg = Message::new(dl);
+            msg.acknowledger = ack;
+
+            // await on channel (backpressure), if instance terminated try next one
+            for _ in 0..self.channels.len() {
+
+                let index = self.next.fetch_add(1, Ordering::Relaxed) % self.channels.len();
+
+                match self.channels[index].send(msg).await {
+                    Ok(_) => continue 'letters,
+                    Err(e) => msg = e.0
+                }
+            }
+
+            // all dead letter instances terminated, logged and acked as failed
+            let ack = msg.acknowledger.take();
+            let dl = msg.data;
+            trace::error!("{} dead letter stage closed, message failed after {} attempts: {:?}", 
+                          dl.stage, dl.attempts, dl.reason);
+
+            acknowledger::ack(ack, None);
+        }
+    }
+}
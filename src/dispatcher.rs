@@ -4,6 +4,10 @@ use hashring::HashRing;
 use indexmap::IndexMap;
 use tokio::sync::mpsc::{self, error::TrySendError};
 
+use crate::message::Message;
+use crate::shutdown_manager::StageId;
+use crate::telemetry::{Telemetry, TelemetryEvent};
+
 
 
 pub type StageName = String;
@@ -22,11 +26,11 @@ pub type StageName = String;
 enum InternalDispatchError<T> {
 
     // T is msg produced but not exit any channel to consume it 
-    DestinationDown(T),
+    DestinationDown(Message<T>),
 
-    NotExist(T),
+    NotExist(Message<T>),
 
-    Full(T),
+    Full(Message<T>),
 
 }
 
@@ -34,8 +38,10 @@ enum InternalDispatchError<T> {
 pub enum DispatchError<T> {
 
     // for roundrobin
-    NotExist(T),
-    NotFound
+    NotExist(Message<T>),
+
+    // for partition
+    NotFound(Message<T>)
 }
 
 
@@ -47,6 +53,24 @@ pub enum RouterType {
 }
 
 
+/// routing of producer layer, cannot be `Partition` 
+/// (messages of producers not routed by `batch_key`)
+#[derive(Copy , Clone)]
+pub enum ProducerRouter {
+    RoundRobin,
+    Broadcast
+}
+
+impl From<ProducerRouter> for RouterType {
+    fn from(router: ProducerRouter) -> Self {
+        match router {
+            ProducerRouter::RoundRobin => RouterType::RoundRobin,
+            ProducerRouter::Broadcast  => RouterType::Broadcast
+        }
+    }
+}
+
+
 #[derive(Debug)]
 pub enum StatusResult {
     SendersRepetive
@@ -55,9 +79,12 @@ pub enum StatusResult {
 
 pub struct Dispatcher<T> {
     c: usize,
-    channels: IndexMap<StageName, mpsc::Sender<T>>,
+    channels: IndexMap<StageName, mpsc::Sender<Message<T>>>,
     pub router_type: RouterType,
-    hashring: Option<HashRing<usize>>
+    hashring: Option<HashRing<usize>>,
+
+    /// stage owns dispatcher, notified when a channel removed
+    observer: Option<(StageId, Telemetry)>
 }
 
 impl<T> Dispatcher<T> 
@@ -66,11 +93,11 @@ where
 {
     
 
-    pub fn new(channels: IndexMap<StageName, mpsc::Sender<T>>, 
+    pub fn new(channels: IndexMap<StageName, mpsc::Sender<Message<T>>>, 
                router_type: RouterType) -> Result<Self, StatusResult> {
 
         // Check channels to not be repetive
-        if let Err(_) = Dispatcher::list_check(&channels) {
+        if Dispatcher::list_check(&channels).is_err() {
             return Err(StatusResult::SendersRepetive);
         }
 
@@ -95,19 +122,30 @@ where
             c: 0, 
             channels,
             router_type,
-            hashring
+            hashring,
+            observer: None
         })
     }
 
 
+    /// emit `ChannelRemoved` as `owner` when a closed channel removed
+    pub(crate) fn with_telemetry(mut self, owner: StageId, telemetry: Telemetry) -> Self {
+        self.observer = Some((owner, telemetry));
+        self
+    }
+
+
     #[inline]
-    pub async fn dispatch(&mut self, msg: T, batch_key: Option<String>) -> Result<(), DispatchError<T>> {
+    pub async fn dispatch(&mut self, msg: Message<T>) -> Result<(), DispatchError<T>> {
         match self.router_type {
 
             RouterType::Partition => {
                 
                 // if batch_key not exist panic
-                self.partition(msg, batch_key.unwrap()).await
+                let batch_key = msg.batch_key.clone()
+                    .expect("==> Partition dispatcher need batch_key of message");
+
+                self.partition(msg, batch_key).await
             }
             RouterType::RoundRobin => {
                 self.roundrobin(msg).await
@@ -119,6 +157,13 @@ where
     }
 
 
+    /// not exist any destination (last layer of topology)
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.channels.is_empty()
+    }
+
+
     /* 
     
     /// return Err if key or chan exist
@@ -176,7 +221,7 @@ where
     
     
     #[inline]
-    async fn partition(&mut self, msg: T, batch_key: String) -> Result<(), DispatchError<T>> {
+    async fn partition(&mut self, msg: Message<T>, batch_key: String) -> Result<(), DispatchError<T>> {
 
         let hashring = unsafe { self.hashring.as_mut().unwrap_unchecked() };
 
@@ -188,36 +233,38 @@ where
                     Some((keychan, sender)) => {
 
                         // Send
-                        if let Err(_) = sender.send(msg).await{
+                        if let Err(e) = sender.send(msg).await {
 
-                            // if closed remove channel
                             let kc = keychan.to_owned();
-                            let _ = self.channels.remove(&kc);
 
                             // remove from hashring
                             let i = index.to_owned(); 
                             hashring.remove(&i);
-                            return Err(DispatchError::NotFound)
+
+                            // if closed remove channel
+                            self.remove_closed(&kc);
+
+                            Err(DispatchError::NotFound(e.0))
 
                         } else {
-                            return Ok(())
+                            Ok(())
                         }
                         
                     }
                     None => {
-                        return Err(DispatchError::NotFound)
+                        Err(DispatchError::NotFound(msg))
                     }
                 }                            
             }
-            None => Err(DispatchError::NotFound)
+            None => Err(DispatchError::NotFound(msg))
         }
     }
     
 
     #[inline]
-    async fn broadcast(&mut self, msg: T) -> Result<(), DispatchError<T>> {
+    async fn broadcast(&mut self, msg: Message<T>) -> Result<(), DispatchError<T>> {
 
-        if self.channels.len() == 0 {
+        if self.channels.is_empty() {
             return Err(DispatchError::NotExist(msg))
         }
 
@@ -230,7 +277,7 @@ where
                 let c = &self.channels;
                 let key = c.get_index(0).unwrap().0.clone();
 
-                self.channels.remove(&key);
+                self.remove_closed(&key);
 
             } 
             return Ok(())
@@ -255,7 +302,7 @@ where
 
         // remove closed list from channels  
         list.iter().for_each(|key| {
-            self.channels.remove(key);
+            self.remove_closed(key);
         });
 
         Ok(())
@@ -267,9 +314,9 @@ where
     /// roundrobin is safe if a destination terminate
     /// auto detect it and remove from channels 
     #[inline]
-    async fn roundrobin(&mut self, mut msg: T) -> Result<(), DispatchError<T>> {
+    async fn roundrobin(&mut self, mut msg: Message<T>) -> Result<(), DispatchError<T>> {
 
-        if self.channels.len() == 0 {
+        if self.channels.is_empty() {
             return Err(DispatchError::NotExist(msg))
         }
 
@@ -282,7 +329,7 @@ where
                 let c = &self.channels;
                 let key = c.get_index(0).unwrap().0.clone();
 
-                self.channels.remove(&key);
+                self.remove_closed(&key);
 
                 return Err(DispatchError::NotExist(e.0));
             } 
@@ -290,6 +337,9 @@ where
         }
 
 
+        // number of processors tried with full buffer
+        let mut full = 0;
+
         loop {
             match self.logic_roundrobin(msg).await {
                 Ok(_) => return Ok(()),
@@ -302,6 +352,31 @@ where
                 // processor buffer was full, try for next processor
                 Err(InternalDispatchError::Full(m)) => {
                     msg = m;
+                    full += 1;
+
+                    // all processors buffer was full, 
+                    // await on next processor (backpressure) instead of spinning
+                    if full >= self.channels.len() {
+                        
+                        let index = self.next_index();
+                        match self.channels[index].send(msg).await {
+                            Ok(_) => return Ok(()),
+                            Err(e) => {
+                                msg = e.0;
+
+                                // channel closed, remove from channels
+                                let (key, _) = self.channels.get_index(index).unwrap();
+                                let key = key.clone();
+                                self.remove_closed(&key);
+
+                                if self.channels.is_empty() {
+                                    return Err(DispatchError::NotExist(msg));
+                                }
+
+                                full = 0;
+                            }
+                        }
+                    }
                 }
 
                 // not exist any channel
@@ -316,7 +391,7 @@ where
     }
 
     #[inline]
-    async fn logic_roundrobin(&mut self, mut msg: T) -> Result<(), InternalDispatchError<T>> {
+    async fn logic_roundrobin(&mut self, mut msg: Message<T>) -> Result<(), InternalDispatchError<T>> {
         
         // get next index
         let index = self.next_index();
@@ -328,7 +403,7 @@ where
             Ok(_ok) => {
 
                 // return Ok() 
-                return Ok(())
+                Ok(())
             }
             
             // channel closed
@@ -340,22 +415,22 @@ where
                 // this sender remove from channels
                 let (key, _) = self.channels.get_index(index).unwrap();
                 let key = key.clone();
-                self.channels.remove(&key);
+                self.remove_closed(&key);
                 
 
                 // if not exist destination return Err
-                if self.channels.len() == 0 {
+                if self.channels.is_empty() {
 
                     return Err(InternalDispatchError::NotExist(msg))
                 }
 
-                return Err(InternalDispatchError::DestinationDown(msg)) 
+                Err(InternalDispatchError::DestinationDown(msg)) 
 
             }
        
             Err(TrySendError::Full(b)) => {
                 msg = b;
-                return Err(InternalDispatchError::Full(msg))
+                Err(InternalDispatchError::Full(msg))
             }
        
         }
@@ -365,6 +440,19 @@ where
 
 
 
+    /// remove channel of a terminated stage
+    fn remove_closed(&mut self, key: &StageName) {
+        if self.channels.remove(key).is_none() {
+            return
+        }
+
+        if let Some((owner, telemetry)) = &self.observer {
+            let remaining = self.channels.len();
+            telemetry.emit(|| TelemetryEvent::ChannelRemoved { owner: *owner, removed: key.clone(), remaining });
+        }
+    }
+
+
     fn next_index(&mut self) -> usize {
         let mut index = self.c;
 
@@ -375,12 +463,12 @@ where
             index = 0;
         }
 
-        return index
+        index
     }
 
     
     /// Check channels to not be repetive
-    fn list_check(channels: &IndexMap<StageName, mpsc::Sender<T>>) -> Result<(), ()> {
+    fn list_check(channels: &IndexMap<StageName, mpsc::Sender<Message<T>>>) -> Result<(), ()> {
         for (oindex, (outer_key, outer_chan)) in channels.iter().enumerate() {
             for (iindex, (inner_key, inner_chan)) in channels.iter().enumerate() {
             
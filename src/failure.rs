@@ -0,0 +1,185 @@
+use std::{any::Any, fmt, future::Future, panic::AssertUnwindSafe, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use futures::FutureExt;
+
+use crate::{
+    acknowledger::{self, Acknowledgement},
+    dead_letter::DeadLetterSink,
+    message::Message,
+    shutdown_manager::StageId,
+    trace
+};
+
+
+
+/// Why a message (or batch) failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailReason {
+
+    /// handler panicked, contains panic message if was string
+    Panicked(Option<String>),
+
+    /// handler returned `ProcResult::Fail` or `BatchError::Fail`
+    Error(String),
+
+    /// handler not finished within `Timeouts::handler`
+    TimedOut(Duration),
+
+    /// next layer not exist any instance to receive message
+    Undeliverable,
+
+    /// message heavier than `max_batch_weight` of batcher (`Oversized::Fail`)
+    Oversized { weight: usize, max: usize }
+}
+
+
+
+/// A message that exhausted processing
+#[derive(Clone)]
+pub struct DeadLetter<T> {
+    /// failed message, with `MessageStatus::Failed` status
+    pub message: Message<T>,
+
+    pub reason: FailReason,
+
+    /// stage that message failed on
+    pub stage: StageId,
+
+    /// number of times message handled
+    pub attempts: usize
+}
+
+impl<T> DeadLetter<T> {
+    pub(crate) fn new(message: Message<T>, reason: FailReason, stage: StageId, attempts: usize) -> Self {
+        DeadLetter {
+            message: message.failed(reason.clone()),
+            reason,
+            stage,
+            attempts
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for DeadLetter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeadLetter")
+            .field("message", &self.message)
+            .field("reason", &self.reason)
+            .field("stage", &self.stage)
+            .field("attempts", &self.attempts)
+            .finish()
+    }
+}
+
+
+
+/// Destination of failed messages of a layer
+///
+///   * called after `handle_failed` hook of processor (or batcher),
+///     failed messages acked to their source once it returned
+///     (dead letter stage of layer ack them once dead letters finished)
+///   * also receive messages upstream layer could not deliver to this layer
+///   * if layer not have failure sink, failed messages logged to stderr
+#[async_trait]
+pub trait FailureSink<T>: Send + Sync 
+where
+    T: Send + 'static
+{
+    async fn sink(&self, failed: Vec<DeadLetter<T>>);
+}
+
+
+/// failure sink of a layer, settles acknowledgements of failed messages
+pub(crate) enum SharedSink<T> {
+
+    /// user sink (or `LogSink`), source acked as failed once `sink` returned
+    Sink(Arc<dyn FailureSink<T>>),
+
+    /// dead letter stage, acknowledgement carried by dead letter so source acked
+    /// as failed once dead letter stage finished it (never acked if it lost)
+    DeadLetter(Arc<DeadLetterSink<T>>)
+}
+
+impl<T> SharedSink<T>
+where
+    T: Send + 'static
+{
+    pub fn new<S>(sink: S) -> Self
+    where
+        S: FailureSink<T> + 'static
+    {
+        SharedSink::Sink(Arc::new(sink))
+    }
+
+    /// failed messages with acknowledgements of their source
+    pub async fn sink(&self, failed: Vec<(DeadLetter<T>, Option<Acknowledgement>)>) {
+        match self {
+            SharedSink::Sink(sink) => {
+                let (letters, acks): (Vec<_>, Vec<_>) = failed
+                    .into_iter()
+                    .map(|(dl, ack)| {
+                        let reason = dl.reason.clone();
+                        (dl, (ack, reason))
+                    })
+                    .unzip();
+
+                sink.sink(letters).await;
+
+                for (ack, reason) in acks {
+                    acknowledger::ack(ack, Some(reason));
+                }
+            }
+            SharedSink::DeadLetter(sink) => sink.send(failed).await
+        }
+    }
+}
+
+impl<T> Clone for SharedSink<T> {
+    fn clone(&self) -> Self {
+        match self {
+            SharedSink::Sink(sink) => SharedSink::Sink(sink.clone()),
+            SharedSink::DeadLetter(sink) => SharedSink::DeadLetter(sink.clone())
+        }
+    }
+}
+
+
+
+/// used when layer not have failure sink, 
+/// failed messages not silently dropped 
+pub(crate) struct LogSink;
+
+#[async_trait]
+impl<T> FailureSink<T> for LogSink 
+where
+    T: Send + 'static
+{
+    async fn sink(&self, failed: Vec<DeadLetter<T>>) {
+        for dl in failed {
+            trace::error!("{} message failed after {} attempts: {:?}", dl.stage, dl.attempts, dl.reason);
+        }
+    }
+}
+
+
+
+/// run handler future, a panic inside it returned as `Err(FailReason::Panicked)`
+/// instead of unwinding whole stage task
+#[inline]
+pub(crate) async fn catch_panic<F>(fut: F) -> Result<F::Output, FailReason>
+where
+    F: Future
+{
+    AssertUnwindSafe(fut)
+        .catch_unwind()
+        .await
+        .map_err(|payload| FailReason::Panicked(panic_message(&*payload)))
+}
+
+
+/// panic message if payload was `&str` or `String`
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> Option<String> {
+    payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+}
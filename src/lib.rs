@@ -3,21 +3,89 @@
 /// trait Producer & starter
 mod producer;
 
-/// trait BatchProcessor & starter
+/// trait BatchProcessor, BatchTransformer & starter
 mod batcher;
 
 /// trait Processor & starter 
 mod processor;
 
+/// pending batches of a batcher instance, grouped by key
+mod batches;
 
-/// shutdown manager
+/// adaptive batch size of batcher layers
+mod adaptive;
+
+
+/// shutdown manager & topology handle
 mod shutdown_manager;
 
 
-/// syncing components
+/// creating and syncing components
 mod topology;
 
 
+/// stage input channel
+mod channel;
+
+
+/// OS signals for topology shutdown
+pub mod signals;
+
+
+/// restart crashed processor & batcher instances
+mod supervisor;
+
+
+/// failed messages
+mod failure;
+
+
+/// dead letter stage of a layer
+mod dead_letter;
+
+
+/// retry failed messages with backoff
+mod retry;
+
+
+/// circuit breaker around processors & batchers
+mod circuit_breaker;
+
+
+/// timeouts & stalled instances
+mod watchdog;
+
+
+/// per stage instance metrics
+mod metrics;
+
+
+/// telemetry events of stage instances
+mod telemetry;
+
+
+/// `tracing` spans & events (stderr if feature not enabled)
+mod trace;
+
+
+/// test source & layer mocks
+#[cfg(feature = "testing")]
+pub mod testing;
+
+
+/// Prometheus `/metrics` endpoint
+#[cfg(feature = "prometheus")]
+mod prometheus;
+
+
+/// message envelope passed between stages
+mod message;
+
+
+/// acknowledge messages to their source
+mod acknowledger;
+
+
 /// dispatcher with two mode (RoundRobin & BroadCast)
 mod dispatcher; 
 
@@ -31,13 +99,58 @@ pub use async_trait::async_trait;
 pub mod builtin;
 
 
-pub use batcher::{BatchProcessor, BatcherTerminate};
+pub use batcher::{BatchProcessor, BatchTransformer, BatchResult, BatcherTerminate, BatchError, BatchWeigher, Linger, Oversized};
+
+pub use adaptive::{AdaptiveBatch, AdaptiveTarget};
 
 pub use processor::{Processor, ProcResult};
 
-pub use dispatcher::RouterType;
+pub use dispatcher::{RouterType, ProducerRouter, StageName};
+
+pub use supervisor::{Supervision, RestartStrategy};
+
+pub use failure::{FailReason, FailureSink, DeadLetter};
+
+pub use retry::{RetryPolicy, Classifier};
+
+pub use circuit_breaker::{CircuitBreaker, BreakerStage, BreakerState, BreakerEvent};
+
+pub use watchdog::{Timeouts, Stall};
+
+pub use metrics::{MetricsSnapshot, StageSnapshot, BatchSnapshot, HistogramSnapshot, FlushReason};
+
+pub use telemetry::{TelemetryHandler, TelemetryEvent, ProducerExit};
+
+pub use message::{Message, MessageStatus, Metadata, BatchKey};
+
+pub use acknowledger::{Acknowledger, Acknowledgement};
+
+pub use shutdown_manager::{
+    TopologyHandle,
+    ShutdownReport,
+    StageReport,
+    StageStatus,
+    StageId,
+    StageKind
+};
+
 
+pub use producer::{Producer, Terminate};
 
-pub use producer::Producer;
+pub use topology::{
+    Topology,
+    ProducerBuilder,
+    TopologyBuilder,
+    BatchLayerBuilder,
+    BatcherBuilder,
+    ProcessingType,
+    PRODUCER_FILLBUFFER_TIMEOUT_REALTIME,
+    PRODUCER_FILLBUFFER_TIMEOUT_BATCH,
+    BATCH_SIZE,
+    BATCH_TIMEOUT,
+    CONCURRENCY,
+    BUFFER_SIZE,
+    BUFFER_POOL_SIZE
+};
 
 
@@ -0,0 +1,159 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{acknowledger::Acknowledgement, failure::FailReason, trace::MessageSpan};
+
+
+
+pub type BatchKey = String;
+
+
+/// headers of a message, e.g. source offset, ingest timestamp, trace id
+pub type Metadata = HashMap<String, String>;
+
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum MessageStatus {
+    #[default]
+    Ok,
+
+    Failed(FailReason)
+}
+
+
+
+/// Envelope of data passed between stages
+///
+///   * `data` is payload, other fields carried across stages
+///     by `Message::map` / `Message::replace`
+///   * `batch_key` required when next dispatcher is `Partition` mode
+///   * `acknowledger` set by producer, taken by framework while message handled
+///     and acked when message finished
+///   * span of last stage handled message carried to next layer (feature `tracing`)
+#[derive(Clone)]
+pub struct Message<T> {
+    pub data: T,
+    pub metadata: Metadata,
+    pub batch_key: Option<BatchKey>,
+    pub status: MessageStatus,
+    pub acknowledger: Option<Acknowledgement>,
+    pub(crate) span: MessageSpan
+}
+
+impl<T> Message<T> {
+
+    pub fn new(data: T) -> Self {
+        Message {
+            data,
+            metadata: Metadata::new(),
+            batch_key: None,
+            status: MessageStatus::Ok,
+            acknowledger: None,
+            span: MessageSpan::default()
+        }
+    }
+
+
+    pub fn with_batch_key(mut self, batch_key: impl Into<BatchKey>) -> Self {
+        self.batch_key = Some(batch_key.into());
+        self
+    }
+
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn with_acknowledger(mut self, acknowledger: Acknowledgement) -> Self {
+        self.acknowledger = Some(acknowledger);
+        self
+    }
+
+    /// continue a trace started outside topology (e.g. extracted from record headers),
+    /// span of message inside first layer become child of it
+    #[cfg(feature = "tracing")]
+    pub fn with_span(mut self, span: tracing::Span) -> Self {
+        self.span = MessageSpan { span: Some(span) };
+        self
+    }
+
+
+    /// transform payload and keep metadata, batch_key, status, acknowledger & span
+    pub fn map<U, F>(self, f: F) -> Message<U>
+    where
+        F: FnOnce(T) -> U
+    {
+        Message {
+            data: f(self.data),
+            metadata: self.metadata,
+            batch_key: self.batch_key,
+            status: self.status,
+            acknowledger: self.acknowledger,
+            span: self.span
+        }
+    }
+
+    /// replace payload and keep metadata, batch_key, status, acknowledger & span
+    pub fn replace<U>(self, data: U) -> Message<U> {
+        self.map(|_| data)
+    }
+
+    /// a message per payload, each one keep metadata, batch_key, status, 
+    /// acknowledger & span (for `ProcResult::DispatchMany`)
+    ///
+    /// ```ignore
+    /// let events = serde_json::from_str::<Vec<Event>>(&msg.data.payload)?;
+    /// ProcResult::DispatchMany(msg.split(events))
+    /// ```
+    pub fn split<U, I>(self, data: I) -> Vec<Message<U>>
+    where
+        I: IntoIterator<Item = U>
+    {
+        let data = data.into_iter().collect::<Vec<_>>();
+        let last = data.len().saturating_sub(1);
+        let mut acknowledger = self.acknowledger;
+
+        data.into_iter()
+            .enumerate()
+            .map(|(i, d)| Message {
+                data: d,
+                metadata: self.metadata.clone(),
+                batch_key: self.batch_key.clone(),
+                status: self.status.clone(),
+
+                // copies acked after all of them finished
+                acknowledger: if i == last { acknowledger.take() } else { acknowledger.clone() },
+                span: self.span.clone()
+            })
+            .collect()
+    }
+
+
+    pub fn is_failed(&self) -> bool {
+        matches!(self.status, MessageStatus::Failed(_))
+    }
+
+    pub(crate) fn failed(mut self, reason: FailReason) -> Self {
+        self.status = MessageStatus::Failed(reason);
+        self
+    }
+}
+
+
+impl<T> From<T> for Message<T> {
+    fn from(data: T) -> Self {
+        Message::new(data)
+    }
+}
+
+
+impl<T: fmt::Debug> fmt::Debug for Message<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Message")
+            .field("data", &self.data)
+            .field("metadata", &self.metadata)
+            .field("batch_key", &self.batch_key)
+            .field("status", &self.status)
+            .finish()
+    }
+}
@@ -0,0 +1,372 @@
+use std::{
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+    time::Duration
+};
+
+use crate::{channel::QueueGauge, shutdown_manager::{StageId, StageKind}};
+
+
+
+/// handler latency buckets (seconds)
+const LATENCY_BOUNDS: &[f64] = &[
+    0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0
+];
+
+/// batch size buckets (messages)
+const BATCH_SIZE_BOUNDS: &[f64] = &[
+    1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 5000.0
+];
+
+/// fill_buffer ratio buckets (filled / buffer_size)
+const FILL_RATIO_BOUNDS: &[f64] = &[
+    0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9, 1.0
+];
+
+
+
+/// what triggered a batcher flush
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushReason {
+
+    /// batch reached `batch_size`
+    Size,
+
+    /// `batch_timeout` elapsed (since first or last message, by `Linger`)
+    Timeout,
+
+    /// batch reached `max_batch_weight` (or next message not fit in it)
+    Weight,
+
+    /// input channel closed (shutdown)
+    Shutdown
+}
+
+
+
+/// Fixed buckets histogram
+struct Histogram {
+    bounds: &'static [f64],
+
+    /// one count per bound, last one for values greater than all bounds
+    counts: Box<[AtomicU64]>,
+
+    /// f64 bits
+    sum: AtomicU64
+}
+
+impl Histogram {
+
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0f64.to_bits())
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        let index = self.bounds
+            .iter()
+            .position(|b| value <= *b)
+            .unwrap_or(self.bounds.len());
+
+        self.counts[index].fetch_add(1, Ordering::Relaxed);
+
+        let _ = self.sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f64::from_bits(bits) + value).to_bits())
+        });
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        let counts = self.counts
+            .iter()
+            .map(|c| c.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+
+        HistogramSnapshot {
+            bounds: self.bounds.to_vec(),
+            count: counts.iter().sum(),
+            counts,
+            sum: f64::from_bits(self.sum.load(Ordering::Relaxed))
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HistogramSnapshot {
+    /// upper bound of buckets
+    pub bounds: Vec<f64>,
+
+    /// observations per bucket (not cumulative),
+    /// last one for values greater than all bounds
+    pub counts: Vec<u64>,
+
+    pub sum: f64,
+    pub count: u64
+}
+
+impl HistogramSnapshot {
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+
+    /// upper bound of bucket that contains `q` quantile (0.0 - 1.0),
+    /// `f64::INFINITY` if it is greater than all bounds
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None
+        }
+
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(self.bounds.get(index).copied().unwrap_or(f64::INFINITY))
+            }
+        }
+
+        Some(f64::INFINITY)
+    }
+}
+
+
+
+
+/// metrics of a stage instance,
+/// shared between instance (and its restarts) and topology handle
+#[derive(Clone)]
+pub(crate) struct StageMetrics {
+    inner: Arc<Inner>
+}
+
+struct Inner {
+    messages_in: AtomicU64,
+    messages_out: AtomicU64,
+    failed: AtomicU64,
+    latency: Histogram,
+    retrying: AtomicU64,
+
+    // batcher
+    pending: AtomicU64,
+    target_size: AtomicU64,
+    batch_size: Histogram,
+    flush_size: AtomicU64,
+    flush_timeout: AtomicU64,
+    flush_shutdown: AtomicU64,
+    flush_weight: AtomicU64,
+
+    // producer
+    fill_ratio: Histogram
+}
+
+impl StageMetrics {
+
+    pub fn new() -> Self {
+        StageMetrics {
+            inner: Arc::new(Inner {
+                messages_in: AtomicU64::new(0),
+                messages_out: AtomicU64::new(0),
+                failed: AtomicU64::new(0),
+                latency: Histogram::new(LATENCY_BOUNDS),
+                retrying: AtomicU64::new(0),
+                pending: AtomicU64::new(0),
+                target_size: AtomicU64::new(0),
+                batch_size: Histogram::new(BATCH_SIZE_BOUNDS),
+                flush_size: AtomicU64::new(0),
+                flush_timeout: AtomicU64::new(0),
+                flush_shutdown: AtomicU64::new(0),
+                flush_weight: AtomicU64::new(0),
+                fill_ratio: Histogram::new(FILL_RATIO_BOUNDS)
+            })
+        }
+    }
+
+    /// messages received (producer: messages filled)
+    pub fn received(&self, n: usize) {
+        self.inner.messages_in.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// messages dispatched to next layer, or finished on last layer
+    pub fn sent(&self, n: usize) {
+        self.inner.messages_out.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// messages failed after all attempts (or undeliverable)
+    pub fn failed(&self, n: usize) {
+        self.inner.failed.fetch_add(n as u64, Ordering::Relaxed);
+    }
+
+    /// handler call duration
+    pub fn latency(&self, elapsed: Duration) {
+        self.inner.latency.observe(elapsed.as_secs_f64());
+    }
+
+    /// messages (batches for batcher) waiting for next attempt
+    pub fn retrying(&self, n: usize) {
+        self.inner.retrying.store(n as u64, Ordering::Relaxed);
+    }
+
+    /// messages inside current batch
+    pub fn pending(&self, n: usize) {
+        self.inner.pending.store(n as u64, Ordering::Relaxed);
+    }
+
+    /// max messages in a batch, changed by adaptive batch size
+    pub fn target_size(&self, n: usize) {
+        self.inner.target_size.store(n as u64, Ordering::Relaxed);
+    }
+
+    pub fn flush(&self, reason: FlushReason, size: usize) {
+        self.inner.batch_size.observe(size as f64);
+
+        let counter = match reason {
+            FlushReason::Size => &self.inner.flush_size,
+            FlushReason::Timeout => &self.inner.flush_timeout,
+            FlushReason::Shutdown => &self.inner.flush_shutdown,
+            FlushReason::Weight => &self.inner.flush_weight
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn fill(&self, filled: usize, buffer_size: usize) {
+        self.inner.fill_ratio.observe(filled as f64 / buffer_size.max(1) as f64);
+    }
+
+
+    pub fn snapshot(&self, id: StageId, queue: Option<(usize, usize)>) -> StageSnapshot {
+        let inner = &self.inner;
+
+        let sizes = inner.batch_size.snapshot();
+
+        // batcher, or batcher as dead letter stage
+        let batch = (id.kind == StageKind::Batcher || sizes.count > 0).then(|| BatchSnapshot {
+            pending: inner.pending.load(Ordering::Relaxed) as usize,
+            target_size: inner.target_size.load(Ordering::Relaxed) as usize,
+            sizes,
+            flushed_by_size: inner.flush_size.load(Ordering::Relaxed),
+            flushed_by_timeout: inner.flush_timeout.load(Ordering::Relaxed),
+            flushed_by_shutdown: inner.flush_shutdown.load(Ordering::Relaxed),
+            flushed_by_weight: inner.flush_weight.load(Ordering::Relaxed)
+        });
+
+        let fill_ratio = (id.kind == StageKind::Producer).then(|| inner.fill_ratio.snapshot());
+
+        StageSnapshot {
+            id,
+            messages_in: inner.messages_in.load(Ordering::Relaxed),
+            messages_out: inner.messages_out.load(Ordering::Relaxed),
+            failed: inner.failed.load(Ordering::Relaxed),
+            latency: inner.latency.snapshot(),
+            retrying: inner.retrying.load(Ordering::Relaxed) as usize,
+            queue_depth: queue.map(|(depth, _)| depth),
+            queue_capacity: queue.map(|(_, capacity)| capacity),
+            batch,
+            fill_ratio
+        }
+    }
+}
+
+
+
+
+/// metrics of all stage instances, shared by topology handle and exporters
+#[derive(Clone)]
+pub(crate) struct MetricsSource {
+    stages: Arc<Vec<(StageId, StageMetrics, Option<QueueGauge>)>>
+}
+
+impl MetricsSource {
+
+    pub fn new(stages: Vec<(StageId, StageMetrics, Option<QueueGauge>)>) -> Self {
+        MetricsSource {
+            stages: Arc::new(stages)
+        }
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            stages: self.stages
+                .iter()
+                .map(|(id, metrics, queue)| {
+                    let queue = queue.as_ref().map(|q| (q.queued(), q.capacity()));
+                    metrics.snapshot(*id, queue)
+                })
+                .collect()
+        }
+    }
+}
+
+
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchSnapshot {
+    /// messages inside current batch, not flushed yet
+    pub pending: usize,
+
+    /// max messages in a batch, current size of adaptive batch size (`AdaptiveBatch`)
+    pub target_size: usize,
+
+    pub sizes: HistogramSnapshot,
+    pub flushed_by_size: u64,
+    pub flushed_by_timeout: u64,
+    pub flushed_by_shutdown: u64,
+    pub flushed_by_weight: u64
+}
+
+
+/// metrics of a stage instance at snapshot time
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageSnapshot {
+    pub id: StageId,
+
+    /// messages received (producer: messages returned by `fill_buffer`)
+    pub messages_in: u64,
+
+    /// messages dispatched to next layer, or finished on last layer
+    pub messages_out: u64,
+
+    /// messages failed after all attempts, or could not delivered to next layer
+    pub failed: u64,
+
+    /// `handle_message` / `handle_batch` duration in seconds,
+    /// every attempt observed (empty for producers, `fill_buffer` waits for input)
+    pub latency: HistogramSnapshot,
+
+    /// messages (batches for batcher) waiting for next attempt
+    pub retrying: usize,
+
+    /// messages queued in input channel, `None` for producers
+    pub queue_depth: Option<usize>,
+
+    /// bound of input channel (`buffer_size`), `None` for producers
+    pub queue_capacity: Option<usize>,
+
+    /// just for batchers
+    pub batch: Option<BatchSnapshot>,
+
+    /// filled / buffer_size of each `fill_buffer`, just for producers
+    pub fill_ratio: Option<HistogramSnapshot>
+}
+
+
+/// metrics of all stage instances, ordered by layer (producers first)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    pub stages: Vec<StageSnapshot>
+}
+
+impl MetricsSnapshot {
+
+    /// stages of a layer
+    pub fn layer(&self, layer: usize) -> impl Iterator<Item = &StageSnapshot> {
+        self.stages
+            .iter()
+            .filter(move |s| s.id.layer == layer)
+    }
+}
@@ -1,34 +1,68 @@
 
-use crate::dispatcher::Dispatcher;
+use crate::dispatcher::{Dispatcher, DispatchError, RouterType};
 use async_trait::async_trait;
-use tokio::sync::mpsc;
+use tokio::{task::JoinHandle, time::Instant};
+
+use crate::channel::StageReceiver;
+use crate::acknowledger;
+use crate::failure::{catch_panic, DeadLetter, FailReason, SharedSink};
+use crate::message::Message;
+use crate::retry::{RetryPolicy, RetryQueue};
+use crate::shutdown_manager::StageId;
+use crate::telemetry::TelemetryEvent;
+use crate::trace::{self, MessageSpan};
+use crate::watchdog::Watch;
 
 
 
 #[async_trait]
-pub trait Processor<Input, Output> {
+pub trait Processor<Input, Output>
+where
+    Input: Send + 'static
+{
     
     async fn init(&mut self);
+
+    /// called before every attempt of `handle_message`, outside of `Timeouts::handler`,
+    /// instance wait here until it can handle next message (e.g. `CircuitBreaker` open)
+    async fn ready(&mut self) {}
     
     /// if return ProcResult::Dispatch
-    ///    and Dispatcher is Partition must set batch_key
-    async fn handle_message(&mut self, msg: Input) -> ProcResult<Output>;
+    ///    and Dispatcher is Partition must set `batch_key` of message
+    async fn handle_message(&mut self, msg: Message<Input>) -> ProcResult<Output>;
+
+    /// called when handle_message failed (returned `ProcResult::Fail` or panicked) on `msg`,
+    /// reason is in `msg.status`, instance keep its state and continue with next message,
+    /// then message passed to failure sink of layer
+    ///
+    /// if layer has `RetryPolicy`, called just once all attempts failed
+    async fn handle_failed(&mut self, _msg: &Message<Input>) {}
 
     async fn terminate(&mut self);
 }
 
 
 
-type BatchKey = String;
-
 pub enum ProcResult<Output> {
     
     /// skip this Batch
     Continue,
 
-    /// dispatch by dispatcher
-    /// if dispatcher mode is not partition, pass None 
-    Dispatch(Output, Option<BatchKey>)
+    /// dispatch by dispatcher,
+    /// use `msg.map(..)` to keep metadata of input message
+    /// (acknowledger carried by framework)
+    Dispatch(Message<Output>),
+
+    /// dispatch zero or more outputs in order (e.g. split a record into events),
+    /// use `msg.split(..)` to keep metadata of input message,
+    /// input acked once all outputs finished (acked as failed if any output failed)
+    ///
+    /// each output waits for room in channel of next layer (backpressure)
+    /// before next one dispatched, so outputs not buffered inside instance
+    DispatchMany(Vec<Message<Output>>),
+
+    /// message failed, passed to `handle_failed` and failure sink of layer
+    Fail(String)
 }
 
 
@@ -42,9 +76,27 @@ where
     Output: Send + 'static,
     Proc: Processor<Input, Output> + Send + 'static
 {
-    recv: mpsc::Receiver<Input>,
+    id: StageId,
+    recv: StageReceiver<Message<Input>>,
     dispatcher: Dispatcher<Output>,
-    proc: Proc
+    proc: Proc,
+    failure_sink: SharedSink<Input>,
+
+    /// failure sink of next layer, for messages could not delivered to it
+    undeliverable: SharedSink<Output>,
+
+    /// last layer of topology, message finished here
+    terminal: bool,
+
+    retry: Option<RetryPolicy>,
+
+    /// messages waiting for next attempt
+    retries: RetryQueue<Message<Input>>,
+
+    /// wait on retry inside instance, to keep order of messages
+    ordered: bool,
+
+    watch: Watch
 }
 impl<Input, Output, Proc> Context<Input, Output, Proc> 
 where
@@ -53,35 +105,238 @@ where
     Proc   : Processor<Input, Output> + Send + 'static
 {
     
-    pub fn new(recv: mpsc::Receiver<Input>,
+    pub fn new(id: StageId,
+               recv: StageReceiver<Message<Input>>,
                dispatcher: Dispatcher<Output>,
-               proc: Proc) -> Self 
+               proc: Proc,
+               failure_sink: SharedSink<Input>,
+               undeliverable: SharedSink<Output>) -> Self 
     {
         Context { 
+            id,
             recv, 
+            terminal: dispatcher.is_empty(),
             dispatcher, 
             proc,  
+            failure_sink,
+            undeliverable,
+            retry: None,
+            retries: RetryQueue::new(),
+            ordered: false,
+            watch: Watch::default()
         }
     }
 
 
+    /// retry failed messages by policy, 
+    /// `ordered` if input of layer partitioned by upstream dispatcher
+    pub fn with_retry(mut self, retry: Option<RetryPolicy>, ordered: bool) -> Self {
+        self.ordered = ordered || matches!(self.dispatcher.router_type, RouterType::Partition);
+        self.retry = retry;
+        self
+    }
+
+
+    /// timeouts of handlers, progress (watchdog) & metrics
+    pub(crate) fn with_watch(mut self, watch: Watch) -> Self {
+        self.watch = watch;
+        self
+    }
+
+
+    /// room for another retry inside instance
+    fn accepts(&self) -> bool {
+        self.retry.as_ref().is_none_or(|r| r.accepts(self.retries.len()))
+    }
+
+
     #[inline]
-    pub fn run(mut self) {        
+    pub fn run(mut self) -> JoinHandle<()> {        
+        let id = self.id;
+
         // spawn
-        tokio::spawn(async move {
+        tokio::spawn(trace::stage(id, async move {
 
-            self.proc.init().await;
+            let _stage = self.watch.telemetry.stage(self.id);
+
+            self.watch.init(self.id, self.proc.init()).await;
             
-            while let Some(msg) = self.recv.recv().await {
-                match self.proc.handle_message(msg).await {
-                    ProcResult::Continue => (),
-                    ProcResult::Dispatch(m, pk) => {
+            loop {
+                self.watch.metrics.retrying(self.retries.len());
+
+                let (msg, attempt) = tokio::select! {
+                    biased;
+
+                    Some(p) = self.retries.next() => (p.item, p.attempt),
+                    // retries at `max_retrying`, not receive until one finished (backpressure)
+                    res = self.recv.recv(), if self.accepts() => match res {
+                        Some(msg) => {
+                            self.watch.metrics.received(1);
+                            (msg, 1)
+                        }
+                        None => break
+                    }
+                };
+
+                self.process(msg, attempt).await;
+            }
+
+            // all upstream stages closed their channel,
+            // finish messages waiting for retry
+            while let Some(p) = self.retries.next().await {
+                self.process(p.item, p.attempt).await;
+                self.watch.metrics.retrying(self.retries.len());
+            }
+
+            self.watch.terminate(self.id, self.proc.terminate()).await;
+        }))
+    }
+
+
+    /// handle and dispatch message inside its span
+    async fn process(&mut self, msg: Message<Input>, attempt: usize) {
+        let span = msg.span.child(self.id, attempt);
+        span.instrument(self.process_in(msg, attempt, span.clone())).await
+    }
+
+
+    async fn process_in(&mut self, mut msg: Message<Input>, attempt: usize, span: MessageSpan) {
+
+        // acknowledger carried by framework, not by processor
+        let ack = msg.acknowledger.take();
+
+        match self.handle_message(msg, attempt).await {
+            Handled::Done(outputs) if self.terminal || outputs.is_empty() => {
+                self.watch.metrics.sent(1);
+                acknowledger::ack(ack, None);
+            }
+            Handled::Done(outputs) => {
+                let mut ack = ack;
+                let last = outputs.len() - 1;
+                let mut delivered = true;
 
-                        let _ = self.dispatcher.dispatch(m, pk).await;
+                for (i, mut m) in outputs.into_iter().enumerate() {
 
+                    // each output carry a copy of acknowledger, 
+                    // original one kept until last output so input acked after all of them
+                    m.acknowledger = if i == last { ack.take() } else { ack.clone() };
+
+                    // span of message on next layer become child of this one
+                    m.span = span.clone();
+
+                    match self.dispatcher.dispatch(m).await {
+                        Ok(_) => (),
+                        Err(DispatchError::NotExist(m)) | Err(DispatchError::NotFound(m)) => {
+                            self.undeliverable(m).await;
+                            delivered = false;
+                        }
                     }
                 }
+
+                if delivered {
+                    self.watch.metrics.sent(1);
+                } else {
+                    self.watch.metrics.failed(1);
+                }
+            }
+            Handled::Retry(mut m, attempt, delay) => {
+                m.acknowledger = ack;
+                self.retries.push(m, attempt, delay);
+            }
+            Handled::Failed(dl) => {
+                self.watch.metrics.failed(1);
+
+                // source acked once failure sink took dead letter
+                self.failure_sink.sink(vec![(dl, ack)]).await;
+            }
+        }
+    }
+
+
+    /// a failed message (or panic inside handle_message) not crash instance,
+    /// message retried by retry policy, if not retried (or all attempts failed)
+    /// passed to handle_failed and failure sink, then skipped
+    async fn handle_message(&mut self, mut msg: Message<Input>, mut attempt: usize) -> Handled<Input, Output> {
+
+        let id = self.id;
+
+        loop {
+            let copy = msg.clone();
+
+            self.watch.ready(self.proc.ready()).await;
+
+            self.watch.telemetry.emit(|| TelemetryEvent::MessageStart { id, attempt });
+            let start = Instant::now();
+
+            let res = match catch_panic(self.watch.handler(self.proc.handle_message(msg))).await {
+                Ok(Ok(ProcResult::Continue)) => Ok(Vec::new()),
+                Ok(Ok(ProcResult::Dispatch(m))) => Ok(vec![m]),
+                Ok(Ok(ProcResult::DispatchMany(outputs))) => Ok(outputs),
+                Ok(Ok(ProcResult::Fail(e))) => Err(FailReason::Error(e)),
+                Ok(Err(timeout)) => Err(FailReason::TimedOut(timeout)),
+                Err(reason) => Err(reason)
+            };
+
+            let duration = start.elapsed();
+
+            let reason = match res {
+                Ok(m) => {
+                    self.watch.telemetry.emit(|| TelemetryEvent::MessageStop { id, attempt, duration });
+                    return Handled::Done(m)
+                }
+                Err(reason) => {
+                    self.watch.telemetry.emit(|| TelemetryEvent::MessageException { id, attempt, duration, reason: reason.clone() });
+                    reason
+                }
+            };
+
+            let delay = self.retry.as_ref().and_then(|r| r.delay(&reason, attempt));
+
+            match delay {
+                Some(delay) if self.ordered => {
+
+                    // waiting inside instance also counted as retrying
+                    self.watch.metrics.retrying(self.retries.len() + 1);
+                    tokio::time::sleep(delay).await;
+                    self.watch.metrics.retrying(self.retries.len());
+
+                    msg = copy;
+                    attempt += 1;
+                }
+                Some(delay) => return Handled::Retry(copy, attempt + 1, delay),
+                None => {
+                    let failed = copy.failed(reason.clone());
+
+                    self.proc.handle_failed(&failed).await;
+
+                    return Handled::Failed(DeadLetter::new(failed, reason, self.id, attempt))
+                }
             }
-        });
+        }
     }
+
+
+    /// next layer not exist any instance (all terminated),
+    /// output passed to failure sink of next layer
+    async fn undeliverable(&mut self, mut msg: Message<Output>) {
+
+        let ack = msg.acknowledger.take();
+
+        let dl = DeadLetter::new(msg, FailReason::Undeliverable, self.id, 1);
+        self.undeliverable.sink(vec![(dl, ack)]).await;
+    }
+}
+
+
+
+enum Handled<Input, Output> {
+
+    /// outputs must be dispatched (empty if `Continue`)
+    Done(Vec<Message<Output>>),
+
+    /// message waiting for next attempt
+    Retry(Message<Input>, usize, std::time::Duration),
+
+    /// passed to `handle_failed`, must be sent to failure sink
+    Failed(DeadLetter<Input>)
 }
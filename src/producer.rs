@@ -2,11 +2,11 @@
 use std::collections::VecDeque;
 
 use async_trait::async_trait;
-use tokio::sync::oneshot;
+use tokio::{sync::oneshot, task::JoinHandle};
 
 
 
-use crate::{dispatcher::{Dispatcher, DispatchError}, RouterType};
+use crate::{dispatcher::{Dispatcher, DispatchError}, message::Message, shutdown_manager::StageId, telemetry::{ProducerExit, TelemetryEvent}, trace, watchdog::Watch, RouterType};
 
 
 
@@ -20,11 +20,14 @@ pub trait Producer<T> {
     async fn init(&mut self);
 
     // if have not enough message 'buffer_size', no problem 
-    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<T>, Terminate>;
+    //
+    // not limited by `Timeouts::handler`, just cancelled by shutdown signal,
+    // messages pulled by a cancelled call are never acked, so source redeliver them
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<T>>, Terminate>;
 
 
     // when got shutdown signal and alreadt buffer is not empty call this
-    async fn drain(&mut self, buffer: VecDeque<T>);
+    async fn drain(&mut self, buffer: VecDeque<Message<T>>);
 
 
     // Call before shutdown
@@ -43,10 +46,12 @@ pub struct Context<T, Prod>
 where
     Prod: Producer<T>
 {
+    id: StageId,
     dispatcher: Dispatcher<T>,
     producer: Prod,
     buffer_size: usize,
-    shutdown: oneshot::Receiver<()>
+    shutdown: oneshot::Receiver<()>,
+    watch: Watch
 }
 impl<T, Prod> Context<T, Prod> 
 where
@@ -54,7 +59,8 @@ where
     Prod : Producer<T> + Send + 'static
 {
     
-    pub fn new(dispatcher: Dispatcher<T>,
+    pub fn new(id: StageId,
+               dispatcher: Dispatcher<T>,
                producer: Prod,
                buffer_size: usize,
                shutdown: oneshot::Receiver<()>) -> Result<Self, ()> {        
@@ -67,17 +73,32 @@ where
                 
 
         Ok(Context {
+            id,
             dispatcher,
             producer,
             buffer_size,
-            shutdown
+            shutdown,
+            watch: Watch::default()
         })
     }
 
+
+    /// progress (watchdog) & metrics of init, terminate and dispatched messages
+    pub(crate) fn with_watch(mut self, watch: Watch) -> Self {
+        self.watch = watch;
+        self
+    }
+
     #[inline]
-    pub fn run(mut self) {
+    pub fn run(mut self) -> JoinHandle<()> {
+        let id = self.id;
+
         // spawn
-        tokio::spawn(async move {
+        tokio::spawn(trace::stage(id, async move {
+
+            let _stage = self.watch.telemetry.stage(self.id);
+
+            self.watch.init(self.id, self.producer.init()).await;
 
             let mut buffer = VecDeque::new();
 
@@ -85,20 +106,29 @@ where
             loop {
 
 
-                if let Ok(_) = self.shutdown.try_recv() {
-                    return
-                }
+                // if buffer was empty , fill buffer
+                if buffer.is_empty() {
 
+                    let res = tokio::select! {
+                        biased;
 
-                // if buffer was empty , fill buffer
-                if buffer.len() == 0 {
+                        // got shutdown signal, stop waiting for data
+                        _ = &mut self.shutdown => {
+                            self.terminate(ProducerExit::Shutdown).await;
+                            return
+                        }
+                        // waiting for input, not counted as busy (watchdog) or handler latency
+                        res = self.producer.fill_buffer(self.buffer_size) => res
+                    };
 
-                    match self.producer.fill_buffer(self.buffer_size).await {
+                    match res {
                         Ok(buff) => {
+                            self.watch.metrics.received(buff.len());
+                            self.watch.metrics.fill(buff.len(), self.buffer_size);
                             buffer = buff;
                         }
                         Err(_) => {
-                            self.producer.terminate().await;
+                            self.terminate(ProducerExit::Terminated).await;
                             return
                         }
                     }
@@ -107,40 +137,48 @@ where
                 
 
                 // loop Dispatch until exist, at least one channel
-                loop {
-                    match buffer.pop_front() {
-                        Some(b) => {
-                            if let Err(DispatchError::NotExist(b)) = self.dispatcher.dispatch(b, None).await {
-                        
-                                // back to buffer because not exist any channel
-                                buffer.push_front(b);
-        
-        
-                                // drain
-                                self.producer.drain(buffer).await;
-        
-                                // terminate
-                                self.producer.terminate().await;
-        
-                                return
-        
-                                // DispatchError::NotFound just used by partition
-                                //   also producer.dispatcher cannot be partition mode 
-                            }
-                        }
-                        None => {
-                            break;
+                while let Some(mut b) = buffer.pop_front() {
+
+                    // trace of message started here (or continue span set by producer)
+                    let span = b.span.child(self.id, 1);
+                    b.span = span.clone();
+
+                    match span.instrument(self.dispatcher.dispatch(b)).await {
+                        Ok(_) => self.watch.metrics.sent(1),
+                        Err(DispatchError::NotExist(b)) => {
+
+                            // back to buffer because not exist any channel
+                            buffer.push_front(b);
+
+
+                            // drain
+                            self.producer.drain(buffer).await;
+
+                            // terminate
+                            self.terminate(ProducerExit::NoDestination).await;
+
+                            return
                         }
+
+                        // DispatchError::NotFound just used by partition
+                        //   also producer.dispatcher cannot be partition mode 
+                        Err(DispatchError::NotFound(_)) => unreachable!()
                     }
                 }
 
 
                 
             }
-        });
+        }))
     }
 
 
+    async fn terminate(&mut self, reason: ProducerExit) {
+        let id = self.id;
+        self.watch.telemetry.emit(|| TelemetryEvent::ProducerTerminate { id, reason });
+        self.watch.terminate(self.id, self.producer.terminate()).await;
+    }
+
 }
 
 
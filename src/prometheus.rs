@@ -0,0 +1,267 @@
+use std::{collections::BTreeMap, fmt::Write, io, net::SocketAddr, time::Duration};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    task::JoinHandle
+};
+
+use crate::{metrics::{HistogramSnapshot, MetricsSnapshot, MetricsSource, StageSnapshot}, shutdown_manager::StageKind, trace};
+
+
+
+/// max size of request head
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// max time to receive request head
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+
+/// bind listener and serve `/metrics` until aborted
+pub(crate) async fn serve<A>(addr: A,
+                             topology: String,
+                             source: MetricsSource) -> io::Result<(SocketAddr, JoinHandle<()>)>
+where
+    A: ToSocketAddrs
+{
+    let listener = TcpListener::bind(addr).await?;
+    let local = listener.local_addr()?;
+
+    let handle = tokio::spawn(async move {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    trace::error!("Prometheus exporter accept failed: {}", e);
+                    continue
+                }
+            };
+
+            let body = source.snapshot().to_prometheus(&topology);
+
+            tokio::spawn(async move {
+                let _ = respond(stream, body).await;
+            });
+        }
+    });
+
+    Ok((local, handle))
+}
+
+
+/// minimal HTTP/1.1, one request per connection
+async fn respond(mut stream: TcpStream, body: String) -> io::Result<()> {
+
+    // idle or slow client not hold its connection (and task) forever
+    let head = match tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream)).await {
+        Ok(head) => head?,
+        Err(_) => return Err(io::ErrorKind::TimedOut.into())
+    };
+
+    let head = String::from_utf8_lossy(&head);
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+
+    let response = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(path)) if path == "/metrics" || path.starts_with("/metrics?") => {
+            format!("HTTP/1.1 200 OK\r\n\
+                     Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+                     Content-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}", body.len(), body)
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_owned()
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+
+/// read until end of request head (or `MAX_REQUEST`)
+async fn read_head(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+
+    let mut head = Vec::with_capacity(512);
+    let mut buf = [0u8; 512];
+
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_REQUEST {
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            break
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    Ok(head)
+}
+
+
+
+
+impl MetricsSnapshot {
+
+    /// Prometheus text exposition format (version 0.0.4)
+    ///
+    /// labels of stage metrics are `topology`, `layer`, `kind`, `stage` (`StageName` of
+    /// instance inside upstream dispatchers) and `instance` (index of instance), same as
+    /// fields of `stage` / `message` / `batch` spans (feature `tracing`),
+    /// layer metrics (`tokio_sky_layer_lag`, `tokio_sky_layer_saturation`)
+    /// just have `topology` and `layer`, dead letter stages of layer not counted in them
+    pub fn to_prometheus(&self, topology: &str) -> String {
+        let topology = escape(topology);
+        let mut out = String::new();
+
+        let stages = self.stages
+            .iter()
+            .map(|s| (labels(&topology, s), s))
+            .collect::<Vec<_>>();
+
+
+        counter(&mut out, "tokio_sky_messages_in_total",
+                "Messages received by stage (producer: returned by fill_buffer)",
+                stages.iter().map(|(l, s)| (l, s.messages_in)));
+
+        counter(&mut out, "tokio_sky_messages_out_total",
+                "Messages dispatched to next layer or finished on last layer",
+                stages.iter().map(|(l, s)| (l, s.messages_out)));
+
+        counter(&mut out, "tokio_sky_messages_failed_total",
+                "Messages failed after all attempts or undeliverable",
+                stages.iter().map(|(l, s)| (l, s.failed)));
+
+        histogram(&mut out, "tokio_sky_handler_duration_seconds",
+                  "Duration of handle_message or handle_batch",
+                  stages.iter().map(|(l, s)| (l, &s.latency)));
+
+        gauge(&mut out, "tokio_sky_queue_depth",
+              "Messages queued in stage input channel",
+              stages.iter().filter_map(|(l, s)| s.queue_depth.map(|d| (l, d as f64))));
+
+        gauge(&mut out, "tokio_sky_queue_capacity",
+              "Bound of stage input channel",
+              stages.iter().filter_map(|(l, s)| s.queue_capacity.map(|c| (l, c as f64))));
+
+        gauge(&mut out, "tokio_sky_retrying",
+              "Messages (batches for batcher) waiting for next attempt",
+              stages.iter().filter(|(_, s)| s.id.kind != StageKind::Producer).map(|(l, s)| (l, s.retrying as f64)));
+
+        gauge(&mut out, "tokio_sky_batch_pending",
+              "Messages inside current batch, not flushed yet",
+              stages.iter().filter_map(|(l, s)| s.batch.as_ref().map(|b| (l, b.pending as f64))));
+
+        gauge(&mut out, "tokio_sky_batch_target_size",
+              "Max messages in a batch, adapted by adaptive batch size",
+              stages.iter().filter_map(|(l, s)| s.batch.as_ref().map(|b| (l, b.target_size as f64))));
+
+        histogram(&mut out, "tokio_sky_batch_size",
+                  "Messages per flushed batch",
+                  stages.iter().filter_map(|(l, s)| s.batch.as_ref().map(|b| (l, &b.sizes))));
+
+        let flushes = stages.iter()
+            .filter_map(|(l, s)| s.batch.as_ref().map(|b| (l, b)))
+            .flat_map(|(l, b)| [
+                (format!("{},reason=\"size\"", l), b.flushed_by_size),
+                (format!("{},reason=\"timeout\"", l), b.flushed_by_timeout),
+                (format!("{},reason=\"shutdown\"", l), b.flushed_by_shutdown),
+                (format!("{},reason=\"weight\"", l), b.flushed_by_weight)
+            ])
+            .collect::<Vec<_>>();
+
+        counter(&mut out, "tokio_sky_batch_flushes_total",
+                "Batch flushes by trigger",
+                flushes.iter().map(|(l, n)| (l, *n)));
+
+        histogram(&mut out, "tokio_sky_fill_ratio",
+                  "Filled messages / buffer_size of each fill_buffer",
+                  stages.iter().filter_map(|(l, s)| s.fill_ratio.as_ref().map(|f| (l, f))));
+
+
+        // per layer, (queued, capacity), dead letter stages of layer not counted
+        let mut layers = BTreeMap::<usize, (usize, usize)>::new();
+        for s in self.stages.iter().filter(|s| s.id.kind != StageKind::DeadLetter) {
+            if let (Some(depth), Some(capacity)) = (s.queue_depth, s.queue_capacity) {
+                let layer = layers.entry(s.id.layer).or_default();
+                layer.0 += depth;
+                layer.1 += capacity;
+            }
+        }
+
+        let layers = layers.into_iter()
+            .map(|(layer, q)| (format!("topology=\"{}\",layer=\"{}\"", topology, layer), q))
+            .collect::<Vec<_>>();
+
+        gauge(&mut out, "tokio_sky_layer_lag",
+              "Messages waiting to be consumed by layer",
+              layers.iter().map(|(l, (depth, _))| (l, *depth as f64)));
+
+        gauge(&mut out, "tokio_sky_layer_saturation",
+              "Queued / capacity of layer input channels (0.0 - 1.0)",
+              layers.iter().map(|(l, (depth, capacity))| (l, *depth as f64 / (*capacity).max(1) as f64)));
+
+        out
+    }
+}
+
+
+
+fn labels(topology: &str, s: &StageSnapshot) -> String {
+    format!("topology=\"{}\",layer=\"{}\",kind=\"{}\",stage=\"{}\",instance=\"{}\"",
+            topology,
+            s.id.layer,
+            format!("{:?}", s.id.kind).to_lowercase(),
+            escape(&s.id.name()),
+            s.id.instance)
+}
+
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\")
+         .replace('"', "\\\"")
+         .replace('\n', "\\n")
+}
+
+
+fn counter<'a, L, I>(out: &mut String, name: &str, help: &str, samples: I)
+where
+    L: AsRef<str> + 'a,
+    I: Iterator<Item = (&'a L, u64)>
+{
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels.as_ref(), value);
+    }
+}
+
+
+fn gauge<'a, L, I>(out: &mut String, name: &str, help: &str, samples: I)
+where
+    L: AsRef<str> + 'a,
+    I: Iterator<Item = (&'a L, f64)>
+{
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge", name, help, name);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels.as_ref(), value);
+    }
+}
+
+
+fn histogram<'a, L, I>(out: &mut String, name: &str, help: &str, samples: I)
+where
+    L: AsRef<str> + 'a,
+    I: Iterator<Item = (&'a L, &'a HistogramSnapshot)>
+{
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+    for (labels, h) in samples {
+        let labels = labels.as_ref();
+
+        // buckets are cumulative in prometheus
+        let mut cumulative = 0;
+        for (bound, count) in h.bounds.iter().zip(&h.counts) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+        }
+
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, h.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, h.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, h.count);
+    }
+}
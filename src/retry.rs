@@ -0,0 +1,238 @@
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::RandomState, BinaryHeap},
+    fmt,
+    hash::{BuildHasher, Hasher},
+    sync::Arc,
+    time::Duration
+};
+
+use tokio::time::Instant;
+
+use crate::failure::FailReason;
+
+
+
+/// decide a failure is transient (can be retried) or not
+pub type Classifier = Arc<dyn Fn(&FailReason) -> bool + Send + Sync>;
+
+
+
+/// Retry policy of a processor or batcher layer
+///
+///   * failed message (or batch) handled again until `max_attempts` (first attempt included),
+///     then passed to `handle_failed` and failure sink of layer
+///   * delay between attempts grows from `initial_backoff` by `multiplier`
+///     up to `max_backoff`, randomized by `jitter` (fraction of delay, 0.0 - 1.0)
+///   * just failures accepted by classifier are retried (default `FailReason::Error`
+///     and `FailReason::TimedOut`, panics and undeliverable messages not retried)
+///   * messages waiting for retry not block other messages of instance,
+///     except when layer receive or dispatch by `RouterType::Partition`,
+///     there instance wait on retry to keep order of messages
+///   * at most `max_retrying` messages (batches for batcher) wait for retry inside
+///     an instance, once reached instance not receive new messages until a retry finished
+#[derive(Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: usize,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    pub jitter: f64,
+    pub max_retrying: usize,
+    classifier: Classifier
+}
+
+impl RetryPolicy {
+
+    /// exponential backoff with default delays
+    pub fn new(max_attempts: usize) -> Self {
+        RetryPolicy {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// 1.0 for constant delay
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// 0.0 for no jitter
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// bound of messages (batches for batcher) waiting for retry inside an instance (at least 1)
+    pub fn max_retrying(mut self, max: usize) -> Self {
+        self.max_retrying = max.max(1);
+        self
+    }
+
+    /// retry just failures classifier returns true for
+    ///
+    /// ```ignore
+    /// RetryPolicy::new(5)
+    ///     .retry_if(|reason| matches!(reason, FailReason::Error(e) if e.contains("Deadlock")))
+    /// ```
+    pub fn retry_if<F>(mut self, classifier: F) -> Self
+    where
+        F: Fn(&FailReason) -> bool + Send + Sync + 'static
+    {
+        self.classifier = Arc::new(classifier);
+        self
+    }
+
+
+    /// instance can receive new messages, retries waiting inside it below `max_retrying`
+    pub(crate) fn accepts(&self, retrying: usize) -> bool {
+        retrying < self.max_retrying.max(1)
+    }
+
+    /// delay before next attempt, `None` if failure must not be retried
+    /// (`attempt` is number of attempts done, first attempt is 1)
+    pub(crate) fn delay(&self, reason: &FailReason, attempt: usize) -> Option<Duration> {
+
+        if attempt >= self.max_attempts || !(self.classifier)(reason) {
+            return None
+        }
+
+        let exp = self.multiplier.powi(attempt.saturating_sub(1).min(i32::MAX as usize) as i32);
+        let delay = self.initial_backoff.as_secs_f64() * exp;
+        let delay = delay.min(self.max_backoff.as_secs_f64());
+
+        // random in [1 - jitter, 1 + jitter] of delay
+        let factor = 1.0 + self.jitter * (2.0 * random() - 1.0);
+
+        Some(Duration::from_secs_f64((delay * factor).max(0.0)))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_retrying: 1000,
+            classifier: Arc::new(|reason| matches!(reason, FailReason::Error(_) | FailReason::TimedOut(_)))
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("multiplier", &self.multiplier)
+            .field("jitter", &self.jitter)
+            .field("max_retrying", &self.max_retrying)
+            .finish()
+    }
+}
+
+
+
+/// random number in [0, 1),
+/// every `RandomState` seeded with new keys so not need rand crate
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+
+
+
+/// message (or batch) waiting for its next attempt
+pub(crate) struct Pending<T> {
+    pub item: T,
+
+    /// number of next attempt
+    pub attempt: usize
+}
+
+
+struct Entry<T> {
+    deadline: Instant,
+    seq: u64,
+    pending: Pending<T>
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+
+    /// reversed, so heap pop earliest deadline (FIFO on same deadline)
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+
+/// retries of an instance ordered by deadline
+pub(crate) struct RetryQueue<T> {
+    heap: BinaryHeap<Entry<T>>,
+    seq: u64
+}
+
+impl<T> RetryQueue<T> {
+
+    pub fn new() -> Self {
+        RetryQueue {
+            heap: BinaryHeap::new(),
+            seq: 0
+        }
+    }
+
+    pub fn push(&mut self, item: T, attempt: usize, delay: Duration) {
+        self.seq += 1;
+        self.heap.push(Entry {
+            deadline: Instant::now() + delay,
+            seq: self.seq,
+            pending: Pending { item, attempt }
+        });
+    }
+
+    /// wait until earliest deadline, `None` immediately if queue empty
+    ///
+    /// cancel safe, entry removed only after deadline reached
+    pub async fn next(&mut self) -> Option<Pending<T>> {
+
+        let deadline = self.heap.peek()?.deadline;
+        tokio::time::sleep_until(deadline).await;
+
+        self.heap.pop().map(|e| e.pending)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// remove all waiting retries (instance terminated)
+    pub fn take_all(&mut self) -> Vec<Pending<T>> {
+        self.heap.drain().map(|e| e.pending).collect()
+    }
+}
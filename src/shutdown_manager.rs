@@ -1,11 +1,15 @@
-use tokio::sync::oneshot;
+use std::{fmt, future::Future, time::Duration};
+
+use tokio::{sync::oneshot, task::{JoinHandle, JoinError}};
+
+use crate::{channel::QueueGauge, dispatcher::StageName, failure::panic_message, signals::Signals, watchdog::{Stall, Watch}, metrics::{MetricsSnapshot, MetricsSource}};
 
 
 
 pub fn start_shutdown_manager(shutdown_recv: oneshot::Receiver<()>, list_shutdown: Vec<oneshot::Sender<()>>) {
-    
+
     tokio::spawn(async move {
-        
+
         // Signal
         let _signal = shutdown_recv.await;
 
@@ -15,4 +19,382 @@ pub fn start_shutdown_manager(shutdown_recv: oneshot::Receiver<()>, list_shutdow
         }
     });
 
-}
\ No newline at end of file
+}
+
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StageKind {
+    Producer,
+    Processor,
+    Batcher,
+
+    /// dead letter stage of a layer
+    DeadLetter
+}
+
+
+/// identify a stage instance inside topology
+///
+///   * layer 0 is producer layer
+///   * instance is index of instance inside layer (same as dispatcher `StageName`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageId {
+    pub layer: usize,
+    pub kind: StageKind,
+    pub instance: usize
+}
+
+impl StageId {
+
+    /// key of instance inside dispatchers of upstream layer
+    pub fn name(&self) -> StageName {
+        self.instance.to_string()
+    }
+}
+
+impl fmt::Display for StageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "layer-{}/{:?}-{}", self.layer, self.kind, self.instance)
+    }
+}
+
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StageStatus {
+
+    /// stage finished after terminate
+    Completed,
+
+    /// stage panicked, contains panic message if was string
+    Panicked(Option<String>),
+
+    /// stage task cancelled by runtime
+    Cancelled,
+
+    /// stage not drained before shutdown deadline, task aborted
+    Aborted
+}
+
+
+#[derive(Debug, Clone)]
+pub struct StageReport {
+    pub id: StageId,
+    pub status: StageStatus,
+
+    /// messages still queued in stage input channel when shutdown finished,
+    /// `None` for producers (have not input channel)
+    pub queued: Option<usize>
+}
+
+
+/// result of shutdown, one report per stage instance
+/// ordered by layer (producers first)
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownReport {
+    pub stages: Vec<StageReport>
+}
+
+impl ShutdownReport {
+
+    /// true if all stages completed
+    pub fn is_clean(&self) -> bool {
+        self.stages
+            .iter()
+            .all(|s| s.status == StageStatus::Completed)
+    }
+
+    /// stages aborted because not drained before deadline
+    pub fn stuck(&self) -> impl Iterator<Item = &StageReport> {
+        self.stages
+            .iter()
+            .filter(|s| s.status == StageStatus::Aborted)
+    }
+}
+
+
+
+/// spawned task of a stage instance
+pub(crate) struct StageTask {
+    pub id: StageId,
+    pub handle: JoinHandle<()>,
+    pub queue: Option<QueueGauge>,
+    pub watch: Watch
+}
+
+
+
+/// returned by `Topology` after start,
+/// keep all spawned stages for graceful shutdown
+pub struct TopologyHandle {
+    shutdown: oneshot::Sender<()>,
+    tasks: Vec<StageTask>,
+    metrics: MetricsSource,
+    watchdog: Option<JoinHandle<()>>,
+
+    /// prometheus endpoint
+    exporter: Option<JoinHandle<()>>
+}
+
+impl TopologyHandle {
+
+    pub(crate) fn new(shutdown: oneshot::Sender<()>, mut tasks: Vec<StageTask>) -> Self {
+
+        // producers first, then layers by order, 
+        // dead letter stage of a layer after its instances
+        tasks.sort_by_key(|t| (t.id.layer, t.id.kind == StageKind::DeadLetter, t.id.instance));
+
+        let metrics = MetricsSource::new(
+            tasks.iter()
+                .map(|t| (t.id, t.watch.metrics.clone(), t.queue.clone()))
+                .collect()
+        );
+
+        TopologyHandle {
+            shutdown,
+            tasks,
+            metrics,
+            watchdog: None,
+            exporter: None
+        }
+    }
+
+
+    /// list of all stage instances
+    pub fn stages(&self) -> Vec<StageId> {
+        self.tasks.iter().map(|t| t.id).collect()
+    }
+
+
+    /// metrics of all stage instances
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+
+    /// Serve metrics in Prometheus text format on `http://addr/metrics`,
+    /// `topology` is value of `topology` label (for several topologies in one process)
+    ///
+    ///   * returns bound address (`addr` port can be 0)
+    ///   * stopped when topology shutdown
+    #[cfg(feature = "prometheus")]
+    pub async fn serve_metrics<A>(&mut self, addr: A, topology: &str) -> std::io::Result<std::net::SocketAddr>
+    where
+        A: tokio::net::ToSocketAddrs
+    {
+        let (local, handle) = crate::prometheus::serve(addr, topology.to_owned(), self.metrics.clone()).await?;
+
+        if let Some(old) = self.exporter.replace(handle) {
+            old.abort();
+        }
+
+        Ok(local)
+    }
+
+
+    /// instances inside a call (handler, init or terminate) longer than `threshold`
+    pub fn stalled(&self, threshold: Duration) -> Vec<Stall> {
+        self.tasks
+            .iter()
+            .filter(|t| !t.handle.is_finished())
+            .filter_map(|t| {
+                t.watch.progress.busy_for()
+                    .filter(|busy_for| *busy_for >= threshold)
+                    .map(|busy_for| Stall { id: t.id, busy_for })
+            })
+            .collect()
+    }
+
+
+    /// every received message finished or waiting for input on producers,
+    /// not exist any queued, in-process, batched or retrying message
+    #[cfg(feature = "testing")]
+    pub(crate) fn is_settled(&self) -> bool {
+        self.tasks
+            .iter()
+            .filter(|t| !t.handle.is_finished())
+            .all(|t| {
+                let s = t.watch.metrics.snapshot(t.id, None);
+
+                if t.id.kind == StageKind::Producer {
+                    // whole buffer dispatched, waiting inside fill_buffer
+                    return s.messages_in == s.messages_out
+                }
+
+                t.watch.progress.busy_for().is_none()
+                    && t.queue.as_ref().map_or(0, |q| q.queued()) == 0
+                    && s.retrying == 0
+                    && s.batch.map_or(0, |b| b.pending) == 0
+            })
+    }
+
+
+    /// Watchdog
+    ///
+    ///   * every `threshold / 2` check instances, `on_stall` called once for
+    ///     each call (handler, init or terminate) not finished within `threshold`
+    ///   * producer waiting inside `fill_buffer` for input not reported
+    ///   * stopped when topology shutdown
+    pub fn watchdog<F>(&mut self, threshold: Duration, on_stall: F)
+    where
+        F: Fn(Stall) + Send + 'static
+    {
+        let watched = self.tasks
+            .iter()
+            .map(|t| (t.id, t.watch.progress.clone()))
+            .collect::<Vec<_>>();
+
+        let period = (threshold / 2).max(Duration::from_millis(1));
+
+        let handle = tokio::spawn(async move {
+
+            // call of each instance already reported
+            let mut reported = vec![None; watched.len()];
+
+            let mut interval = tokio::time::interval(period);
+
+            loop {
+                interval.tick().await;
+
+                for ((id, progress), reported) in watched.iter().zip(reported.iter_mut()) {
+
+                    let since = progress.busy_since();
+
+                    match progress.busy_for() {
+                        Some(busy_for) if busy_for >= threshold && since != *reported => {
+                            *reported = since;
+                            on_stall(Stall { id: *id, busy_for });
+                        }
+                        _ => ()
+                    }
+                }
+            }
+        });
+
+        if let Some(old) = self.watchdog.replace(handle) {
+            old.abort();
+        }
+    }
+
+
+    /// Graceful shutdown
+    ///
+    ///   * first terminate Producers, then wait until
+    ///     every processor and batcher drain its channel and call terminate
+    pub async fn shutdown(self) -> ShutdownReport {
+        self.drain(std::future::pending()).await
+    }
+
+
+    /// Graceful shutdown with deadline
+    ///
+    ///   * same as `shutdown`, but if stages not drained until `timeout`
+    ///     remaining stages aborted and reported as `StageStatus::Aborted`
+    ///     with number of messages still queued in their channel
+    pub async fn shutdown_with_timeout(self, timeout: Duration) -> ShutdownReport {
+        self.drain(tokio::time::sleep(timeout)).await
+    }
+
+
+    /// Wait for SIGINT / SIGTERM (Ctrl-C on non unix), then graceful shutdown
+    ///
+    ///   * a second signal while draining escalate to forced stop,
+    ///     remaining stages aborted and reported as `StageStatus::Aborted`
+    pub async fn shutdown_on_signals(self) -> ShutdownReport {
+
+        let mut signals = Signals::new().expect("==> Cannot listen on OS signals");
+
+        signals.recv().await;
+
+        self.drain(async move { signals.recv().await }).await
+    }
+
+
+    /// same as `shutdown_on_signals`, also force stop when 
+    /// stages not drained until `timeout` after first signal
+    pub async fn shutdown_on_signals_with_timeout(self, timeout: Duration) -> ShutdownReport {
+
+        let mut signals = Signals::new().expect("==> Cannot listen on OS signals");
+
+        signals.recv().await;
+
+        self.drain(async move {
+            tokio::select! {
+                _ = signals.recv() => (),
+                _ = tokio::time::sleep(timeout) => ()
+            }
+        }).await
+    }
+
+
+
+    /// send shutdown signal to producers and wait for stages by layer order,
+    /// when `force` resolved, abort every stage not finished yet
+    async fn drain<F>(self, force: F) -> ShutdownReport 
+    where
+        F: Future<Output = ()>
+    {
+
+        // Signal to producers
+        let _ = self.shutdown.send(());
+
+        let watchdog = self.watchdog;
+        let exporter = self.exporter;
+
+        tokio::pin!(force);
+        let mut forced = false;
+
+        let mut report = ShutdownReport::default();
+
+        // tasks sorted by layer, so upstream layers finish first
+        for mut task in self.tasks {
+
+            let mut res = None;
+
+            if !forced {
+                tokio::select! {
+                    r = &mut task.handle => {
+                        res = Some(r);
+                    }
+                    _ = &mut force => {
+                        forced = true;
+                    }
+                }
+            }
+
+            let status = match res {
+                Some(r) => status_of(r),
+                None if task.handle.is_finished() => status_of((&mut task.handle).await),
+                None => {
+                    task.handle.abort();
+                    let _ = (&mut task.handle).await;
+                    StageStatus::Aborted
+                }
+            };
+
+            report.stages.push(StageReport { 
+                id: task.id, 
+                status,
+                queued: task.queue.map(|q| q.queued())
+            })
+        }
+
+        for background in [watchdog, exporter].into_iter().flatten() {
+            background.abort();
+        }
+
+        report
+    }
+}
+
+
+
+fn status_of(res: Result<(), JoinError>) -> StageStatus {
+    match res {
+        Ok(_) => StageStatus::Completed,
+        Err(e) if e.is_panic() => StageStatus::Panicked(panic_message(&*e.into_panic())),
+        Err(_) => StageStatus::Cancelled
+    }
+}
@@ -0,0 +1,61 @@
+use std::io;
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+
+
+/// Listen on OS shutdown signals
+///
+///   * unix: SIGINT & SIGTERM 
+///   * others: Ctrl-C
+///
+/// listeners registered when created, 
+/// so a signal received before `recv` is not lost
+pub struct Signals {
+    #[cfg(unix)]
+    sigint: Signal,
+
+    #[cfg(unix)]
+    sigterm: Signal
+}
+
+impl Signals {
+
+    pub fn new() -> io::Result<Self> {
+
+        #[cfg(unix)]
+        return Ok(Signals {
+            sigint: signal(SignalKind::interrupt())?,
+            sigterm: signal(SignalKind::terminate())?
+        });
+
+        #[cfg(not(unix))]
+        return Ok(Signals {});
+    }
+
+
+    /// wait for next signal
+    pub async fn recv(&mut self) {
+
+        #[cfg(unix)]
+        tokio::select! {
+            _ = self.sigint.recv() => (),
+            _ = self.sigterm.recv() => ()
+        }
+
+        #[cfg(not(unix))]
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+
+
+/// wait for first SIGINT / SIGTERM (Ctrl-C on non unix)
+///
+/// for wiring custom shutdown, 
+/// `TopologyHandle::shutdown_on_signals` already use it
+pub async fn shutdown_signal() -> io::Result<()> {
+    Signals::new()?.recv().await;
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}, time::Duration};
+
+use tokio::{sync::watch, task::JoinHandle, time::Instant};
+
+use crate::channel::StageReceiver;
+
+
+
+/// Which instances restart when an instance crashed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestartStrategy {
+
+    /// just crashed instance restart
+    OneForOne,
+
+    /// crashed instance and every instance created after it
+    /// (higher instance index in same layer) restart
+    RestForOne
+}
+
+
+
+/// Supervision of a processor or batcher layer
+///
+///   * crashed (panicked) instance rebuilt from layer factory,
+///     and keep serving same channel (same `StageName` inside upstream dispatchers)
+///   * if more than `max_restarts` happen within `max_seconds`
+///     supervisor give up and stop all instances of the layer
+#[derive(Debug, Clone, Copy)]
+pub struct Supervision {
+    pub strategy: RestartStrategy,
+    pub max_restarts: usize,
+    pub max_seconds: Duration
+}
+
+impl Supervision {
+
+    pub fn one_for_one() -> Self {
+        Supervision {
+            strategy: RestartStrategy::OneForOne,
+            ..Default::default()
+        }
+    }
+
+    pub fn rest_for_one() -> Self {
+        Supervision {
+            strategy: RestartStrategy::RestForOne,
+            ..Default::default()
+        }
+    }
+
+    /// max number of restarts within a time window
+    pub fn max_restarts(mut self, max_restarts: usize, within: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.max_seconds = within;
+        self
+    }
+}
+
+impl Default for Supervision {
+    fn default() -> Self {
+        Supervision {
+            strategy: RestartStrategy::OneForOne,
+            max_restarts: 3,
+            max_seconds: Duration::from_secs(5)
+        }
+    }
+}
+
+
+
+
+#[derive(Clone, Copy)]
+enum Command {
+    Run,
+
+    /// restart instances with index greater than this
+    RestartAfter(usize),
+
+    /// supervisor give up
+    Shutdown
+}
+
+
+/// shared by all instances of a supervised layer
+pub(crate) struct LayerSupervisor {
+    opts: Supervision,
+    restarts: Mutex<VecDeque<Instant>>,
+    command: watch::Sender<Command>
+}
+
+impl LayerSupervisor {
+
+    pub fn new(opts: Supervision) -> Arc<Self> {
+        let (command, _) = watch::channel(Command::Run);
+        Arc::new(LayerSupervisor {
+            opts,
+            restarts: Mutex::new(VecDeque::new()),
+            command
+        })
+    }
+
+
+    /// record a restart, return false if restart intensity exceeded
+    fn allow_restart(&self) -> bool {
+        let now = Instant::now();
+        let mut restarts = self.restarts.lock().unwrap_or_else(|e| e.into_inner());
+
+        while let Some(t) = restarts.front() {
+            if now.duration_since(*t) > self.opts.max_seconds {
+                restarts.pop_front();
+            } else {
+                break
+            }
+        }
+
+        if restarts.len() >= self.opts.max_restarts {
+            return false
+        }
+
+        restarts.push_back(now);
+        true
+    }
+
+
+    /// run an instance under supervision,
+    /// `spawn` create instance from factory and run it on `recv`
+    ///
+    /// returned task finish when instance finished (channel closed),
+    /// or panic with instance panic if supervisor give up
+    pub fn supervise<T, S>(self: &Arc<Self>,
+                           instance: usize,
+                           recv: StageReceiver<T>,
+                           mut spawn: S) -> JoinHandle<()>
+    where
+        T: Send + 'static,
+        S: FnMut(StageReceiver<T>) -> JoinHandle<()> + Send + 'static
+    {
+        let sup = self.clone();
+        let mut command = self.command.subscribe();
+
+        tokio::spawn(async move {
+
+            // keep receiver, so channel stay open between restarts
+            let recv = recv;
+
+            loop {
+                let mut child = AbortOnDrop(spawn(recv.share()));
+
+                // ignore commands sent before this instance started
+                command.borrow_and_update();
+
+                loop {
+                    tokio::select! {
+                        res = &mut child.0 => {
+                            match res {
+                                Err(e) if e.is_panic() => {
+
+                                    if !sup.allow_restart() {
+
+                                        // give up, stop whole layer
+                                        let _ = sup.command.send(Command::Shutdown);
+                                        std::panic::resume_unwind(e.into_panic())
+                                    }
+
+                                    if sup.opts.strategy == RestartStrategy::RestForOne {
+                                        let _ = sup.command.send(Command::RestartAfter(instance));
+                                    }
+                                }
+
+                                // finished or cancelled
+                                _ => return
+                            }
+                            break
+                        }
+
+                        Ok(_) = command.changed() => {
+                            let cmd = *command.borrow_and_update();
+                            match cmd {
+                                Command::Run => (),
+                                Command::RestartAfter(i) if instance > i => {
+                                    child.0.abort();
+                                    let _ = (&mut child.0).await;
+                                    break
+                                }
+                                Command::RestartAfter(_) => (),
+                                Command::Shutdown => {
+                                    child.0.abort();
+                                    let _ = (&mut child.0).await;
+                                    return
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        })
+    }
+}
+
+
+
+/// abort instance if supervisor task aborted
+struct AbortOnDrop(JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
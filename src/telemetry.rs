@@ -0,0 +1,152 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{dispatcher::StageName, failure::FailReason, shutdown_manager::StageId};
+
+
+
+/// Receive telemetry events of a topology
+///
+/// called synchronously inside stage instance that emitted event,
+/// so must be fast and not block (e.g. update counters, send to a channel)
+///
+/// ```ignore
+/// let topology =
+///         Topology::producer(|| Prod)
+///             .telemetry(|e: &TelemetryEvent| {
+///                 if let TelemetryEvent::ChannelRemoved { owner, removed, remaining } = e {
+///                     println!("==> {} lost {}, {} remaining", owner, removed, remaining);
+///                 }
+///             })
+///             .then(|| Layer1Process)
+///             .start();
+/// ```
+pub trait TelemetryHandler: Send + Sync + 'static {
+    fn handle(&self, event: &TelemetryEvent);
+}
+
+impl<F> TelemetryHandler for F
+where
+    F: Fn(&TelemetryEvent) + Send + Sync + 'static
+{
+    fn handle(&self, event: &TelemetryEvent) {
+        self(event)
+    }
+}
+
+
+
+
+/// why a producer instance terminated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProducerExit {
+
+    /// topology shutdown
+    Shutdown,
+
+    /// `fill_buffer` returned `Terminate`
+    Terminated,
+
+    /// not exist any instance on first processor layer
+    NoDestination
+}
+
+
+
+/// Events of stage instances
+///
+///   * `id` is instance emitted event
+///   * `attempt` starts from 1, greater on retries
+///   * `duration` is time inside call
+#[derive(Debug, Clone)]
+pub enum TelemetryEvent {
+
+    /// instance task started (also after restart by supervisor)
+    StageStart { id: StageId },
+
+    /// instance task finished (or aborted by supervisor), `panicked` if crashed
+    StageStop { id: StageId, panicked: bool },
+
+    InitDone { id: StageId, duration: Duration },
+
+    TerminateDone { id: StageId, duration: Duration },
+
+    /// before `handle_message`
+    MessageStart { id: StageId, attempt: usize },
+
+    /// `handle_message` returned `Continue` or `Dispatch`
+    MessageStop { id: StageId, attempt: usize, duration: Duration },
+
+    /// `handle_message` failed, panicked or timed out
+    MessageException { id: StageId, attempt: usize, duration: Duration, reason: FailReason },
+
+    /// before `handle_batch`
+    BatchStart { id: StageId, size: usize, attempt: usize },
+
+    /// `handle_batch` finished, `failed` is reason if batch failed
+    BatchStop { id: StageId, size: usize, attempt: usize, duration: Duration, failed: Option<FailReason> },
+
+    /// dispatcher of `owner` removed closed channel of `removed` instance of next layer
+    ChannelRemoved { owner: StageId, removed: StageName, remaining: usize },
+
+    /// producer instance terminating
+    ProducerTerminate { id: StageId, reason: ProducerExit }
+}
+
+
+
+
+/// registered handlers of a topology, shared by all stage instances
+#[derive(Clone, Default)]
+pub(crate) struct Telemetry {
+    handlers: Arc<Vec<Arc<dyn TelemetryHandler>>>
+}
+
+impl Telemetry {
+
+    pub fn attach<H>(&mut self, handler: H)
+    where
+        H: TelemetryHandler
+    {
+        Arc::make_mut(&mut self.handlers).push(Arc::new(handler));
+    }
+
+
+    /// event built just if any handler registered
+    #[inline]
+    pub fn emit<F>(&self, event: F)
+    where
+        F: FnOnce() -> TelemetryEvent
+    {
+        if self.handlers.is_empty() {
+            return
+        }
+
+        let event = event();
+        for handler in self.handlers.iter() {
+            handler.handle(&event);
+        }
+    }
+
+
+    /// emit `StageStart` now and `StageStop` when guard dropped
+    pub fn stage(&self, id: StageId) -> StageGuard {
+        self.emit(|| TelemetryEvent::StageStart { id });
+        StageGuard { id, telemetry: self.clone() }
+    }
+}
+
+
+
+/// instance task can finish by return, panic or abort
+pub(crate) struct StageGuard {
+    id: StageId,
+    telemetry: Telemetry
+}
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        let id = self.id;
+        let panicked = std::thread::panicking();
+        self.telemetry.emit(|| TelemetryEvent::StageStop { id, panicked });
+    }
+}
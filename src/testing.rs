@@ -0,0 +1,560 @@
+use std::{
+    any::Any,
+    collections::{HashMap, VecDeque},
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, MutexGuard},
+    time::Duration
+};
+
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use tokio::{sync::{mpsc, Notify}, time::Instant};
+
+use crate::{
+    acknowledger::{Acknowledger, Acknowledgement},
+    batcher::{BatchProcessor, BatchError, BatchResult, BatchTransformer},
+    failure::FailReason,
+    message::{BatchKey, Message},
+    metrics::FlushReason,
+    processor::{Processor, ProcResult},
+    producer::{Producer, Terminate},
+    shutdown_manager::TopologyHandle
+};
+
+
+
+/// reference of a pushed message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MessageRef(u64);
+
+
+/// terminal state of a pushed message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+
+    /// finished last layer (or returned `ProcResult::Continue`)
+    Acked,
+
+    /// failed after all attempts, or undeliverable
+    Failed(FailReason),
+
+    /// dropped without acked (e.g. drained by terminated batcher, aborted stage)
+    Lost
+}
+
+
+
+
+/// This module provide helpers for testing topologies
+///
+///   * TestSource push messages into topology by its in-memory producer,
+///     every pushed message tracked until acked, failed or lost
+///   * mock_producer / mock_processor / mock_batcher on topology builders
+///     replace factory of a layer
+///
+/// ```ignore
+/// let mut source = TestSource::new();
+///
+/// let topology =
+///         Topology::producer(source.producer())
+///             .then(|| Parse)
+///             .then(|| Store)
+///             .mock_processor(2, || MockStore)
+///             .start();
+///
+/// let ok = source.push("1".to_owned());
+/// let bad = source.push("x".to_owned());
+///
+/// source.assert_acked(ok).await;
+/// source.assert_failed(bad).await;
+///
+/// source.close();
+/// topology.shutdown().await;
+/// ```
+pub struct TestSource<T> {
+    sender: Option<mpsc::UnboundedSender<Message<T>>>,
+    recv: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Message<T>>>>,
+    tracker: Arc<Tracker>
+}
+
+impl<T> TestSource<T>
+where
+    T: Send + 'static
+{
+
+    pub fn new() -> Self {
+        let (sender, recv) = mpsc::unbounded_channel();
+
+        TestSource {
+            sender: Some(sender),
+            recv: Arc::new(tokio::sync::Mutex::new(recv)),
+            tracker: Arc::new(Tracker::default())
+        }
+    }
+
+
+    /// factory of producer layer, all instances read from this source
+    pub fn producer(&self) -> impl Fn() -> TestProducer<T> + Send + Sync + 'static {
+        let recv = self.recv.clone();
+        move || TestProducer { recv: recv.clone() }
+    }
+
+
+    /// push data as a new message
+    pub fn push(&self, data: T) -> MessageRef {
+        self.push_message(Message::new(data))
+    }
+
+    /// push message (e.g. with `batch_key` or metadata), its acknowledger replaced
+    pub fn push_message(&self, msg: Message<T>) -> MessageRef {
+        let id = self.tracker.next.fetch_add(1, Ordering::Relaxed);
+
+        let ack = Acknowledgement::new(self.tracker.clone(), Tracked { id, tracker: self.tracker.clone() });
+
+        self.sender
+            .as_ref()
+            .expect("==> TestSource closed")
+            .send(msg.with_acknowledger(ack))
+            .unwrap_or_else(|_| panic!("==> TestSource producers terminated"));
+
+        MessageRef(id)
+    }
+
+    /// push several messages, producer fill them in same buffer if `buffer_pool_size` allows
+    pub fn push_batch<I>(&self, data: I) -> Vec<MessageRef>
+    where
+        I: IntoIterator<Item = T>
+    {
+        data.into_iter()
+            .map(|d| self.push(d))
+            .collect()
+    }
+
+
+    /// producers terminate after pushed messages consumed
+    pub fn close(&mut self) {
+        self.sender = None;
+    }
+
+
+    /// outcome if message reached terminal state
+    pub fn try_outcome(&self, msg: MessageRef) -> Option<Outcome> {
+        self.tracker.outcomes().get(&msg.0).cloned()
+    }
+
+    /// wait until message reached terminal state
+    pub async fn outcome(&self, msg: MessageRef) -> Outcome {
+        loop {
+            let notified = self.tracker.notify.notified();
+            tokio::pin!(notified);
+
+            // registered before check, so an ack between check and await not missed
+            notified.as_mut().enable();
+
+            if let Some(outcome) = self.try_outcome(msg) {
+                return outcome
+            }
+
+            notified.await;
+        }
+    }
+
+
+    /// wait until message acked, panic if failed or lost
+    pub async fn assert_acked(&self, msg: MessageRef) {
+        match self.outcome(msg).await {
+            Outcome::Acked => (),
+            outcome => panic!("==> {:?} expected to be acked, but {:?}", msg, outcome)
+        }
+    }
+
+    /// wait until message failed, panic if acked or lost, returns reason
+    pub async fn assert_failed(&self, msg: MessageRef) -> FailReason {
+        match self.outcome(msg).await {
+            Outcome::Failed(reason) => reason,
+            outcome => panic!("==> {:?} expected to be failed, but {:?}", msg, outcome)
+        }
+    }
+
+    /// `assert_acked`, panic if not reached terminal state within timeout
+    pub async fn assert_acked_within(&self, msg: MessageRef, timeout: Duration) {
+        tokio::time::timeout(timeout, self.assert_acked(msg))
+            .await
+            .unwrap_or_else(|_| panic!("==> {:?} not finished within {:?}", msg, timeout))
+    }
+}
+
+impl<T> Default for TestSource<T>
+where
+    T: Send + 'static
+{
+    fn default() -> Self {
+        TestSource::new()
+    }
+}
+
+
+
+
+/// steps of `advance_until_flushed` before giving up
+const MAX_ADVANCE_STEPS: usize = 100_000;
+
+/// yields of stages between two steps, so messages move through layers
+const SETTLE_YIELDS: usize = 64;
+
+
+/// Advance paused clock by `step` until topology settled: every pushed message
+/// passed all layers, batchers flushed (by size or timeout) and retries finished,
+/// returns advanced time
+///
+/// runtime must be `current_thread` with paused clock (`start_paused = true`
+/// or `tokio::time::pause()`), so timers (batch timeout, retry backoff, timeouts)
+/// fire just when clock advanced
+///
+/// ```ignore
+/// #[tokio::test(start_paused = true)]
+/// async fn flush_by_timeout() {
+///     ...
+///     source.push_batch(vec![1, 2]);
+///
+///     let elapsed = testing::advance_until_flushed(&topology, Duration::from_millis(10)).await;
+///     assert!(elapsed >= batch_timeout);
+/// }
+/// ```
+pub async fn advance_until_flushed(topology: &TopologyHandle, step: Duration) -> Duration {
+    let start = Instant::now();
+
+    for _ in 0..MAX_ADVANCE_STEPS {
+        if settle(topology).await {
+            return start.elapsed()
+        }
+
+        tokio::time::advance(step).await;
+    }
+
+    panic!("==> Topology not settled after advancing {:?}", start.elapsed())
+}
+
+
+/// run ready stages without advancing clock, true if topology settled
+async fn settle(topology: &TopologyHandle) -> bool {
+    for _ in 0..SETTLE_YIELDS {
+        tokio::task::yield_now().await;
+    }
+
+    topology.is_settled()
+}
+
+
+
+
+/// Producer of `TestSource`, terminate when source closed and empty
+pub struct TestProducer<T> {
+    recv: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<Message<T>>>>
+}
+
+#[async_trait]
+impl<T> Producer<T> for TestProducer<T>
+where
+    T: Send + 'static
+{
+    async fn init(&mut self) {}
+
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<T>>, Terminate> {
+        let mut recv = self.recv.lock().await;
+
+        let first = recv.recv().await.ok_or(Terminate)?;
+
+        let mut buffer = VecDeque::with_capacity(buffer_size);
+        buffer.push_back(first);
+
+        while buffer.len() < buffer_size {
+            match recv.try_recv() {
+                Ok(msg) => buffer.push_back(msg),
+                Err(_) => break
+            }
+        }
+
+        Ok(buffer)
+    }
+
+    /// not dispatched messages are lost
+    async fn drain(&mut self, _buffer: VecDeque<Message<T>>) {}
+
+    async fn terminate(&mut self) {}
+}
+
+
+
+
+#[derive(Default)]
+struct Tracker {
+    next: AtomicU64,
+    outcomes: Mutex<HashMap<u64, Outcome>>,
+    notify: Notify
+}
+
+impl Tracker {
+    fn outcomes(&self) -> MutexGuard<'_, HashMap<u64, Outcome>> {
+        self.outcomes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn settle(&self, id: u64, outcome: Outcome) {
+        self.outcomes().entry(id).or_insert(outcome);
+        self.notify.notify_waiters();
+    }
+}
+
+impl Acknowledger for Tracker {
+    type Ref = Tracked;
+
+    fn ack(&self, successful: Vec<Tracked>, failed: Vec<(Tracked, FailReason)>) {
+        for t in successful {
+            self.settle(t.id, Outcome::Acked);
+        }
+
+        for (t, reason) in failed {
+            self.settle(t.id, Outcome::Failed(reason));
+        }
+    }
+}
+
+
+/// reference inside acknowledgement, dropped without acked means message lost
+struct Tracked {
+    id: u64,
+    tracker: Arc<Tracker>
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        // no-op if already acked
+        self.tracker.settle(self.id, Outcome::Lost);
+    }
+}
+
+
+
+
+/// factory of a mocked layer
+type MockFactory<P> = Arc<dyn Fn() -> Box<P> + Send + Sync>;
+
+
+/// mocked factories by layer index, taken when layer started
+#[derive(Default)]
+pub(crate) struct Mocks(HashMap<usize, Box<dyn Any + Send>>);
+
+impl Mocks {
+
+    fn insert<P>(&mut self, layer: usize, factory: MockFactory<P>)
+    where
+        P: ?Sized + 'static
+    {
+        self.0.insert(layer, Box::new(factory));
+    }
+
+    pub fn producer<T, Prod, F>(&mut self, factory: F)
+    where
+        T    : Send + 'static,
+        F    : Fn() -> Prod + Send + Sync + 'static,
+        Prod : Producer<T> + Send + 'static
+    {
+        let factory: MockFactory<dyn Producer<T> + Send> = Arc::new(move || Box::new(factory()));
+        self.insert(0, factory);
+    }
+
+    pub fn processor<Input, Output, Proc, F>(&mut self, layer: usize, factory: F)
+    where
+        Input  : Send + 'static,
+        Output : Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : Processor<Input, Output> + Send + 'static
+    {
+        let factory: MockFactory<dyn Processor<Input, Output> + Send> = Arc::new(move || Box::new(factory()));
+        self.insert(layer, factory);
+    }
+
+    pub fn batcher<Input, Proc, F>(&mut self, layer: usize, factory: F)
+    where
+        Input : Send + 'static,
+        F     : Fn() -> Proc + Send + Sync + 'static,
+        Proc  : BatchProcessor<Input> + Send + 'static
+    {
+        let factory: MockFactory<dyn BatchProcessor<Input> + Send> = Arc::new(move || Box::new(factory()));
+        self.insert(layer, factory);
+    }
+
+    pub fn batch_transformer<Input, Output, Proc, F>(&mut self, layer: usize, factory: F)
+    where
+        Input  : Send + 'static,
+        Output : Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : BatchTransformer<Input, Output> + Send + 'static
+    {
+        let factory: MockFactory<dyn BatchTransformer<Input, Output> + Send> = Arc::new(move || Box::new(factory()));
+        self.insert(layer, factory);
+    }
+
+    pub fn take<P>(&mut self, layer: usize) -> Option<MockFactory<P>>
+    where
+        P: ?Sized + 'static
+    {
+        let mock = self.0.remove(&layer)?;
+
+        let factory = mock
+            .downcast::<MockFactory<P>>()
+            .unwrap_or_else(|_| panic!("==> Mock of layer {} must be same kind and types as layer", layer));
+
+        Some(*factory)
+    }
+
+    /// every mock must replace a layer
+    pub fn check_used(&self) {
+        if let Some(layer) = self.0.keys().min() {
+            panic!("==> Mock of layer {} not exist in topology", layer)
+        }
+    }
+}
+
+
+
+
+#[async_trait]
+impl<T> Producer<T> for Box<dyn Producer<T> + Send>
+where
+    T: Send + 'static
+{
+    async fn init(&mut self) {
+        (**self).init().await
+    }
+
+    async fn fill_buffer(&mut self, buffer_size: usize) -> Result<VecDeque<Message<T>>, Terminate> {
+        (**self).fill_buffer(buffer_size).await
+    }
+
+    async fn drain(&mut self, buffer: VecDeque<Message<T>>) {
+        (**self).drain(buffer).await
+    }
+
+    async fn terminate(&mut self) {
+        (**self).terminate().await
+    }
+}
+
+
+#[async_trait]
+impl<Input, Output> Processor<Input, Output> for Box<dyn Processor<Input, Output> + Send>
+where
+    Input: Send + 'static,
+    Output: Send + 'static
+{
+    async fn init(&mut self) {
+        (**self).init().await
+    }
+
+    async fn ready(&mut self) {
+        (**self).ready().await
+    }
+
+    async fn handle_message(&mut self, msg: Message<Input>) -> ProcResult<Output> {
+        (**self).handle_message(msg).await
+    }
+
+    // returns future of inner processor, so `Input` not need to be `Sync`
+    fn handle_failed<'life0, 'life1, 'async_trait>(&'life0 mut self, 
+                                                   msg: &'life1 Message<Input>) -> BoxFuture<'async_trait, ()>
+    where
+        'life0 : 'async_trait,
+        'life1 : 'async_trait,
+        Self   : 'async_trait
+    {
+        (**self).handle_failed(msg)
+    }
+
+    async fn terminate(&mut self) {
+        (**self).terminate().await
+    }
+}
+
+
+#[async_trait]
+impl<Input> BatchProcessor<Input> for Box<dyn BatchProcessor<Input> + Send>
+where
+    Input: Send + 'static
+{
+    async fn init(&mut self) {
+        (**self).init().await
+    }
+
+    async fn ready(&mut self) {
+        (**self).ready().await
+    }
+
+    async fn handle_batch(&mut self, key: Option<BatchKey>, batch: Vec<Message<Input>>) -> Result<(), BatchError<Input>> {
+        (**self).handle_batch(key, batch).await
+    }
+
+    async fn on_flush(&mut self, reason: FlushReason) {
+        (**self).on_flush(reason).await
+    }
+
+    fn handle_failed<'life0, 'life1, 'async_trait>(&'life0 mut self, 
+                                                   batch: &'life1 [Message<Input>]) -> BoxFuture<'async_trait, ()>
+    where
+        'life0 : 'async_trait,
+        'life1 : 'async_trait,
+        Self   : 'async_trait
+    {
+        (**self).handle_failed(batch)
+    }
+
+    async fn drain(&mut self, batch: Vec<Message<Input>>) {
+        (**self).drain(batch).await
+    }
+
+    async fn terminate(&mut self) {
+        (**self).terminate().await
+    }
+}
+
+
+
+#[async_trait]
+impl<Input, Output> BatchTransformer<Input, Output> for Box<dyn BatchTransformer<Input, Output> + Send>
+where
+    Input: Send + 'static,
+    Output: Send + 'static
+{
+    async fn init(&mut self) {
+        (**self).init().await
+    }
+
+    async fn ready(&mut self) {
+        (**self).ready().await
+    }
+
+    async fn handle_batch(&mut self, key: Option<BatchKey>, batch: Vec<Message<Input>>) -> Result<BatchResult<Output>, BatchError<Input>> {
+        (**self).handle_batch(key, batch).await
+    }
+
+    async fn on_flush(&mut self, reason: FlushReason) {
+        (**self).on_flush(reason).await
+    }
+
+    fn handle_failed<'life0, 'life1, 'async_trait>(&'life0 mut self, 
+                                                   batch: &'life1 [Message<Input>]) -> BoxFuture<'async_trait, ()>
+    where
+        'life0 : 'async_trait,
+        'life1 : 'async_trait,
+        Self   : 'async_trait
+    {
+        (**self).handle_failed(batch)
+    }
+
+    async fn drain(&mut self, batch: Vec<Message<Input>>) {
+        (**self).drain(batch).await
+    }
+
+    async fn terminate(&mut self) {
+        (**self).terminate().await
+    }
+}
@@ -1,14 +1,24 @@
-
-
-use std::time::Duration;
-
-use crate::batcher::{BatchProcessor, self};
-use crate::{shutdown_manager::start_shutdown_manager};
+use std::{any::Any, marker::PhantomData, sync::Arc, time::Duration};
+
+use crate::batcher::{BatchProcessor, BatchTransformer, BatchWeigher, Linger, Oversized, Terminal, self};
+use crate::batches::{BatchLimits, Batches};
+use crate::adaptive::AdaptiveBatch;
+use crate::shutdown_manager::{start_shutdown_manager, StageId, StageKind, StageTask, TopologyHandle};
+use crate::channel::StageReceiver;
+use crate::message::Message;
+use crate::supervisor::{LayerSupervisor, Supervision};
+use crate::retry::RetryPolicy;
+use crate::watchdog::{Timeouts, Watch};
+use crate::telemetry::{Telemetry, TelemetryHandler};
+#[cfg(feature = "testing")]
+use crate::testing::Mocks;
+use crate::failure::{DeadLetter, FailureSink, LogSink, SharedSink};
+use crate::dead_letter::DeadLetterSink;
 use crate::processor::Processor;
 use indexmap::IndexMap;
 use tokio::sync::{mpsc::{channel, self}, oneshot};
 
-use crate::{producer::{Producer, self}, dispatcher::{Dispatcher, RouterType}, processor};
+use crate::{producer::{Producer, self}, dispatcher::{Dispatcher, ProducerRouter, RouterType, StageName}, processor};
 
 
 pub const PRODUCER_FILLBUFFER_TIMEOUT_REALTIME: Duration = Duration::from_millis(2);
@@ -16,26 +26,26 @@ pub const PRODUCER_FILLBUFFER_TIMEOUT_BATCH: Duration = Duration::from_millis(50
 
 pub const BATCH_SIZE: usize = 100;
 pub const BATCH_TIMEOUT: Duration = Duration::from_millis(50);
-pub const CONCURRENCY: i32 = 1;
+pub const CONCURRENCY: usize = 1;
 pub const BUFFER_SIZE: usize = 10;
 pub const BUFFER_POOL_SIZE: usize = 100;
 
 
 /// Realtime, timeout is 1 milliseconds
 /// Batch,    timeout is 50 milliseconds
-/// 
+///
 /// ## Batch
-///     producer await until buffer full or timeout
-///     in Batch timeout is 50 milliseconds increase latency 
-///     if need fast serving data this is not best way
-/// 
+///   * producer await until buffer full or timeout
+///   * in Batch timeout is 50 milliseconds increase latency
+///   * if need fast serving data this is not best way
+///
 /// ## RealTime
-///     producer await until buffer full or timeout
-///     in RealTime timeout is 1 milliseconds decrease latency
-///     but may increase cpu usage 
-/// 
+///   * producer await until buffer full or timeout
+///   * in RealTime timeout is 1 milliseconds decrease latency
+///   * but may increase cpu usage
+///
 /// ## CustomTimeout
-///     if want set timeout custom  
+///   * if want set timeout custom
 pub enum ProcessingType {
     RealTime,
     Batch,
@@ -44,1160 +54,1178 @@ pub enum ProcessingType {
 
 
 
+/// input channels of a layer, one per instance
+type Channels<T> = IndexMap<StageName, mpsc::Sender<Message<T>>>;
 
-fn start_producer<T, Prod, F> (producer_factory: F,
-                               mut concurrency: i32,
-                               router: RouterType, 
-                               proc_channels: IndexMap<String, mpsc::Sender<T>>, 
-                               mut buffer_pool_size: usize,
-                               shutdown: oneshot::Receiver<()>)
+
+/// next layer of a layer
+struct Next<T> {
+    /// input channels of next layer
+    channels: Channels<T>,
+
+    /// failure sink of next layer, receive messages could not delivered to it
+    sink: SharedSink<T>
+}
+
+impl<T> Next<T>
 where
-    T: Clone + Send + 'static,
-    F: Fn() -> Prod + Send + 'static,
-    Prod: Producer<T> + Send + 'static
+    T: Send + 'static
 {
-    if concurrency <= 0 {
-        concurrency = CONCURRENCY;
+    /// last layer not have next layer
+    fn none() -> Self {
+        Next {
+            channels: IndexMap::new(),
+            sink: SharedSink::new(LogSink)
+        }
     }
+}
 
-    if buffer_pool_size <= 0 {
-        buffer_pool_size = BUFFER_POOL_SIZE;
-    }
 
-    if proc_channels.len() <= 0 {
-        panic!("==> Producer must at-least have 1_Processor_layer")
-    }
+/// start a layer and then every layer before it,
+/// gets next layer
+type Starter<T> = Box<dyn FnOnce(LayerOptions, Next<T>, &mut Stages) + Send>;
 
- 
 
+/// start failure sink of a layer (e.g. dead letter stage), gets layer index
+type SinkStarter<T> = Box<dyn FnOnce(usize, &mut Stages) -> SharedSink<T> + Send>;
 
-    let mut list_shutdown = vec![];
 
-    for _elem in 0..concurrency {
-    
-        
-        let (sx, rx) = oneshot::channel();
+/// `SinkStarter` of layer input type, type erased inside layer options
+struct ErasedSinkStarter(Box<dyn Any + Send>);
 
-        let dispatcher = Dispatcher::new(proc_channels.clone(), router).unwrap();
+impl ErasedSinkStarter {
 
-        producer::Context::new(dispatcher, 
-                               producer_factory(), 
-                               buffer_pool_size,
-                               rx).unwrap().run();
+    fn new<T>(starter: SinkStarter<T>) -> Self 
+    where
+        T: Send + 'static
+    {
+        ErasedSinkStarter(Box::new(starter))
+    }
 
-        list_shutdown.push(sx)
+    /// start sink, `LogSink` if layer not have sink
+    fn start<T>(starter: Option<Self>, layer: usize, stages: &mut Stages) -> SharedSink<T> 
+    where
+        T: Send + 'static
+    {
+        match starter {
+            Some(starter) => {
+                // builder only set sink of layer input type
+                let starter = starter.0
+                    .downcast::<SinkStarter<T>>()
+                    .expect("==> Failure sink type must be same as layer input");
+
+                starter(layer, stages)
+            }
+            None => SharedSink::new(LogSink)
+        }
     }
+}
 
 
-    start_shutdown_manager(shutdown, list_shutdown)
-}
 
+/// `BatchWeigher` of layer input type, type erased inside layer options
+struct ErasedWeigher(Box<dyn Any + Send>);
 
+impl ErasedWeigher {
 
+    fn new<T, W>(weigher: W) -> Self
+    where
+        T: Send + 'static,
+        W: BatchWeigher<T>
+    {
+        let weigher: Arc<dyn BatchWeigher<T>> = Arc::new(weigher);
+        ErasedWeigher(Box::new(weigher))
+    }
 
-pub struct Config<Output> {
-    proc_channels : IndexMap<String, mpsc::Sender<Output>>,
-    router_type: RouterType
+    fn take<T>(self) -> Arc<dyn BatchWeigher<T>>
+    where
+        T: Send + 'static
+    {
+        // builder only set weigher of layer input type
+        *self.0
+            .downcast::<Arc<dyn BatchWeigher<T>>>()
+            .expect("==> Batch weigher type must be same as layer input")
+    }
 }
 
 
-fn start_processor<Input, Output, Proc, F> (processor_factory: F,
-                                            mut concurrency: i32,
-                                            mut buffer_size: usize,
-                                            cfg: Option<Config<Output>>) -> IndexMap<String,mpsc::Sender<Input>> 
-where
-    Input  : Clone + Send + 'static,
-    Output : Clone + Send + 'static,
-    F      : Fn() -> Proc + Send + 'static,
-    Proc   : Processor<Input, Output> + Send + 'static
-{
-    if concurrency <= 0 {
-        concurrency = CONCURRENCY;
-    }
+/// options of a batcher layer
+struct BatchOptions {
+    batch_size: usize,
+    batch_timeout: Duration,
+    linger: Linger,
 
+    /// pending batch per `batch_key` inside each instance
+    by_key: bool,
 
-    if buffer_size <= 0 {
-        buffer_size = BUFFER_SIZE;
-    }
-    
+    /// max total weight of a batch
+    weight: Option<(usize, ErasedWeigher)>,
+    oversized: Oversized,
 
+    /// adapt `batch_size` of each instance
+    adaptive: Option<AdaptiveBatch>
+}
 
-    let mut list = IndexMap::with_capacity(concurrency as usize);
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            batch_size: BATCH_SIZE,
+            batch_timeout: BATCH_TIMEOUT,
+            linger: Linger::default(),
+            by_key: false,
+            weight: None,
+            oversized: Oversized::default(),
+            adaptive: None
+        }
+    }
+}
 
-    for elem in 0..concurrency {
-    
-        let (sender, recv) = channel(buffer_size);
-        list.insert(format!("{}", elem), sender);
 
-        let dispatcher = match cfg {
-            Some(ref c) => {
-                Dispatcher::new(c.proc_channels.clone(), c.router_type).unwrap()
-            }
-            None => {
-                Dispatcher::new(IndexMap::new(), RouterType::RoundRobin).unwrap()
-            }
-        };
-    
-        processor::Context::<Input, Output, Proc>::new(recv, dispatcher, processor_factory()).run();
+/// options shared by producer, processor and batcher layers
+struct LayerOptions {
+    concurrency: usize,
 
-    }
+    /// used by dispatcher for routing message to next layer
+    router: RouterType,
 
-    return list
-}
+    /// producer: buffer pool size
+    /// processor: channel size
+    buffer_size: usize,
 
+    /// restart crashed processor instances
+    supervision: Option<Supervision>,
 
+    /// destination of failed messages (`LogSink` if not set)
+    failure_sink: Option<ErasedSinkStarter>,
 
+    retry: Option<RetryPolicy>,
 
+    timeouts: Timeouts,
 
-fn start_batch_processor<Input, Proc, F> (batcher_factory: F,
-                                          mut concurrency: i32,
-                                          mut buffer_size: usize,
-                                          mut batch_size: usize,
-                                          mut batch_timeout: Duration
-                                        ) -> IndexMap<String,mpsc::Sender<Input>> 
-where
-    Input  : Clone + Send + 'static,
-    F      : Fn() -> Proc + Send + 'static,
-    Proc   : BatchProcessor<Input> + Send + 'static
-{
+    /// input partitioned by upstream dispatcher (set when started)
+    ordered: bool,
 
-    if concurrency <= 0 {
-        concurrency = CONCURRENCY;
-    }
+    /// just for batcher layers
+    batch: Option<BatchOptions>
+}
 
-    if buffer_size <= 0 {
-        buffer_size = BUFFER_SIZE;
-    }
+impl LayerOptions {
 
-    if batch_size <= 0 {
-        batch_size = BATCH_SIZE;
+    fn batcher() -> Self {
+        LayerOptions {
+            batch: Some(BatchOptions::default()),
+            ..Default::default()
+        }
     }
 
-    if batch_timeout <= Duration::ZERO {
-        batch_timeout = BATCH_TIMEOUT;
+    /// batch options, just set by builders of batcher layers
+    fn batch(&mut self) -> &mut BatchOptions {
+        self.batch.get_or_insert_with(BatchOptions::default)
     }
+}
 
+impl Default for LayerOptions {
+    fn default() -> Self {
+        LayerOptions {
+            concurrency: CONCURRENCY,
+            router: RouterType::RoundRobin,
+            buffer_size: BUFFER_SIZE,
+            supervision: None,
+            failure_sink: None,
+            retry: None,
+            timeouts: Timeouts::default(),
+            ordered: false,
+            batch: None
+        }
+    }
+}
 
 
-    let mut list = IndexMap::with_capacity(concurrency as usize);
+/// options of whole topology, can be set on any builder
+#[derive(Default)]
+struct TopologyOptions {
+    /// handlers passed to every stage instance
+    telemetry: Telemetry,
 
-    for elem in 0..concurrency {
-    
-        let (sender, recv) = channel(buffer_size);
-        list.insert(format!("{}", elem), sender);
-    
+    /// mocked layer factories
+    #[cfg(feature = "testing")]
+    mocks: Mocks
+}
 
-        batcher::Context::<Input, Proc>::new(recv, 
-                                             batcher_factory(),
-                                             batch_size,
-                                             batch_timeout).run();
 
-    }
-
-    return list
+/// everything that topology started
+#[derive(Default)]
+struct Stages {
+    producers_shutdown: Vec<oneshot::Sender<()>>,
+    tasks: Vec<StageTask>,
+    topology: TopologyOptions
 }
 
 
+struct Layer<T> {
+    /// index of layer, producer layer is 0
+    index: usize,
+    opts: LayerOptions,
+    starter: Starter<T>,
 
+    /// carried to last layer, passed to stages when started
+    topology: TopologyOptions
+}
 
+impl<T> Layer<T>
+where
+    T: Clone + Send + 'static
+{
 
+    /// append a processor layer after this layer
+    fn then<Output, Proc, F>(self, processor_factory: F) -> Layer<Output>
+    where
+        Output : Clone + Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : Processor<T, Output> + Send + 'static
+    {
+        let mut upstream = self;
+        let index = upstream.index + 1;
+
+        Layer {
+            index,
+            opts: LayerOptions::default(),
+            topology: std::mem::take(&mut upstream.topology),
+            starter: Box::new(move |mut opts, next, stages| {
+
+                opts.ordered = matches!(upstream.opts.router, RouterType::Partition);
+
+                #[cfg(feature = "testing")]
+                if let Some(mock) = stages.topology.mocks.take::<dyn Processor<T, Output> + Send>(index) {
+                    let proc = start_processor(move || mock(), index, StageKind::Processor, opts, next, stages);
+                    return (upstream.starter)(upstream.opts, proc, stages)
+                }
+
+                let proc =
+                        start_processor(processor_factory, index, StageKind::Processor, opts, next, stages);
+
+                (upstream.starter)(upstream.opts, proc, stages)
+            })
+        }
+    }
 
 
+    /// append a batcher layer after this layer, 
+    /// outputs of each batch dispatched to next layer
+    fn then_batch<Output, Proc, F>(self, batcher_factory: F) -> Layer<Output>
+    where
+        Output : Clone + Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : BatchTransformer<T, Output> + Send + 'static
+    {
+        let mut upstream = self;
+        let index = upstream.index + 1;
+
+        Layer {
+            index,
+            opts: LayerOptions::batcher(),
+            topology: std::mem::take(&mut upstream.topology),
+            starter: Box::new(move |mut opts, next, stages| {
+
+                opts.ordered = matches!(upstream.opts.router, RouterType::Partition);
+
+                #[cfg(feature = "testing")]
+                if let Some(mock) = stages.topology.mocks.take::<dyn BatchTransformer<T, Output> + Send>(index) {
+                    let batcher = start_batcher(move || mock(), index, StageKind::Batcher, opts, next, stages);
+                    return (upstream.starter)(upstream.opts, batcher, stages)
+                }
+
+                let batcher =
+                        start_batcher(batcher_factory, index, StageKind::Batcher, opts, next, stages);
+
+                (upstream.starter)(upstream.opts, batcher, stages)
+            })
+        }
+    }
 
 
-// ------------------------------------------------------------
-
+    /// start this layer and every layer before it
+    fn start(self, mut stages: Stages, next: Next<T>) -> TopologyHandle {
 
+        (self.starter)(self.opts, next, &mut stages);
 
+        #[cfg(feature = "testing")]
+        stages.topology.mocks.check_used();
 
-/// producer -> processor
-/// 
-pub fn run_topology_1<Prod, ProdFactory, Input, Output, Proc, ProcFactory>
 
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     processor_factory: ProcFactory,
-     proc_concurrency: i32,
-     proc_buffer_size: usize) -> oneshot::Sender<()>
+        // Shutdown channel
+        let (sx, rx) = oneshot::channel::<()>();
 
-where
-    Prod: Producer<Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
+        start_shutdown_manager(rx, stages.producers_shutdown);
 
-    Input: Clone + Send + 'static,
-    Output: Clone + Send + 'static,
+        TopologyHandle::new(sx, stages.tasks)
+    }
+}
 
-    Proc: Processor<Input, Output> + Send + 'static,
-    ProcFactory: Fn() -> Proc + Send + 'static
-{
-    
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
 
 
-    let proc_channels  = 
-            start_processor(processor_factory, proc_concurrency, proc_buffer_size, None);
-    
 
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   proc_channels, 
-                   buffer_pool_size, 
-                   rx);
 
-    sx
+/// Entry point for creating and syncing components
+///
+/// TokioSky always have one Producer layer,
+/// at-least 1 processor (or `then_batch` batcher) layer, and 1 optional `batcher` layer at end
+///
+///
+/// ```ignore
+/// let topology =
+///         Topology::producer(|| Prod)
+///             .concurrency(3)
+///             .then(|| Layer1Process)
+///             .router(RouterType::Partition)
+///             .then(|| Layer2Process)
+///             .batcher(|| Batcher)
+///             .batch_size(100)
+///             .start();
+///
+/// let report = topology.shutdown().await;
+/// ```
+pub struct Topology;
+
+impl Topology {
+
+    /// first layer of topology
+    pub fn producer<T, Prod, F>(producer_factory: F) -> ProducerBuilder<T>
+    where
+        T    : Clone + Send + 'static,
+        F    : Fn() -> Prod + Send + 'static,
+        Prod : Producer<T> + Send + 'static
+    {
+        ProducerBuilder(Layer {
+            index: 0,
+            opts: LayerOptions {
+                buffer_size: BUFFER_POOL_SIZE,
+                ..Default::default()
+            },
+            topology: TopologyOptions::default(),
+            starter: Box::new(move |opts, next, stages| {
+
+                #[cfg(feature = "testing")]
+                if let Some(mock) = stages.topology.mocks.take::<dyn Producer<T> + Send>(0) {
+                    return start_producer(move || mock(), opts, next.channels, stages)
+                }
+
+                start_producer(producer_factory, opts, next.channels, stages)
+            })
+        })
+    }
 }
 
 
 
 
 
-/// producer -> processor -> processor
-/// 
-pub fn run_topology_2<Prod, ProdFactory, 
-                      Layer1Input, Layer1Output, Layer1Proc, Layer1ProcFactory,
-                      Layer2Output, Layer2Proc, Layer2ProcFactory>
+/// Producer layer, must have at-least 1 processor layer
+pub struct ProducerBuilder<T>(Layer<T>);
 
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     layer1_processor_factory: Layer1ProcFactory,
-     layer1_proc_concurrency: i32,
-     layer1_router: RouterType,
-     layer1_buffer_size: usize,
-    
-     layer2_processor_factory: Layer2ProcFactory,
-     layer2_proc_concurrency: i32,
-     layer2_buffer_size: usize
-    
+impl<T> ProducerBuilder<T>
+where
+    T: Clone + Send + 'static
+{
 
-    ) -> oneshot::Sender<()>
+    /// number of producer instances (default `CONCURRENCY`)
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.0.opts.concurrency = concurrency;
+        self
+    }
 
-where
-    Prod: Producer<Layer1Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
-    Layer1Input: Clone + Send + 'static,
+    /// routing to first processor layer (default `RoundRobin`)
+    pub fn router(mut self, router: ProducerRouter) -> Self {
+        self.0.opts.router = router.into();
+        self
+    }
 
-    Layer1Output: Clone + Send + 'static,
+    /// producer internally used buffer for increase throughout (default `BUFFER_POOL_SIZE`)
+    pub fn buffer_pool_size(mut self, buffer_pool_size: usize) -> Self {
+        self.0.opts.buffer_size = buffer_pool_size;
+        self
+    }
 
-    Layer2Output: Clone + Send + 'static,
+    /// timeouts of `init` & `terminate`, producers have no handler timeout 
+    /// (`fill_buffer` waits for input and returns what it has by its own deadline, 
+    /// e.g. `ProcessingType`), panics if `Timeouts::handler` set
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        assert!(timeouts.handler.is_none(), "==> Timeouts::handler not applied to producers, fill_buffer must return by its own deadline");
+        self.0.opts.timeouts = timeouts;
+        self
+    }
 
-    Layer1Proc: Processor<Layer1Input, Layer1Output> + Send + 'static,
-    Layer1ProcFactory: Fn() -> Layer1Proc + Send + 'static,
+    /// register telemetry handler of whole topology (can be called several times)
+    pub fn telemetry<H>(mut self, handler: H) -> Self
+    where
+        H: TelemetryHandler
+    {
+        self.0.topology.telemetry.attach(handler);
+        self
+    }
 
-    Layer2Proc: Processor<Layer1Output, Layer2Output> + Send + 'static,
-    Layer2ProcFactory: Fn() -> Layer2Proc + Send + 'static
-{
-    
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
-
-
-    let layer2_proc_channels  = 
-            start_processor(layer2_processor_factory, 
-                            layer2_proc_concurrency, 
-                            layer2_buffer_size, 
-                            None);
-    
-
-            
-    let layer1_proc_channels  = 
-            start_processor(layer1_processor_factory, 
-                            layer1_proc_concurrency, 
-                            layer1_buffer_size, 
-                            Some(Config { proc_channels: layer2_proc_channels, router_type: layer1_router  }));
-    
-
-
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   layer1_proc_channels, 
-                   buffer_pool_size,
-                   rx);
-
-    sx
+    /// append first processor layer
+    pub fn then<Output, Proc, F>(self, processor_factory: F) -> TopologyBuilder<T, Output>
+    where
+        Output : Clone + Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : Processor<T, Output> + Send + 'static
+    {
+        TopologyBuilder(self.0.then(processor_factory), PhantomData)
+    }
+
+    /// append first layer as a batcher, its outputs dispatched to next layer
+    pub fn then_batch<Output, Proc, F>(self, batcher_factory: F) -> BatchLayerBuilder<T, Output>
+    where
+        Output : Clone + Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : BatchTransformer<T, Output> + Send + 'static
+    {
+        BatchLayerBuilder(self.0.then_batch(batcher_factory), PhantomData)
+    }
 }
 
 
 
-/// producer -> processor -> processor -> processor
-/// 
-pub fn run_topology_3<Prod, ProdFactory, Layer1Input, 
-                      Layer1Output, Layer1Proc, Layer1ProcFactory,
-                      Layer2Output, Layer2Proc, Layer2ProcFactory,
-                      Layer3Output, Layer3Proc, Layer3ProcFactory>
-
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     layer1_processor_factory: Layer1ProcFactory,
-     layer1_proc_concurrency: i32,
-     layer1_router: RouterType,
-     layer1_buffer_size: usize,
-    
-     layer2_processor_factory: Layer2ProcFactory,
-     layer2_proc_concurrency: i32,
-     layer2_router: RouterType,
-     layer2_buffer_size: usize,
-    
-     layer3_processor_factory: Layer3ProcFactory,
-     layer3_proc_concurrency: i32,
-     layer3_buffer_size: usize,
-
-    ) -> oneshot::Sender<()>
 
-where
-    Prod: Producer<Layer1Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
-    Layer1Input: Clone + Send + 'static,
 
-    Layer1Output: Clone + Send + 'static,
+/// options of a processor or batcher layer, same on every builder its last layer on
+/// (builder provides `opts()`), docs name kind of instances, what fails and handler
+macro_rules! layer_options {
+    ($stage:literal, $items:literal, $handler:literal) => {
 
-    Layer2Output: Clone + Send + 'static,
+        #[doc = concat!("number of ", $stage, " instances (default `CONCURRENCY`)")]
+        pub fn concurrency(mut self, concurrency: usize) -> Self {
+            self.opts().concurrency = concurrency;
+            self
+        }
 
-    Layer3Output: Clone + Send + 'static,
+        #[doc = concat!("channel size of each ", $stage, " instance (default `BUFFER_SIZE`)")]
+        pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+            self.opts().buffer_size = buffer_size;
+            self
+        }
 
-    Layer1Proc: Processor<Layer1Input, Layer1Output> + Send + 'static,
-    Layer1ProcFactory: Fn() -> Layer1Proc + Send + 'static,
+        #[doc = concat!("restart crashed ", $stage, " instances from factory (default not supervised)")]
+        pub fn supervise(mut self, supervision: Supervision) -> Self {
+            self.opts().supervision = Some(supervision);
+            self
+        }
 
-    Layer2Proc: Processor<Layer1Output, Layer2Output> + Send + 'static,
-    Layer2ProcFactory: Fn() -> Layer2Proc + Send + 'static,
+        #[doc = concat!("retry failed ", $items, " of this layer")]
+        pub fn retry(mut self, policy: RetryPolicy) -> Self {
+            self.opts().retry = Some(policy);
+            self
+        }
 
-    Layer3Proc: Processor<Layer2Output, Layer3Output> + Send + 'static,
-    Layer3ProcFactory: Fn() -> Layer3Proc + Send + 'static
-{
-   
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
-
-
-    let layer3_proc_channels  = 
-            start_processor(layer3_processor_factory, 
-                            layer3_proc_concurrency, 
-                            layer3_buffer_size, 
-                            None);
-            
-
-    let layer2_proc_channels  = 
-            start_processor(layer2_processor_factory, 
-                            layer2_proc_concurrency, 
-                            layer2_buffer_size, 
-                            Some(Config { proc_channels: layer3_proc_channels, router_type: layer2_router }));
-    
-
-            
-    let layer1_proc_channels  = 
-            start_processor(layer1_processor_factory, 
-                            layer1_proc_concurrency, 
-                            layer1_buffer_size, 
-                            Some(Config { proc_channels: layer2_proc_channels, router_type: layer1_router  }));
-    
-
-
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   layer1_proc_channels, 
-                   buffer_pool_size,
-                   rx);
-                
-    sx
+        #[doc = concat!("timeouts of `init`, `", $handler, "` (handler) & `terminate`")]
+        pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+            self.opts().timeouts = timeouts;
+            self
+        }
+    };
 }
 
 
+/// routing of a layer has next layer
+macro_rules! router_option {
+    () => {
 
+        /// routing to next layer (default `RoundRobin`)
+        pub fn router(mut self, router: RouterType) -> Self {
+            self.opts().router = router;
+            self
+        }
+    };
+}
 
-/// producer -> processor -> processor -> processor -> processor
-/// 
-pub fn run_topology_4<Prod, ProdFactory, Layer1Input, 
-                      Layer1Output, Layer1Proc, Layer1ProcFactory,
-                      Layer2Output, Layer2Proc, Layer2ProcFactory,
-                      Layer3Output, Layer3Proc, Layer3ProcFactory,
-                      Layer4Output, Layer4Proc, Layer4ProcFactory>
-
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     layer1_processor_factory: Layer1ProcFactory,
-     layer1_proc_concurrency: i32,
-     layer1_router: RouterType,
-     layer1_buffer_size: usize,
-    
-     layer2_processor_factory: Layer2ProcFactory,
-     layer2_proc_concurrency: i32,
-     layer2_router: RouterType,
-     layer2_buffer_size: usize,
-    
-     layer3_processor_factory: Layer3ProcFactory,
-     layer3_proc_concurrency: i32,
-     layer3_router: RouterType,
-     layer3_buffer_size: usize,
-
-     layer4_processor_factory: Layer4ProcFactory,
-     layer4_proc_concurrency: i32,
-     layer4_buffer_size: usize,
-
-    ) -> oneshot::Sender<()>
 
-where
-    Prod: Producer<Layer1Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
-    Layer1Input: Clone + Send + 'static,
+/// options of a batcher layer, `$input` is input of layer
+macro_rules! batch_options {
+    ($input:ty) => {
+
+        /// max messages in a batch (default `BATCH_SIZE`)
+        pub fn batch_size(mut self, batch_size: usize) -> Self {
+            self.opts().batch().batch_size = batch_size;
+            self
+        }
+
+        /// handle batch if not full after this timeout (default `BATCH_TIMEOUT`),
+        /// measured by `linger`
+        pub fn batch_timeout(mut self, batch_timeout: Duration) -> Self {
+            self.opts().batch().batch_timeout = batch_timeout;
+            self
+        }
+
+        /// measure `batch_timeout` from first (`Linger::Absolute`, default) 
+        /// or last message (`Linger::Idle`) of batch
+        pub fn linger(mut self, linger: Linger) -> Self {
+            self.opts().batch().linger = linger;
+            self
+        }
+
+        /// each instance hold a pending batch per `batch_key` of messages, 
+        /// each one flushed by its own size and timeout (default one batch for all messages)
+        pub fn batch_by_key(mut self) -> Self {
+            self.opts().batch().by_key = true;
+            self
+        }
+
+        /// flush batch also when total weight of its messages reach `max_weight`
+        /// (e.g. payload bytes), a batch never heavier than it
+        ///
+        /// ```ignore
+        /// .batcher(|| Insert)
+        /// .max_batch_weight(1 << 20, |m: &Message<Row>| m.data.len())
+        /// ```
+        pub fn max_batch_weight<W>(mut self, max_weight: usize, weigher: W) -> Self
+        where
+            W: BatchWeigher<$input>
+        {
+            self.opts().batch().weight = Some((max_weight, ErasedWeigher::new(weigher)));
+            self
+        }
+
+        /// what to do with a message heavier than `max_batch_weight` 
+        /// (default `Oversized::Alone`, flushed as a batch of one message)
+        pub fn oversized(mut self, oversized: Oversized) -> Self {
+            self.opts().batch().oversized = oversized;
+            self
+        }
+
+        /// adapt batch size of each instance within bounds, toward a `handle_batch` latency
+        /// or throughput (default fixed `batch_size`), `batch_size` is initial size
+        ///
+        /// ```ignore
+        /// .batcher(|| Insert)
+        /// .adaptive_batch(AdaptiveBatch::latency(Duration::from_millis(200)).bounds(10, 5000))
+        /// ```
+        pub fn adaptive_batch(mut self, adaptive: AdaptiveBatch) -> Self {
+            self.opts().batch().adaptive = Some(adaptive);
+            self
+        }
+    };
+}
 
-    Layer1Output: Clone + Send + 'static,
 
-    Layer2Output: Clone + Send + 'static,
+/// failure sink of a layer, `$input` is input of layer
+macro_rules! failure_options {
+    ($input:ty) => {
+
+        /// destination of failed messages of this layer (default logged to stderr)
+        pub fn failure_sink<S>(mut self, sink: S) -> Self 
+        where
+            S: FailureSink<$input> + 'static
+        {
+            self.opts().failure_sink = Some(sink_starter(sink));
+            self
+        }
+
+        /// dead letter stage of this layer, a processor layer receive failed 
+        /// and undeliverable messages of this layer (replace failure sink)
+        pub fn dead_letter<Proc, F>(mut self, processor_factory: F) -> Self 
+        where
+            F    : Fn() -> Proc + Send + Sync + 'static,
+            Proc : Processor<DeadLetter<$input>, ()> + Send + 'static
+        {
+            self.opts().failure_sink = Some(dead_letter_starter(processor_factory));
+            self
+        }
+
+        /// same as `dead_letter`, but dead letter stage is a batcher layer
+        pub fn dead_letter_batcher<Proc, F>(mut self, batcher_factory: F) -> Self 
+        where
+            F    : Fn() -> Proc + Send + Sync + 'static,
+            Proc : BatchProcessor<DeadLetter<$input>> + Send + 'static
+        {
+            self.opts().failure_sink = Some(dead_letter_batcher_starter(batcher_factory));
+            self
+        }
+    };
+}
+
 
-    Layer3Output: Clone + Send + 'static,
+/// options of whole topology, can be set on builder of any processor or batcher layer
+/// (builder provides `topology()`)
+macro_rules! topology_options {
+    () => {
+
+        /// register telemetry handler of whole topology (can be called several times)
+        pub fn telemetry<H>(mut self, handler: H) -> Self
+        where
+            H: TelemetryHandler
+        {
+            self.topology().telemetry.attach(handler);
+            self
+        }
+
+        /// replace producer factory by a mock, `U` must be output type of producers,
+        /// else `start` panics (feature `testing`)
+        #[cfg(feature = "testing")]
+        pub fn mock_producer<U, Prod, F>(mut self, factory: F) -> Self
+        where
+            U    : Send + 'static,
+            F    : Fn() -> Prod + Send + Sync + 'static,
+            Prod : Producer<U> + Send + 'static
+        {
+            self.topology().mocks.producer(factory);
+            self
+        }
+
+        /// replace factory of processor layer `layer` (first processor layer is 1) by a mock,
+        /// must have same input and output as layer (feature `testing`)
+        #[cfg(feature = "testing")]
+        pub fn mock_processor<I, O, Proc, F>(mut self, layer: usize, factory: F) -> Self
+        where
+            I    : Send + 'static,
+            O    : Send + 'static,
+            F    : Fn() -> Proc + Send + Sync + 'static,
+            Proc : Processor<I, O> + Send + 'static
+        {
+            self.topology().mocks.processor(layer, factory);
+            self
+        }
+
+        /// replace factory of batcher layer `layer` added by `then_batch` by a mock,
+        /// must have same input and output as layer (feature `testing`)
+        #[cfg(feature = "testing")]
+        pub fn mock_batch_transformer<I, O, Proc, F>(mut self, layer: usize, factory: F) -> Self
+        where
+            I    : Send + 'static,
+            O    : Send + 'static,
+            F    : Fn() -> Proc + Send + Sync + 'static,
+            Proc : BatchTransformer<I, O> + Send + 'static
+        {
+            self.topology().mocks.batch_transformer(layer, factory);
+            self
+        }
+    };
+}
 
-    Layer4Output: Clone + Send + 'static,
 
-    Layer1Proc: Processor<Layer1Input, Layer1Output> + Send + 'static,
-    Layer1ProcFactory: Fn() -> Layer1Proc + Send + 'static,
 
-    Layer2Proc: Processor<Layer1Output, Layer2Output> + Send + 'static,
-    Layer2ProcFactory: Fn() -> Layer2Proc + Send + 'static,
 
-    Layer3Proc: Processor<Layer2Output, Layer3Output> + Send + 'static,
-    Layer3ProcFactory: Fn() -> Layer3Proc + Send + 'static,
+/// Topology which last layer is a processor layer,
+/// options set on this builder belongs to last layer
+///
+/// `In` is input and `T` is output of last layer
+pub struct TopologyBuilder<In, T>(Layer<T>, PhantomData<fn(In)>);
 
-    Layer4Proc: Processor<Layer3Output, Layer4Output> + Send + 'static,
-    Layer4ProcFactory: Fn() -> Layer4Proc + Send + 'static
+impl<In, T> TopologyBuilder<In, T>
+where
+    In: Clone + Send + 'static,
+    T: Clone + Send + 'static
 {
-   
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
-
-
-    let layer4_proc_channels  = 
-            start_processor(layer4_processor_factory, 
-                            layer4_proc_concurrency, 
-                            layer4_buffer_size, 
-                            None);
-    
-
-    let layer3_proc_channels  = 
-            start_processor(layer3_processor_factory, 
-                            layer3_proc_concurrency, 
-                            layer3_buffer_size, 
-                            Some(Config { proc_channels: layer4_proc_channels, router_type: layer3_router }));
-                            
-    
-
-    let layer2_proc_channels  = 
-            start_processor(layer2_processor_factory, 
-                            layer2_proc_concurrency, 
-                            layer2_buffer_size, 
-                            Some(Config { proc_channels: layer3_proc_channels, router_type: layer2_router }));
-    
-
-            
-    let layer1_proc_channels  = 
-            start_processor(layer1_processor_factory, 
-                            layer1_proc_concurrency, 
-                            layer1_buffer_size, 
-                            Some(Config { proc_channels: layer2_proc_channels, router_type: layer1_router  }));
-    
-
-
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   layer1_proc_channels, 
-                   buffer_pool_size,
-                   rx);
-                
-    sx
-}
 
+    fn opts(&mut self) -> &mut LayerOptions {
+        &mut self.0.opts
+    }
 
+    fn topology(&mut self) -> &mut TopologyOptions {
+        &mut self.0.topology
+    }
 
+    layer_options!("processor", "messages", "handle_message");
+    router_option!();
+    failure_options!(In);
+    topology_options!();
+
+    /// append next processor layer
+    pub fn then<Output, Proc, F>(self, processor_factory: F) -> TopologyBuilder<T, Output>
+    where
+        Output : Clone + Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : Processor<T, Output> + Send + 'static
+    {
+        TopologyBuilder(self.0.then(processor_factory), PhantomData)
+    }
 
+    /// append a batcher layer in middle of topology,
+    /// outputs of each batch dispatched to next layer
+    pub fn then_batch<Output, Proc, F>(self, batcher_factory: F) -> BatchLayerBuilder<T, Output>
+    where
+        Output : Clone + Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : BatchTransformer<T, Output> + Send + 'static
+    {
+        BatchLayerBuilder(self.0.then_batch(batcher_factory), PhantomData)
+    }
 
-/// producer -> processor -> processor -> processor -> processor
-/// 
-pub fn run_topology_5<Prod, ProdFactory, Layer1Input, 
-                      Layer1Output, Layer1Proc, Layer1ProcFactory,
-                      Layer2Output, Layer2Proc, Layer2ProcFactory,
-                      Layer3Output, Layer3Proc, Layer3ProcFactory,
-                      Layer4Output, Layer4Proc, Layer4ProcFactory,
-                      Layer5Output, Layer5Proc, Layer5ProcFactory>
-
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     layer1_processor_factory: Layer1ProcFactory,
-     layer1_proc_concurrency: i32,
-     layer1_router: RouterType,
-     layer1_buffer_size: usize,
-    
-     layer2_processor_factory: Layer2ProcFactory,
-     layer2_proc_concurrency: i32,
-     layer2_router: RouterType,
-     layer2_buffer_size: usize,
-    
-     layer3_processor_factory: Layer3ProcFactory,
-     layer3_proc_concurrency: i32,
-     layer3_router: RouterType,
-     layer3_buffer_size: usize,
-
-     layer4_processor_factory: Layer4ProcFactory,
-     layer4_proc_concurrency: i32,
-     layer4_router: RouterType,
-     layer4_buffer_size: usize,
-
-     layer5_processor_factory: Layer5ProcFactory,
-     layer5_proc_concurrency: i32,
-     layer5_buffer_size: usize,
-
-    ) -> oneshot::Sender<()>
+    /// append batcher as latest layer
+    pub fn batcher<Proc, F>(self, batcher_factory: F) -> BatcherBuilder<T>
+    where
+        F    : Fn() -> Proc + Send + Sync + 'static,
+        Proc : BatchProcessor<T> + Send + 'static
+    {
+        let index = self.0.index + 1;
+
+        BatcherBuilder {
+            opts: LayerOptions::batcher(),
+            upstream: self.0,
+            starter: Box::new(move |opts, stages| {
+
+                #[cfg(feature = "testing")]
+                if let Some(mock) = stages.topology.mocks.take::<dyn BatchProcessor<T> + Send>(index) {
+                    return start_batcher(move || Terminal(mock()), index, StageKind::Batcher, opts, Next::none(), stages)
+                }
+
+                start_batcher(move || Terminal(batcher_factory()), index, StageKind::Batcher, opts, Next::none(), stages)
+            })
+        }
+    }
 
-where
-    Prod: Producer<Layer1Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
-    Layer1Input: Clone + Send + 'static,
+    /// create and syncing components,
+    /// returns handle for graceful shutdown
+    pub fn start(mut self) -> TopologyHandle {
+        let stages = Stages {
+            topology: std::mem::take(&mut self.0.topology),
+            ..Default::default()
+        };
 
-    Layer1Output: Clone + Send + 'static,
+        self.0.start(stages, Next::none())
+    }
 
-    Layer2Output: Clone + Send + 'static,
+}
 
-    Layer3Output: Clone + Send + 'static,
 
-    Layer4Output: Clone + Send + 'static,
 
-    Layer5Output: Clone + Send + 'static,
 
-    Layer1Proc: Processor<Layer1Input, Layer1Output> + Send + 'static,
-    Layer1ProcFactory: Fn() -> Layer1Proc + Send + 'static,
+/// Topology which last layer is a batcher layer in middle of topology (`then_batch`),
+/// options set on this builder belongs to last layer
+///
+/// `In` is input and `T` is output of last layer
+pub struct BatchLayerBuilder<In, T>(Layer<T>, PhantomData<fn(In)>);
 
-    Layer2Proc: Processor<Layer1Output, Layer2Output> + Send + 'static,
-    Layer2ProcFactory: Fn() -> Layer2Proc + Send + 'static,
+impl<In, T> BatchLayerBuilder<In, T>
+where
+    In: Clone + Send + 'static,
+    T: Clone + Send + 'static
+{
 
-    Layer3Proc: Processor<Layer2Output, Layer3Output> + Send + 'static,
-    Layer3ProcFactory: Fn() -> Layer3Proc + Send + 'static,
+    fn opts(&mut self) -> &mut LayerOptions {
+        &mut self.0.opts
+    }
 
-    Layer4Proc: Processor<Layer3Output, Layer4Output> + Send + 'static,
-    Layer4ProcFactory: Fn() -> Layer4Proc + Send + 'static,
+    fn topology(&mut self) -> &mut TopologyOptions {
+        &mut self.0.topology
+    }
 
-    Layer5Proc: Processor<Layer4Output, Layer5Output> + Send + 'static,
-    Layer5ProcFactory: Fn() -> Layer5Proc + Send + 'static
-{
-   
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
-
-
-    let layer5_proc_channels  = 
-            start_processor(layer5_processor_factory, 
-                            layer5_proc_concurrency, 
-                            layer5_buffer_size, 
-                            None);
-
-    
-    let layer4_proc_channels  = 
-            start_processor(layer4_processor_factory, 
-                            layer4_proc_concurrency, 
-                            layer4_buffer_size, 
-                            Some(Config { proc_channels: layer5_proc_channels, router_type: layer4_router }));
-                            
-        
-
-    let layer3_proc_channels  = 
-            start_processor(layer3_processor_factory, 
-                            layer3_proc_concurrency, 
-                            layer3_buffer_size, 
-                            Some(Config { proc_channels: layer4_proc_channels, router_type: layer3_router }));
-                            
-    
-
-    let layer2_proc_channels  = 
-            start_processor(layer2_processor_factory, 
-                            layer2_proc_concurrency, 
-                            layer2_buffer_size, 
-                            Some(Config { proc_channels: layer3_proc_channels, router_type: layer2_router }));
-    
-
-            
-    let layer1_proc_channels  = 
-            start_processor(layer1_processor_factory, 
-                            layer1_proc_concurrency, 
-                            layer1_buffer_size, 
-                            Some(Config { proc_channels: layer2_proc_channels, router_type: layer1_router  }));
-    
-
-
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   layer1_proc_channels, 
-                   buffer_pool_size,
-                   rx);
-                
-    sx
-}
+    layer_options!("batcher", "batches", "handle_batch");
+    router_option!();
+    batch_options!(In);
+    failure_options!(In);
+    topology_options!();
+
+    /// append next processor layer
+    pub fn then<Output, Proc, F>(self, processor_factory: F) -> TopologyBuilder<T, Output>
+    where
+        Output : Clone + Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : Processor<T, Output> + Send + 'static
+    {
+        TopologyBuilder(self.0.then(processor_factory), PhantomData)
+    }
 
+    /// append another batcher layer in middle of topology
+    pub fn then_batch<Output, Proc, F>(self, batcher_factory: F) -> BatchLayerBuilder<T, Output>
+    where
+        Output : Clone + Send + 'static,
+        F      : Fn() -> Proc + Send + Sync + 'static,
+        Proc   : BatchTransformer<T, Output> + Send + 'static
+    {
+        BatchLayerBuilder(self.0.then_batch(batcher_factory), PhantomData)
+    }
 
+    /// append batcher as latest layer
+    pub fn batcher<Proc, F>(self, batcher_factory: F) -> BatcherBuilder<T>
+    where
+        F    : Fn() -> Proc + Send + Sync + 'static,
+        Proc : BatchProcessor<T> + Send + 'static
+    {
+        TopologyBuilder::<In, T>(self.0, PhantomData).batcher(batcher_factory)
+    }
 
-// -----------------------------------------------
+    /// create and syncing components,
+    /// returns handle for graceful shutdown
+    pub fn start(self) -> TopologyHandle {
+        TopologyBuilder::<In, T>(self.0, PhantomData).start()
+    }
 
+}
 
 
 
 
+/// start batcher layer, returns it as next layer of upstream
+type BatcherStarter<T> = Box<dyn FnOnce(LayerOptions, &mut Stages) -> Next<T> + Send>;
 
-/// producer -> processor -> batcher
-/// 
-pub fn run_topology_1_with_batcher<Prod, ProdFactory, Input, 
-                                   Output, Proc, ProcFactory, 
-                                   Batcher, BatcherFactory>
 
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     processor_factory: ProcFactory,
-     proc_concurrency: i32,
-     proc_router: RouterType,
-     proc_buffer_size: usize,    
+/// Topology which last layer is batcher, cannot have next stage
+pub struct BatcherBuilder<T> {
+    opts: LayerOptions,
+    upstream: Layer<T>,
+    starter: BatcherStarter<T>
+}
 
-     batcher_factory: BatcherFactory,
-     batcher_concurrency: i32,
-     batcher_buffer_size: usize,
-     batcher_batch_size: usize,
-     batcher_batch_timeout: Duration,
+impl<T> BatcherBuilder<T>
+where
+    T: Clone + Send + 'static
+{
 
-    ) -> oneshot::Sender<()>
+    fn opts(&mut self) -> &mut LayerOptions {
+        &mut self.opts
+    }
 
-where
-    Prod: Producer<Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
+    fn topology(&mut self) -> &mut TopologyOptions {
+        &mut self.upstream.topology
+    }
 
-    Input: Clone + Send + 'static,
-    Output: Clone + Send + 'static,
+    layer_options!("batcher", "batches", "handle_batch");
+    batch_options!(T);
+    failure_options!(T);
+    topology_options!();
+
+    /// replace factory of batcher by a mock (feature `testing`)
+    #[cfg(feature = "testing")]
+    pub fn mock_batcher<Proc, F>(mut self, factory: F) -> Self
+    where
+        F    : Fn() -> Proc + Send + Sync + 'static,
+        Proc : BatchProcessor<T> + Send + 'static
+    {
+        let index = self.upstream.index + 1;
+        self.topology().mocks.batcher(index, factory);
+        self
+    }
 
-    Proc: Processor<Input, Output> + Send + 'static,
-    ProcFactory: Fn() -> Proc + Send + 'static,
+    /// create and syncing components,
+    /// returns handle for graceful shutdown
+    pub fn start(mut self) -> TopologyHandle {
+        let mut stages = Stages {
+            topology: std::mem::take(&mut self.upstream.topology),
+            ..Default::default()
+        };
 
-    Batcher: BatchProcessor<Output> + Send + 'static,
-    BatcherFactory: Fn() -> Batcher + Send + 'static
+        self.opts.ordered = matches!(self.upstream.opts.router, RouterType::Partition);
+        let batcher = (self.starter)(self.opts, &mut stages);
+        self.upstream.start(stages, batcher)
+    }
 
-{
-    
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
-
-
-    let batcher_channels = 
-            start_batch_processor(batcher_factory, 
-                                  batcher_concurrency, 
-                                  batcher_buffer_size, 
-                                  batcher_batch_size, 
-                                  batcher_batch_timeout);
-    
-
-    let proc_channels  = 
-            start_processor(processor_factory, 
-                            proc_concurrency, 
-                            proc_buffer_size, 
-                            Some(Config{ proc_channels: batcher_channels, router_type: proc_router}));
-    
-
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   proc_channels, 
-                   buffer_pool_size, 
-                   rx);
-
-    sx
 }
 
 
 
 
 
-/// producer -> processor -> processor -> batcher
-/// 
-pub fn run_topology_2_with_batcher<Prod, ProdFactory, 
-                      Layer1Input, Layer1Output, Layer1Proc, Layer1ProcFactory,
-                      Layer2Output, Layer2Proc, Layer2ProcFactory,
-                      Batcher, BatcherFactory>
+// ------------------------------------------------------------
 
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     layer1_processor_factory: Layer1ProcFactory,
-     layer1_proc_concurrency: i32,
-     layer1_router: RouterType,
-     layer1_buffer_size: usize,
-    
-     layer2_processor_factory: Layer2ProcFactory,
-     layer2_proc_concurrency: i32,
-     layer2_router: RouterType,
-     layer2_buffer_size: usize,
 
-     batcher_factory: BatcherFactory,
-     batcher_concurrency: i32,
-     batcher_buffer_size: usize,
-     batcher_batch_size: usize,
-     batcher_batch_timeout: Duration
-    
 
-    ) -> oneshot::Sender<()>
 
+fn start_producer<T, Prod, F> (producer_factory: F,
+                               mut opts: LayerOptions,
+                               proc_channels: Channels<T>,
+                               stages: &mut Stages)
 where
-    Prod: Producer<Layer1Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
-    Layer1Input: Clone + Send + 'static,
+    T: Clone + Send + 'static,
+    F: Fn() -> Prod + Send + 'static,
+    Prod: Producer<T> + Send + 'static
+{
+    if opts.concurrency == 0 {
+        opts.concurrency = CONCURRENCY;
+    }
 
-    Layer1Output: Clone + Send + 'static,
+    if opts.buffer_size == 0 {
+        opts.buffer_size = BUFFER_POOL_SIZE;
+    }
+
+    if proc_channels.is_empty() {
+        panic!("==> Producer must at-least have 1_Processor_layer")
+    }
 
-    Layer2Output: Clone + Send + 'static,
 
-    Layer1Proc: Processor<Layer1Input, Layer1Output> + Send + 'static,
-    Layer1ProcFactory: Fn() -> Layer1Proc + Send + 'static,
+    for elem in 0..opts.concurrency {
 
-    Layer2Proc: Processor<Layer1Output, Layer2Output> + Send + 'static,
-    Layer2ProcFactory: Fn() -> Layer2Proc + Send + 'static,
+        let (sx, rx) = oneshot::channel();
 
-    Batcher: BatchProcessor<Layer2Output> + Send + 'static,
-    BatcherFactory: Fn() -> Batcher + Send + 'static
-{
-    
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
-
-
-    let batcher_channels = 
-            start_batch_processor(batcher_factory, 
-                                  batcher_concurrency, 
-                                  batcher_buffer_size, 
-                                  batcher_batch_size, 
-                                  batcher_batch_timeout); 
-
-
-    let layer2_proc_channels  = 
-            start_processor(layer2_processor_factory, 
-                            layer2_proc_concurrency, 
-                            layer2_buffer_size, 
-                            Some(Config { proc_channels: batcher_channels, router_type: layer2_router }));
-    
-
-            
-    let layer1_proc_channels  = 
-            start_processor(layer1_processor_factory, 
-                            layer1_proc_concurrency, 
-                            layer1_buffer_size, 
-                            Some(Config { proc_channels: layer2_proc_channels, router_type: layer1_router  }));
-    
-
-
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   layer1_proc_channels, 
-                   buffer_pool_size,
-                   rx);
-
-    sx
+        let id = StageId { layer: 0, kind: StageKind::Producer, instance: elem };
+        let watch = Watch::new(opts.timeouts, stages.topology.telemetry.clone());
+
+        let dispatcher = Dispatcher::new(proc_channels.clone(), opts.router)
+            .unwrap()
+            .with_telemetry(id, stages.topology.telemetry.clone());
+
+        let handle = 
+            producer::Context::new(id,
+                                   dispatcher,
+                                   producer_factory(),
+                                   opts.buffer_size,
+                                   rx)
+                .expect("==> Producer dispatcher cannot be Partition mode")
+                .with_watch(watch.clone())
+                .run();
+
+        stages.producers_shutdown.push(sx);
+        stages.tasks.push(StageTask {
+            id,
+            handle,
+            queue: None,
+            watch
+        });
+    }
 }
 
 
 
-/// producer -> processor -> processor -> processor -> batcher
-/// 
-pub fn run_topology_3_with_batcher<Prod, ProdFactory, Layer1Input, 
-                      Layer1Output, Layer1Proc, Layer1ProcFactory,
-                      Layer2Output, Layer2Proc, Layer2ProcFactory,
-                      Layer3Output, Layer3Proc, Layer3ProcFactory,
-                      Batcher, BatcherFactory>
-
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     layer1_processor_factory: Layer1ProcFactory,
-     layer1_proc_concurrency: i32,
-     layer1_router: RouterType,
-     layer1_buffer_size: usize,
-    
-     layer2_processor_factory: Layer2ProcFactory,
-     layer2_proc_concurrency: i32,
-     layer2_router: RouterType,
-     layer2_buffer_size: usize,
-    
-     layer3_processor_factory: Layer3ProcFactory,
-     layer3_proc_concurrency: i32,
-     layer3_router: RouterType,
-     layer3_buffer_size: usize,
-
-     batcher_factory: BatcherFactory,
-     batcher_concurrency: i32,
-     batcher_buffer_size: usize,
-     batcher_batch_size: usize,
-     batcher_batch_timeout: Duration
-
-    ) -> oneshot::Sender<()>
 
+fn start_processor<Input, Output, Proc, F> (processor_factory: F,
+                                            layer: usize,
+                                            kind: StageKind,
+                                            mut opts: LayerOptions,
+                                            next: Next<Output>,
+                                            stages: &mut Stages) -> Next<Input>
 where
-    Prod: Producer<Layer1Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
-    Layer1Input: Clone + Send + 'static,
-
-    Layer1Output: Clone + Send + 'static,
+    Input  : Clone + Send + 'static,
+    Output : Clone + Send + 'static,
+    F      : Fn() -> Proc + Send + Sync + 'static,
+    Proc   : Processor<Input, Output> + Send + 'static
+{
+    if opts.concurrency == 0 {
+        opts.concurrency = CONCURRENCY;
+    }
 
-    Layer2Output: Clone + Send + 'static,
+    if opts.buffer_size == 0 {
+        opts.buffer_size = BUFFER_SIZE;
+    }
 
-    Layer3Output: Clone + Send + 'static,
 
-    Layer1Proc: Processor<Layer1Input, Layer1Output> + Send + 'static,
-    Layer1ProcFactory: Fn() -> Layer1Proc + Send + 'static,
 
-    Layer2Proc: Processor<Layer1Output, Layer2Output> + Send + 'static,
-    Layer2ProcFactory: Fn() -> Layer2Proc + Send + 'static,
+    let processor_factory = Arc::new(processor_factory);
+    let supervisor = opts.supervision.map(LayerSupervisor::new);
+    let failure_sink = ErasedSinkStarter::start::<Input>(opts.failure_sink.take(), layer, stages);
 
-    Layer3Proc: Processor<Layer2Output, Layer3Output> + Send + 'static,
-    Layer3ProcFactory: Fn() -> Layer3Proc + Send + 'static,
+    let mut list = IndexMap::with_capacity(opts.concurrency);
 
+    for elem in 0..opts.concurrency {
 
-    Batcher: BatchProcessor<Layer3Output> + Send + 'static,
-    BatcherFactory: Fn() -> Batcher + Send + 'static,
-{
-   
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
-
-
-    let batcher_channels = 
-            start_batch_processor(batcher_factory, 
-                                  batcher_concurrency, 
-                                  batcher_buffer_size, 
-                                  batcher_batch_size, 
-                                  batcher_batch_timeout);
-    
-
-    let layer3_proc_channels  = 
-            start_processor(layer3_processor_factory, 
-                            layer3_proc_concurrency, 
-                            layer3_buffer_size, 
-                            Some(Config { proc_channels: batcher_channels, router_type: layer3_router }));
-            
-
-    let layer2_proc_channels  = 
-            start_processor(layer2_processor_factory, 
-                            layer2_proc_concurrency, 
-                            layer2_buffer_size, 
-                            Some(Config { proc_channels: layer3_proc_channels, router_type: layer2_router }));
-    
-
-            
-    let layer1_proc_channels  = 
-            start_processor(layer1_processor_factory, 
-                            layer1_proc_concurrency, 
-                            layer1_buffer_size, 
-                            Some(Config { proc_channels: layer2_proc_channels, router_type: layer1_router  }));
-    
-
-
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   layer1_proc_channels, 
-                   buffer_pool_size,
-                   rx);
-                
-    sx
-}
+        let id = StageId { layer, kind, instance: elem };
 
+        let (sender, recv) = channel(opts.buffer_size);
+        list.insert(id.name(), sender);
 
+        let recv = StageReceiver::new(recv);
+        let queue = Some(recv.gauge());
 
+        let factory = processor_factory.clone();
+        let next_channels = next.channels.clone();
+        let router = opts.router;
+        let failure_sink = failure_sink.clone();
+        let undeliverable = next.sink.clone();
+        let (retry, ordered) = (opts.retry.clone(), opts.ordered);
+        let watch = Watch::new(opts.timeouts, stages.topology.telemetry.clone());
+        let instance_watch = watch.clone();
 
-/// producer -> processor -> processor -> processor -> processor -> batcher
-/// 
-pub fn run_topology_4_with_batcher<Prod, ProdFactory, Layer1Input, 
-                      Layer1Output, Layer1Proc, Layer1ProcFactory,
-                      Layer2Output, Layer2Proc, Layer2ProcFactory,
-                      Layer3Output, Layer3Proc, Layer3ProcFactory,
-                      Layer4Output, Layer4Proc, Layer4ProcFactory,
-                      Batcher, BatcherFactory>
-
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     layer1_processor_factory: Layer1ProcFactory,
-     layer1_proc_concurrency: i32,
-     layer1_router: RouterType,
-     layer1_buffer_size: usize,
-    
-     layer2_processor_factory: Layer2ProcFactory,
-     layer2_proc_concurrency: i32,
-     layer2_router: RouterType,
-     layer2_buffer_size: usize,
-    
-     layer3_processor_factory: Layer3ProcFactory,
-     layer3_proc_concurrency: i32,
-     layer3_router: RouterType,
-     layer3_buffer_size: usize,
-
-     layer4_processor_factory: Layer4ProcFactory,
-     layer4_proc_concurrency: i32,
-     layer4_router: RouterType,
-     layer4_buffer_size: usize,
-
-     batcher_factory: BatcherFactory,
-     batcher_concurrency: i32,
-     batcher_buffer_size: usize,
-     batcher_batch_size: usize,
-     batcher_batch_timeout: Duration
-
-    ) -> oneshot::Sender<()>
+        let start = move |recv| {
+            let dispatcher = Dispatcher::new(next_channels.clone(), router)
+                .unwrap()
+                .with_telemetry(id, instance_watch.telemetry.clone());
 
-where
-    Prod: Producer<Layer1Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
-    Layer1Input: Clone + Send + 'static,
+            processor::Context::<Input, Output, Proc>::new(id, 
+                                                           recv, 
+                                                           dispatcher, 
+                                                           factory(), 
+                                                           failure_sink.clone(),
+                                                           undeliverable.clone())
+                .with_retry(retry.clone(), ordered)
+                .with_watch(instance_watch.clone())
+                .run()
+        };
 
-    Layer1Output: Clone + Send + 'static,
+        let handle = match supervisor {
+            Some(ref sup) => sup.supervise(elem, recv, start),
+            None => start(recv)
+        };
 
-    Layer2Output: Clone + Send + 'static,
+        stages.tasks.push(StageTask {
+            id,
+            handle,
+            queue,
+            watch
+        });
 
-    Layer3Output: Clone + Send + 'static,
+    }
 
-    Layer4Output: Clone + Send + 'static,
+    Next {
+        channels: list,
+        sink: failure_sink
+    }
+}
 
-    Layer1Proc: Processor<Layer1Input, Layer1Output> + Send + 'static,
-    Layer1ProcFactory: Fn() -> Layer1Proc + Send + 'static,
 
-    Layer2Proc: Processor<Layer1Output, Layer2Output> + Send + 'static,
-    Layer2ProcFactory: Fn() -> Layer2Proc + Send + 'static,
 
-    Layer3Proc: Processor<Layer2Output, Layer3Output> + Send + 'static,
-    Layer3ProcFactory: Fn() -> Layer3Proc + Send + 'static,
 
-    Layer4Proc: Processor<Layer3Output, Layer4Output> + Send + 'static,
-    Layer4ProcFactory: Fn() -> Layer4Proc + Send + 'static,
 
-    Batcher: BatchProcessor<Layer4Output> + Send + 'static,
-    BatcherFactory: Fn() -> Batcher + Send + 'static
+fn start_batcher<Input, Output, Proc, F> (batcher_factory: F,
+                                          layer: usize,
+                                          kind: StageKind,
+                                          mut opts: LayerOptions,
+                                          next: Next<Output>,
+                                          stages: &mut Stages) -> Next<Input>
+where
+    Input  : Clone + Send + 'static,
+    Output : Clone + Send + 'static,
+    F      : Fn() -> Proc + Send + Sync + 'static,
+    Proc   : BatchTransformer<Input, Output> + Send + 'static
 {
-   
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
-
-
-    let batcher_channels = 
-            start_batch_processor(batcher_factory, 
-                                  batcher_concurrency, 
-                                  batcher_buffer_size, 
-                                  batcher_batch_size, 
-                                  batcher_batch_timeout);
-
-
-    let layer4_proc_channels  = 
-            start_processor(layer4_processor_factory, 
-                            layer4_proc_concurrency, 
-                            layer4_buffer_size, 
-                            Some(Config { proc_channels: batcher_channels, router_type: layer4_router }));
-    
-
-    let layer3_proc_channels  = 
-            start_processor(layer3_processor_factory, 
-                            layer3_proc_concurrency, 
-                            layer3_buffer_size, 
-                            Some(Config { proc_channels: layer4_proc_channels, router_type: layer3_router }));
-                            
-    
-
-    let layer2_proc_channels  = 
-            start_processor(layer2_processor_factory, 
-                            layer2_proc_concurrency, 
-                            layer2_buffer_size, 
-                            Some(Config { proc_channels: layer3_proc_channels, router_type: layer2_router }));
-    
-
-            
-    let layer1_proc_channels  = 
-            start_processor(layer1_processor_factory, 
-                            layer1_proc_concurrency, 
-                            layer1_buffer_size, 
-                            Some(Config { proc_channels: layer2_proc_channels, router_type: layer1_router  }));
-    
-
-
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   layer1_proc_channels, 
-                   buffer_pool_size,
-                   rx);
-                
-    sx
-}
 
+    if opts.concurrency == 0 {
+        opts.concurrency = CONCURRENCY;
+    }
 
+    if opts.buffer_size == 0 {
+        opts.buffer_size = BUFFER_SIZE;
+    }
 
+    let mut batch = opts.batch.unwrap_or_default();
 
+    if batch.batch_size == 0 {
+        batch.batch_size = BATCH_SIZE;
+    }
 
-/// producer -> processor -> processor -> processor -> processor
-/// 
-pub fn run_topology_5_with_batcher<Prod, ProdFactory, Layer1Input, 
-                      Layer1Output, Layer1Proc, Layer1ProcFactory,
-                      Layer2Output, Layer2Proc, Layer2ProcFactory,
-                      Layer3Output, Layer3Proc, Layer3ProcFactory,
-                      Layer4Output, Layer4Proc, Layer4ProcFactory,
-                      Layer5Output, Layer5Proc, Layer5ProcFactory,
-                      Batcher, BatcherFactory>
-
-    (producer_factory: ProdFactory,
-     prod_concurrency: i32,
-     router: RouterType,
-     buffer_pool_size: usize,
- 
-     layer1_processor_factory: Layer1ProcFactory,
-     layer1_proc_concurrency: i32,
-     layer1_router: RouterType,
-     layer1_buffer_size: usize,
-    
-     layer2_processor_factory: Layer2ProcFactory,
-     layer2_proc_concurrency: i32,
-     layer2_router: RouterType,
-     layer2_buffer_size: usize,
-    
-     layer3_processor_factory: Layer3ProcFactory,
-     layer3_proc_concurrency: i32,
-     layer3_router: RouterType,
-     layer3_buffer_size: usize,
-
-     layer4_processor_factory: Layer4ProcFactory,
-     layer4_proc_concurrency: i32,
-     layer4_router: RouterType,
-     layer4_buffer_size: usize,
-
-     layer5_processor_factory: Layer5ProcFactory,
-     layer5_proc_concurrency: i32,
-     layer5_router: RouterType,
-     layer5_buffer_size: usize,
-
-     batcher_factory: BatcherFactory,
-     batcher_concurrency: i32,
-     batcher_buffer_size: usize,
-     batcher_batch_size: usize,
-     batcher_batch_timeout: Duration
-
-    ) -> oneshot::Sender<()>
+    if batch.batch_timeout.is_zero() {
+        batch.batch_timeout = BATCH_TIMEOUT;
+    }
 
-where
-    Prod: Producer<Layer1Input> + Send + 'static,
-    ProdFactory: Fn() -> Prod + Send + 'static,
-    Layer1Input: Clone + Send + 'static,
+    let limits = BatchLimits {
+        size: batch.batch_size,
+        timeout: batch.batch_timeout,
+        linger: batch.linger,
+        by_key: batch.by_key,
+        weight: batch.weight.map(|(max, weigher)| (max, weigher.take::<Input>())),
+        oversized: batch.oversized
+    };
+
+
+    let batcher_factory = Arc::new(batcher_factory);
+    let supervisor = opts.supervision.map(LayerSupervisor::new);
+    let failure_sink = ErasedSinkStarter::start::<Input>(opts.failure_sink.take(), layer, stages);
+
+    let mut list = IndexMap::with_capacity(opts.concurrency);
+
+    for elem in 0..opts.concurrency {
+
+        let id = StageId { layer, kind, instance: elem };
+
+        let (sender, recv) = channel(opts.buffer_size);
+        list.insert(id.name(), sender);
+
+        let recv = StageReceiver::new(recv);
+        let queue = Some(recv.gauge());
+
+        let factory = batcher_factory.clone();
+        let next_channels = next.channels.clone();
+        let router = opts.router;
+        let failure_sink = failure_sink.clone();
+        let undeliverable = next.sink.clone();
+        let limits = limits.clone();
+        let adaptive = batch.adaptive;
+        let (retry, ordered) = (opts.retry.clone(), opts.ordered);
+        let watch = Watch::new(opts.timeouts, stages.topology.telemetry.clone());
+        let instance_watch = watch.clone();
+
+        let start = move |recv| {
+            let dispatcher = Dispatcher::new(next_channels.clone(), router)
+                .unwrap()
+                .with_telemetry(id, instance_watch.telemetry.clone());
+
+            batcher::Context::<Input, Output, Proc>::new(id,
+                                                         recv,
+                                                         dispatcher,
+                                                         factory(),
+                                                         Batches::new(limits.clone()),
+                                                         failure_sink.clone(),
+                                                         undeliverable.clone())
+                .with_retry(retry.clone(), ordered)
+                .with_adaptive(adaptive)
+                .with_watch(instance_watch.clone())
+                .run()
+        };
 
-    Layer1Output: Clone + Send + 'static,
+        let handle = match supervisor {
+            Some(ref sup) => sup.supervise(elem, recv, start),
+            None => start(recv)
+        };
 
-    Layer2Output: Clone + Send + 'static,
+        stages.tasks.push(StageTask {
+            id,
+            handle,
+            queue,
+            watch
+        });
 
-    Layer3Output: Clone + Send + 'static,
+    }
 
-    Layer4Output: Clone + Send + 'static,
+    Next {
+        channels: list,
+        sink: failure_sink
+    }
+}
 
-    Layer5Output: Clone + Send + 'static,
 
-    Layer1Proc: Processor<Layer1Input, Layer1Output> + Send + 'static,
-    Layer1ProcFactory: Fn() -> Layer1Proc + Send + 'static,
 
-    Layer2Proc: Processor<Layer1Output, Layer2Output> + Send + 'static,
-    Layer2ProcFactory: Fn() -> Layer2Proc + Send + 'static,
 
-    Layer3Proc: Processor<Layer2Output, Layer3Output> + Send + 'static,
-    Layer3ProcFactory: Fn() -> Layer3Proc + Send + 'static,
+fn sink_starter<T, S>(sink: S) -> ErasedSinkStarter
+where
+    T: Send + 'static,
+    S: FailureSink<T> + 'static
+{
+    let sink = SharedSink::new(sink);
+    let starter: SinkStarter<T> = Box::new(move |_, _| sink);
 
-    Layer4Proc: Processor<Layer3Output, Layer4Output> + Send + 'static,
-    Layer4ProcFactory: Fn() -> Layer4Proc + Send + 'static,
+    ErasedSinkStarter::new(starter)
+}
 
-    Layer5Proc: Processor<Layer4Output, Layer5Output> + Send + 'static,
-    Layer5ProcFactory: Fn() -> Layer5Proc + Send + 'static,
 
-    Batcher: BatchProcessor<Layer5Output> + Send +'static,
-    BatcherFactory: Fn() -> Batcher + Send + 'static
+/// dead letter stage run at same layer index, 
+/// after all instances of layer in shutdown order
+fn dead_letter_starter<T, Proc, F>(processor_factory: F) -> ErasedSinkStarter
+where
+    T    : Clone + Send + 'static,
+    F    : Fn() -> Proc + Send + Sync + 'static,
+    Proc : Processor<DeadLetter<T>, ()> + Send + 'static
 {
-   
-    // Shutdown channel
-    let (sx, rx) = oneshot::channel::<()>(); 
-
-
-    let batcher_channels = 
-            start_batch_processor(batcher_factory, 
-                                  batcher_concurrency, 
-                                  batcher_buffer_size, 
-                                  batcher_batch_size, 
-                                  batcher_batch_timeout);
-
-
-    let layer5_proc_channels  = 
-            start_processor(layer5_processor_factory, 
-                            layer5_proc_concurrency, 
-                            layer5_buffer_size, 
-                            Some(Config { proc_channels: batcher_channels, router_type: layer5_router }));
-
-    
-    let layer4_proc_channels  = 
-            start_processor(layer4_processor_factory, 
-                            layer4_proc_concurrency, 
-                            layer4_buffer_size, 
-                            Some(Config { proc_channels: layer5_proc_channels, router_type: layer4_router }));
-                            
-        
-
-    let layer3_proc_channels  = 
-            start_processor(layer3_processor_factory, 
-                            layer3_proc_concurrency, 
-                            layer3_buffer_size, 
-                            Some(Config { proc_channels: layer4_proc_channels, router_type: layer3_router }));
-                            
-    
-
-    let layer2_proc_channels  = 
-            start_processor(layer2_processor_factory, 
-                            layer2_proc_concurrency, 
-                            layer2_buffer_size, 
-                            Some(Config { proc_channels: layer3_proc_channels, router_type: layer2_router }));
-    
-
-            
-    let layer1_proc_channels  = 
-            start_processor(layer1_processor_factory, 
-                            layer1_proc_concurrency, 
-                            layer1_buffer_size, 
-                            Some(Config { proc_channels: layer2_proc_channels, router_type: layer1_router  }));
-    
-
-
-    start_producer(producer_factory, 
-                   prod_concurrency, 
-                   router, 
-                   layer1_proc_channels, 
-                   buffer_pool_size,
-                   rx);
-                
-    sx
+    let starter: SinkStarter<T> = Box::new(move |layer, stages| {
+
+        let dlq = start_processor(processor_factory, 
+                                  layer, 
+                                  StageKind::DeadLetter, 
+                                  LayerOptions::default(), 
+                                  Next::none(), 
+                                  stages);
+
+        SharedSink::DeadLetter(Arc::new(DeadLetterSink::new(dlq.channels.into_values().collect())))
+    });
+
+    ErasedSinkStarter::new(starter)
 }
 
 
+fn dead_letter_batcher_starter<T, Proc, F>(batcher_factory: F) -> ErasedSinkStarter
+where
+    T    : Clone + Send + 'static,
+    F    : Fn() -> Proc + Send + Sync + 'static,
+    Proc : BatchProcessor<DeadLetter<T>> + Send + 'static
+{
+    let starter: SinkStarter<T> = Box::new(move |layer, stages| {
+
+        let dlq = start_batcher(move || Terminal(batcher_factory()), 
+                                layer, 
+                                StageKind::DeadLetter, 
+                                LayerOptions::batcher(), 
+                                Next::none(), 
+                                stages);
+
+        SharedSink::DeadLetter(Arc::new(DeadLetterSink::new(dlq.channels.into_values().collect())))
+    });
+
+    ErasedSinkStarter::new(starter)
+}
@@ -0,0 +1,140 @@
+use std::future::Future;
+
+use crate::{message::Message, shutdown_manager::StageId};
+
+
+
+/// error event, by `tracing` if feature enabled, else stderr
+macro_rules! error {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::error!($($arg)+);
+
+        #[cfg(not(feature = "tracing"))]
+        eprintln!("==> {}", format_args!($($arg)+));
+    }};
+}
+
+/// warn event, by `tracing` if feature enabled, else stderr
+#[cfg(any(feature = "kafka", feature = "pulsar"))]
+macro_rules! warning {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        tracing::warn!($($arg)+);
+
+        #[cfg(not(feature = "tracing"))]
+        eprintln!("==> {}", format_args!($($arg)+));
+    }};
+}
+
+pub(crate) use error;
+#[cfg(any(feature = "kafka", feature = "pulsar"))]
+pub(crate) use warning;
+
+
+
+
+/// span of a stage instance task (layer, stage name, instance),
+/// a restarted instance get a new span
+#[cfg(feature = "tracing")]
+pub(crate) fn stage<F: Future>(id: StageId, fut: F) -> tracing::instrument::Instrumented<F> {
+    use tracing::Instrument;
+
+    fut.instrument(tracing::info_span!("stage",
+                                       layer = id.layer,
+                                       kind = ?id.kind,
+                                       stage = %id.name(),
+                                       instance = id.instance))
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn stage<F: Future>(_id: StageId, fut: F) -> F {
+    fut
+}
+
+
+
+
+/// span of a message (or batch) inside a stage,
+/// message carry span of last stage handled it to next layer
+///
+/// zero sized if `tracing` feature not enabled
+#[derive(Clone, Default)]
+pub(crate) struct MessageSpan {
+    #[cfg(feature = "tracing")]
+    pub span: Option<tracing::Span>
+}
+
+
+#[cfg(feature = "tracing")]
+impl MessageSpan {
+
+    /// span of message inside stage `id`, child of span of message on previous layer,
+    /// or a new trace (follows from stage span) if message not have any
+    pub fn child(&self, id: StageId, attempt: usize) -> Self {
+        let parent = self.span.as_ref().and_then(|s| s.id());
+        let root = parent.is_none();
+
+        let span = tracing::info_span!(parent: parent,
+                                       "message",
+                                       layer = id.layer,
+                                       kind = ?id.kind,
+                                       stage = %id.name(),
+                                       instance = id.instance,
+                                       attempt);
+        if root {
+            span.follows_from(tracing::Span::current());
+        }
+
+        MessageSpan { span: Some(span) }
+    }
+
+
+    /// new trace for a batch, linked to span of its messages
+    pub fn batch<T>(id: StageId, batch: &[Message<T>], attempt: usize) -> Self {
+        let span = tracing::info_span!(parent: None,
+                                       "batch",
+                                       layer = id.layer,
+                                       kind = ?id.kind,
+                                       stage = %id.name(),
+                                       instance = id.instance,
+                                       size = batch.len(),
+                                       attempt);
+
+        span.follows_from(tracing::Span::current());
+        for msg in batch {
+            if let Some(s) = &msg.span.span {
+                span.follows_from(s);
+            }
+        }
+
+        MessageSpan { span: Some(span) }
+    }
+
+
+    pub fn instrument<F: Future>(&self, fut: F) -> tracing::instrument::Instrumented<F> {
+        use tracing::Instrument;
+
+        fut.instrument(self.span.clone().unwrap_or_else(tracing::Span::none))
+    }
+}
+
+
+#[cfg(not(feature = "tracing"))]
+impl MessageSpan {
+
+    #[inline]
+    pub fn child(&self, _id: StageId, _attempt: usize) -> Self {
+        MessageSpan {}
+    }
+
+    #[inline]
+    pub fn batch<T>(_id: StageId, _batch: &[Message<T>], _attempt: usize) -> Self {
+        MessageSpan {}
+    }
+
+    #[inline]
+    pub fn instrument<F: Future>(&self, fut: F) -> F {
+        fut
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    future::Future,
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+    time::Duration
+};
+
+use tokio::time::Instant;
+
+use crate::{metrics::StageMetrics, shutdown_manager::StageId, telemetry::{Telemetry, TelemetryEvent}};
+
+
+
+/// Timeouts of a layer, not set means wait forever
+///
+///   * `handler` - `handle_message` or `handle_batch`, expired handler count as
+///     `FailReason::TimedOut` (retried by `RetryPolicy` like other failures),
+///     not applied to producers (rejected by `ProducerBuilder::timeouts`), cancelling
+///     `fill_buffer` would lose messages already pulled from source
+///   * `init` & `terminate` - expired instance crash (restarted if supervised,
+///     else reported as `StageStatus::Panicked`)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    pub handler: Option<Duration>,
+    pub init: Option<Duration>,
+    pub terminate: Option<Duration>
+}
+
+impl Timeouts {
+
+    pub fn handler(mut self, timeout: Duration) -> Self {
+        self.handler = Some(timeout);
+        self
+    }
+
+    pub fn init(mut self, timeout: Duration) -> Self {
+        self.init = Some(timeout);
+        self
+    }
+
+    pub fn terminate(mut self, timeout: Duration) -> Self {
+        self.terminate = Some(timeout);
+        self
+    }
+}
+
+
+
+
+/// stage instance made no progress, reported by watchdog
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stall {
+    pub id: StageId,
+
+    /// time instance is inside current call (handler, init or terminate)
+    /// or waiting to be ready for it
+    pub busy_for: Duration
+}
+
+
+
+
+const IDLE: u64 = u64::MAX;
+
+
+/// tracks an instance is inside a call since when,
+/// shared between instance (and its restarts) and topology handle
+#[derive(Clone)]
+pub(crate) struct Progress {
+    inner: Arc<ProgressInner>
+}
+
+struct ProgressInner {
+    base: Instant,
+
+    /// nanos from base, `IDLE` if not inside a call
+    busy_since: AtomicU64
+}
+
+impl Progress {
+
+    pub fn new() -> Self {
+        Progress {
+            inner: Arc::new(ProgressInner {
+                base: Instant::now(),
+                busy_since: AtomicU64::new(IDLE)
+            })
+        }
+    }
+
+    /// time inside current call, `None` if idle
+    pub fn busy_for(&self) -> Option<Duration> {
+        match self.inner.busy_since.load(Ordering::Acquire) {
+            IDLE => None,
+            since => {
+                let elapsed = self.inner.base.elapsed();
+                Some(elapsed.saturating_sub(Duration::from_nanos(since)))
+            }
+        }
+    }
+
+    /// identify current call, so a stall reported once
+    pub fn busy_since(&self) -> Option<u64> {
+        match self.inner.busy_since.load(Ordering::Acquire) {
+            IDLE => None,
+            since => Some(since)
+        }
+    }
+
+    fn enter(&self) -> BusyGuard<'_> {
+        let since = self.inner.base.elapsed().as_nanos().min((IDLE - 1) as u128) as u64;
+        self.inner.busy_since.store(since, Ordering::Release);
+        BusyGuard(self)
+    }
+}
+
+
+/// mark instance idle when call finished, panicked or cancelled
+struct BusyGuard<'a>(&'a Progress);
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        self.0.inner.busy_since.store(IDLE, Ordering::Release);
+    }
+}
+
+
+
+
+/// timeouts, progress, metrics & telemetry of a stage instance
+#[derive(Clone)]
+pub(crate) struct Watch {
+    pub timeouts: Timeouts,
+    pub progress: Progress,
+    pub metrics: StageMetrics,
+    pub telemetry: Telemetry
+}
+
+impl Watch {
+
+    pub fn new(timeouts: Timeouts, telemetry: Telemetry) -> Self {
+        Watch {
+            timeouts,
+            progress: Progress::new(),
+            metrics: StageMetrics::new(),
+            telemetry
+        }
+    }
+
+
+    /// handler call, `Err(timeout)` if not finished within `Timeouts::handler`,
+    /// duration recorded as handler latency
+    pub async fn handler<F>(&self, fut: F) -> Result<F::Output, Duration>
+    where
+        F: Future
+    {
+        let _busy = self.progress.enter();
+
+        let start = Instant::now();
+        let res = run(self.timeouts.handler, fut).await;
+        self.metrics.latency(start.elapsed());
+
+        res
+    }
+
+    /// wait until instance ready for next handler call (`ready`),
+    /// not limited by `Timeouts::handler` and not recorded as handler latency
+    pub async fn ready<F>(&self, fut: F)
+    where
+        F: Future<Output = ()>
+    {
+        let _busy = self.progress.enter();
+        fut.await
+    }
+
+    pub async fn init<F>(&self, id: StageId, fut: F)
+    where
+        F: Future<Output = ()>
+    {
+        let _busy = self.progress.enter();
+
+        let start = Instant::now();
+        if let Err(timeout) = run(self.timeouts.init, fut).await {
+            panic!("==> {} init timed out after {:?}", id, timeout)
+        }
+
+        let duration = start.elapsed();
+        self.telemetry.emit(|| TelemetryEvent::InitDone { id, duration });
+    }
+
+    pub async fn terminate<F>(&self, id: StageId, fut: F)
+    where
+        F: Future<Output = ()>
+    {
+        let _busy = self.progress.enter();
+
+        let start = Instant::now();
+        if let Err(timeout) = run(self.timeouts.terminate, fut).await {
+            panic!("==> {} terminate timed out after {:?}", id, timeout)
+        }
+
+        let duration = start.elapsed();
+        self.telemetry.emit(|| TelemetryEvent::TerminateDone { id, duration });
+    }
+}
+
+impl Default for Watch {
+    fn default() -> Self {
+        Watch::new(Timeouts::default(), Telemetry::default())
+    }
+}
+
+
+async fn run<F>(timeout: Option<Duration>, fut: F) -> Result<F::Output, Duration>
+where
+    F: Future
+{
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut).await.map_err(|_| timeout),
+        None => Ok(fut.await)
+    }
+}
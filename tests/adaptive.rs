@@ -0,0 +1,136 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio_sky::{async_trait, AdaptiveBatch, BatchError, BatchKey, BatchProcessor, Message, Processor, ProcResult, Topology, TopologyHandle};
+use tokio_sky::testing::{self, TestSource};
+
+
+const INITIAL_SIZE: usize = 4;
+const INCREASE: usize = 2;
+const PER_MESSAGE: Duration = Duration::from_millis(10);
+const TARGET: Duration = Duration::from_millis(100);
+const BATCH_TIMEOUT: Duration = Duration::from_secs(5);
+const STEP: Duration = Duration::from_millis(10);
+
+
+type Sizes = Arc<Mutex<Vec<usize>>>;
+
+
+
+fn adaptive() -> AdaptiveBatch {
+    AdaptiveBatch::latency(TARGET)
+        .bounds(2, 20)
+        .increase(INCREASE)
+        .decrease(0.5)
+}
+
+
+fn target_size(topology: &TopologyHandle) -> usize {
+    topology.metrics().layer(2).next().unwrap().batch.clone().unwrap().target_size
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn size_grows_on_target_and_halves_when_missed() {
+    let mut source = TestSource::new();
+    let sizes = Sizes::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher({
+                        let sizes = sizes.clone();
+                        move || Slow { sizes: sizes.clone(), fail: false }
+                    })
+                    .concurrency(1)
+                    .batch_size(INITIAL_SIZE)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .adaptive_batch(adaptive())
+                    .start();
+
+    // 4, 6, 8 and 10 messages handled within target, each step up by `increase`
+    source.push_batch(0..28);
+    testing::advance_until_flushed(&topology, STEP).await;
+
+    assert_eq!(*sizes.lock().unwrap(), vec![4, 6, 8, 10]);
+    assert_eq!(target_size(&topology), 12);
+
+    // 12 messages take longer than target
+    source.push_batch(0..12);
+    testing::advance_until_flushed(&topology, STEP).await;
+
+    assert_eq!(sizes.lock().unwrap().last(), Some(&12));
+    assert_eq!(target_size(&topology), 6);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn failed_batches_shrink_size_to_min_bound() {
+    let mut source = TestSource::new();
+    let sizes = Sizes::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher({
+                        let sizes = sizes.clone();
+                        move || Slow { sizes: sizes.clone(), fail: true }
+                    })
+                    .concurrency(1)
+                    .batch_size(INITIAL_SIZE * 2)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .adaptive_batch(adaptive())
+                    .start();
+
+    // 8, 4 and 2 messages failed, never below min bound
+    source.push_batch(0..16);
+    testing::advance_until_flushed(&topology, STEP).await;
+
+    assert_eq!(*sizes.lock().unwrap(), vec![8, 4, 2, 2]);
+    assert_eq!(target_size(&topology), 2);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+struct Forward;
+
+#[async_trait]
+impl Processor<u64, u64> for Forward {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) -> ProcResult<u64> {
+        ProcResult::Dispatch(msg)
+    }
+}
+
+
+/// take `PER_MESSAGE` per message of batch, record size of each batch
+struct Slow {
+    sizes: Sizes,
+    fail: bool
+}
+
+#[async_trait]
+impl BatchProcessor<u64> for Slow {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, batch: Vec<Message<u64>>) -> Result<(), BatchError<u64>> {
+        self.sizes.lock().unwrap().push(batch.len());
+        tokio::time::sleep(PER_MESSAGE * batch.len() as u32).await;
+
+        if self.fail {
+            return Err(BatchError::Fail("sink down".to_owned()))
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio_sky::{async_trait, BatchError, BatchKey, BatchResult, BatchTransformer, FailReason, Message, Processor, ProcResult, Topology};
+use tokio_sky::testing::{self, Outcome, TestSource};
+
+
+const BATCH_SIZE: usize = 4;
+const DELAY: Duration = Duration::from_secs(2);
+const STEP: Duration = Duration::from_millis(100);
+
+
+type Received = Arc<Mutex<Vec<u64>>>;
+
+
+
+#[tokio::test(start_paused = true)]
+async fn batch_output_acks_whole_batch_once_finished() {
+    let mut source = TestSource::new();
+    let received = Received::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then_batch(|| Sum)
+                    .batch_size(BATCH_SIZE)
+                    .then({
+                        let received = received.clone();
+                        move || Collect { received: received.clone(), delay: DELAY }
+                    })
+                    .concurrency(1)
+                    .start();
+
+    let batch = source.push_batch(0..BATCH_SIZE as u64);
+
+    // aggregate still handled by next layer
+    tokio::time::sleep(DELAY / 2).await;
+    assert!(batch.iter().all(|m| source.try_outcome(*m).is_none()));
+
+    testing::advance_until_flushed(&topology, STEP).await;
+
+    for msg in batch {
+        assert_eq!(source.try_outcome(msg), Some(Outcome::Acked));
+    }
+    assert_eq!(*received.lock().unwrap(), vec![6]);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn each_result_settles_its_own_message() {
+    let mut source = TestSource::new();
+    let received = Received::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then_batch(|| PerMessage)
+                    .batch_size(BATCH_SIZE)
+                    .then({
+                        let received = received.clone();
+                        move || Collect { received: received.clone(), delay: Duration::ZERO }
+                    })
+                    .concurrency(1)
+                    .start();
+
+    let msgs = source.push_batch(0..BATCH_SIZE as u64);
+
+    testing::advance_until_flushed(&topology, STEP).await;
+
+    let outcomes = msgs.into_iter()
+        .map(|m| source.try_outcome(m))
+        .collect::<Vec<_>>();
+
+    assert_eq!(outcomes, vec![
+        Some(Outcome::Acked),
+        Some(Outcome::Acked),
+        Some(Outcome::Acked),
+        Some(Outcome::Failed(FailReason::Error("odd".to_owned())))
+    ]);
+
+    // outputs dispatched in order of batch
+    assert_eq!(*received.lock().unwrap(), vec![0, 20, 21]);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn each_with_wrong_number_of_results_fails_batch() {
+    let mut source = TestSource::new();
+    let received = Received::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then_batch(|| Short)
+                    .batch_size(BATCH_SIZE)
+                    .then({
+                        let received = received.clone();
+                        move || Collect { received: received.clone(), delay: Duration::ZERO }
+                    })
+                    .start();
+
+    let msgs = source.push_batch(0..BATCH_SIZE as u64);
+
+    testing::advance_until_flushed(&topology, STEP).await;
+
+    for msg in msgs {
+        assert!(matches!(source.try_outcome(msg), Some(Outcome::Failed(_))));
+    }
+    assert!(received.lock().unwrap().is_empty());
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+/// one aggregate output per batch
+struct Sum;
+
+#[async_trait]
+impl BatchTransformer<u64, u64> for Sum {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, batch: Vec<Message<u64>>) -> Result<BatchResult<u64>, BatchError<u64>> {
+        let sum = batch.iter().map(|m| m.data).sum();
+        Ok(BatchResult::Batch(Message::new(sum)))
+    }
+}
+
+
+/// `0` dispatched, `1` finished, `2` split in two, `3` failed
+struct PerMessage;
+
+#[async_trait]
+impl BatchTransformer<u64, u64> for PerMessage {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, batch: Vec<Message<u64>>) -> Result<BatchResult<u64>, BatchError<u64>> {
+        let results = batch.into_iter()
+            .map(|m| match m.data {
+                0 => ProcResult::Dispatch(m),
+                1 => ProcResult::Continue,
+                2 => ProcResult::DispatchMany(m.split([20, 21])),
+                _ => ProcResult::Fail("odd".to_owned())
+            })
+            .collect();
+
+        Ok(BatchResult::Each(results))
+    }
+}
+
+
+/// one result less than messages of batch
+struct Short;
+
+#[async_trait]
+impl BatchTransformer<u64, u64> for Short {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, batch: Vec<Message<u64>>) -> Result<BatchResult<u64>, BatchError<u64>> {
+        let results = batch.into_iter()
+            .skip(1)
+            .map(ProcResult::Dispatch)
+            .collect();
+
+        Ok(BatchResult::Each(results))
+    }
+}
+
+
+struct Collect {
+    received: Received,
+    delay: Duration
+}
+
+#[async_trait]
+impl Processor<u64, ()> for Collect {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) -> ProcResult<()> {
+        tokio::time::sleep(self.delay).await;
+        self.received.lock().unwrap().push(msg.data);
+        ProcResult::Continue
+    }
+}
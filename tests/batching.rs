@@ -0,0 +1,351 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio_sky::{async_trait, BatchError, BatchKey, BatchProcessor, FailReason, Message, Oversized, Processor, ProcResult, RetryPolicy, RouterType, Topology};
+use tokio_sky::testing::{self, Outcome, TestSource};
+
+
+const BATCH_SIZE: usize = 3;
+const BATCH_TIMEOUT: Duration = Duration::from_secs(5);
+const BACKOFF: Duration = Duration::from_secs(1);
+const STEP: Duration = Duration::from_millis(100);
+const MAX_WEIGHT: usize = 10;
+
+
+type Batches = Arc<Mutex<Vec<(Option<BatchKey>, Vec<u64>)>>>;
+
+
+
+#[tokio::test(start_paused = true)]
+async fn full_batch_flushed_by_size_without_advancing_clock() {
+    let mut source = TestSource::new();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher(Store::default)
+                    .batch_size(BATCH_SIZE)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .start();
+
+    let full = source.push_batch(0..BATCH_SIZE as u64);
+
+    let elapsed = testing::advance_until_flushed(&topology, STEP).await;
+    assert_eq!(elapsed, Duration::ZERO);
+
+    for msg in full {
+        assert_eq!(source.try_outcome(msg), Some(Outcome::Acked));
+    }
+
+    let batch = topology.metrics().layer(2).next().unwrap().batch.clone().unwrap();
+    assert_eq!(batch.flushed_by_size, 1);
+    assert_eq!(batch.flushed_by_timeout, 0);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn partial_batch_flushed_by_timeout() {
+    let mut source = TestSource::new();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher(Store::default)
+                    .batch_size(BATCH_SIZE)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .start();
+
+    let partial = source.push_batch(0..(BATCH_SIZE - 1) as u64);
+
+    let elapsed = testing::advance_until_flushed(&topology, STEP).await;
+    assert_eq!(elapsed, BATCH_TIMEOUT);
+
+    for msg in partial {
+        assert_eq!(source.try_outcome(msg), Some(Outcome::Acked));
+    }
+
+    let batch = topology.metrics().layer(2).next().unwrap().batch.clone().unwrap();
+    assert_eq!(batch.flushed_by_size, 0);
+    assert_eq!(batch.flushed_by_timeout, 1);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn ordered_retry_not_settled_until_finished() {
+    let mut source = TestSource::new();
+
+    // input of batcher partitioned, so failed batch retried inside instance
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .router(RouterType::Partition)
+                    .batcher(|| Store { fail_first: 2, ..Default::default() })
+                    .batch_size(1)
+                    .retry(RetryPolicy::new(5).backoff(BACKOFF, BACKOFF).multiplier(1.0).jitter(0.0))
+                    .start();
+
+    let msg = source.push_message(Message::new(1).with_batch_key("user-1"));
+
+    let elapsed = testing::advance_until_flushed(&topology, STEP).await;
+    assert!(elapsed >= BACKOFF * 2, "settled after {:?} before retries finished", elapsed);
+
+    assert_eq!(source.try_outcome(msg), Some(Outcome::Acked));
+
+    source.close();
+    topology.shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn interleaved_keys_batched_separately_inside_one_instance() {
+    let mut source = TestSource::new();
+    let batches = Batches::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher({
+                        let batches = batches.clone();
+                        move || Grouped { batches: batches.clone() }
+                    })
+                    .concurrency(1)
+                    .batch_size(2)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .batch_by_key()
+                    .start();
+
+    for (data, key) in [(1, "a"), (2, "b"), (3, "a"), (4, "b")] {
+        source.push_message(Message::new(data).with_batch_key(key));
+    }
+
+    let elapsed = testing::advance_until_flushed(&topology, STEP).await;
+    assert_eq!(elapsed, Duration::ZERO);
+
+    assert_eq!(*batches.lock().unwrap(), vec![
+        (Some("a".to_owned()), vec![1, 3]),
+        (Some("b".to_owned()), vec![2, 4])
+    ]);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn each_key_flushed_by_its_own_timeout() {
+    let mut source = TestSource::new();
+    let batches = Batches::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher({
+                        let batches = batches.clone();
+                        move || Grouped { batches: batches.clone() }
+                    })
+                    .concurrency(1)
+                    .batch_size(BATCH_SIZE)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .batch_by_key()
+                    .start();
+
+    let first = source.push_message(Message::new(1).with_batch_key("a"));
+    tokio::time::sleep(BATCH_TIMEOUT / 2).await;
+
+    let second = source.push_message(Message::new(2).with_batch_key("b"));
+    tokio::time::sleep(BATCH_TIMEOUT / 2 + STEP).await;
+
+    // timeout of "a" not flush pending batch of "b"
+    assert_eq!(source.try_outcome(first), Some(Outcome::Acked));
+    assert_eq!(source.try_outcome(second), None);
+
+    let elapsed = testing::advance_until_flushed(&topology, STEP).await;
+    assert!(elapsed <= BATCH_TIMEOUT / 2, "flushed after {:?}", elapsed);
+    assert_eq!(source.try_outcome(second), Some(Outcome::Acked));
+
+    let batch = topology.metrics().layer(2).next().unwrap().batch.clone().unwrap();
+    assert_eq!(batch.flushed_by_timeout, 2);
+    assert_eq!(batches.lock().unwrap().len(), 2);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn batch_flushed_by_weight_never_heavier_than_max() {
+    let mut source = TestSource::new();
+    let batches = Batches::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher({
+                        let batches = batches.clone();
+                        move || Grouped { batches: batches.clone() }
+                    })
+                    .concurrency(1)
+                    .batch_size(BATCH_SIZE * 10)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .max_batch_weight(MAX_WEIGHT, |m: &Message<u64>| m.data as usize)
+                    .start();
+
+    // 3 not fit beside 4 + 5, then 3 + 7 reach max exactly
+    source.push_batch(vec![4, 5, 3, 7]);
+
+    let elapsed = testing::advance_until_flushed(&topology, STEP).await;
+    assert_eq!(elapsed, Duration::ZERO);
+
+    assert_eq!(*batches.lock().unwrap(), vec![
+        (None, vec![4, 5]),
+        (None, vec![3, 7])
+    ]);
+
+    let batch = topology.metrics().layer(2).next().unwrap().batch.clone().unwrap();
+    assert_eq!(batch.flushed_by_weight, 2);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn oversized_message_flushed_alone_after_pending_batch() {
+    let mut source = TestSource::new();
+    let batches = Batches::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher({
+                        let batches = batches.clone();
+                        move || Grouped { batches: batches.clone() }
+                    })
+                    .concurrency(1)
+                    .batch_size(BATCH_SIZE * 10)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .max_batch_weight(MAX_WEIGHT, |m: &Message<u64>| m.data as usize)
+                    .start();
+
+    let msgs = source.push_batch(vec![4, 20, 3]);
+
+    let elapsed = testing::advance_until_flushed(&topology, STEP).await;
+    assert_eq!(elapsed, BATCH_TIMEOUT);
+
+    for msg in msgs {
+        assert_eq!(source.try_outcome(msg), Some(Outcome::Acked));
+    }
+
+    assert_eq!(*batches.lock().unwrap(), vec![
+        (None, vec![4]),
+        (None, vec![20]),
+        (None, vec![3])
+    ]);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn oversized_message_failed_once() {
+    let mut source = TestSource::new();
+    let batches = Batches::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher({
+                        let batches = batches.clone();
+                        move || Grouped { batches: batches.clone() }
+                    })
+                    .concurrency(1)
+                    .batch_size(BATCH_SIZE * 10)
+                    .batch_timeout(BATCH_TIMEOUT)
+                    .max_batch_weight(MAX_WEIGHT, |m: &Message<u64>| m.data as usize)
+                    .oversized(Oversized::Fail)
+                    .retry(RetryPolicy::new(5).backoff(BACKOFF, BACKOFF).jitter(0.0))
+                    .start();
+
+    let small = source.push(4);
+    let oversized = source.push(20);
+
+    testing::advance_until_flushed(&topology, STEP).await;
+
+    assert_eq!(source.try_outcome(small), Some(Outcome::Acked));
+    assert_eq!(source.try_outcome(oversized), Some(Outcome::Failed(FailReason::Oversized { weight: 20, max: MAX_WEIGHT })));
+
+    // not retried nor handled
+    assert_eq!(*batches.lock().unwrap(), vec![(None, vec![4])]);
+
+    let metrics = topology.metrics();
+    assert_eq!(metrics.layer(2).next().unwrap().failed, 1);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+
+struct Forward;
+
+#[async_trait]
+impl Processor<u64, u64> for Forward {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) -> ProcResult<u64> {
+        ProcResult::Dispatch(msg)
+    }
+}
+
+
+/// fails first `fail_first` batches
+#[derive(Default)]
+struct Store {
+    fail_first: usize,
+    calls: usize
+}
+
+#[async_trait]
+impl BatchProcessor<u64> for Store {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, _batch: Vec<Message<u64>>) -> Result<(), BatchError<u64>> {
+        self.calls += 1;
+
+        if self.calls <= self.fail_first {
+            return Err(BatchError::Fail("transient".to_owned()))
+        }
+
+        Ok(())
+    }
+}
+
+
+/// record key and payloads of each batch
+struct Grouped {
+    batches: Batches
+}
+
+#[async_trait]
+impl BatchProcessor<u64> for Grouped {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, key: Option<BatchKey>, batch: Vec<Message<u64>>) -> Result<(), BatchError<u64>> {
+        let data = batch.into_iter().map(|m| m.data).collect();
+        self.batches.lock().unwrap().push((key, data));
+        Ok(())
+    }
+}
@@ -0,0 +1,214 @@
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}};
+use std::time::Duration;
+
+use tokio_sky::{async_trait, BatchError, BatchKey, BatchProcessor, BreakerState, CircuitBreaker, Message, Processor, ProcResult, Topology};
+use tokio_sky::testing::{Outcome, TestSource};
+
+
+const THRESHOLD: usize = 2;
+const OPEN_TIMEOUT: Duration = Duration::from_secs(10);
+const BUFFER: usize = 2;
+
+
+type Transitions = Arc<Mutex<Vec<(BreakerState, BreakerState)>>>;
+
+
+
+fn breaker(transitions: &Transitions) -> CircuitBreaker {
+    let transitions = transitions.clone();
+    CircuitBreaker::new("db")
+        .failure_threshold(THRESHOLD)
+        .open_timeout(OPEN_TIMEOUT)
+        .on_state_change(move |e| transitions.lock().unwrap().push((e.from, e.to)))
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn breaker_opens_then_half_open_trial_closes_it() {
+    let mut source = TestSource::new();
+    let transitions = Transitions::default();
+    let breaker = breaker(&transitions);
+    let down = Arc::new(AtomicBool::new(true));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let factory = {
+        let (breaker, down, calls) = (breaker.clone(), down.clone(), calls.clone());
+        move || breaker.wrap(Database { down: down.clone(), calls: calls.clone() })
+    };
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(factory)
+                    .concurrency(1)
+                    .start();
+
+    for msg in source.push_batch(0..THRESHOLD as u64) {
+        source.assert_failed(msg).await;
+    }
+    assert_eq!(breaker.state(), BreakerState::Open);
+
+    // open breaker not call handler nor fail message
+    let waiting = source.push(100);
+    tokio::time::sleep(OPEN_TIMEOUT / 2).await;
+    assert_eq!(source.try_outcome(waiting), None);
+    assert_eq!(calls.load(Ordering::SeqCst), THRESHOLD);
+
+    // database back, trial after open timeout close breaker
+    down.store(false, Ordering::SeqCst);
+    source.assert_acked(waiting).await;
+
+    assert_eq!(breaker.state(), BreakerState::Closed);
+    assert_eq!(*transitions.lock().unwrap(), vec![
+        (BreakerState::Closed, BreakerState::Open),
+        (BreakerState::Open, BreakerState::HalfOpen),
+        (BreakerState::HalfOpen, BreakerState::Closed)
+    ]);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn failed_trial_opens_breaker_again() {
+    let mut source = TestSource::new();
+    let transitions = Transitions::default();
+    let breaker = breaker(&transitions);
+    let down = Arc::new(AtomicBool::new(true));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let factory = {
+        let (breaker, down, calls) = (breaker.clone(), down.clone(), calls.clone());
+        move || breaker.wrap(Database { down: down.clone(), calls: calls.clone() })
+    };
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(factory)
+                    .concurrency(1)
+                    .start();
+
+    for msg in source.push_batch(0..THRESHOLD as u64 + 1) {
+        source.assert_failed(msg).await;
+    }
+
+    // third message was the trial
+    assert_eq!(breaker.state(), BreakerState::Open);
+    assert_eq!(*transitions.lock().unwrap(), vec![
+        (BreakerState::Closed, BreakerState::Open),
+        (BreakerState::Open, BreakerState::HalfOpen),
+        (BreakerState::HalfOpen, BreakerState::Open)
+    ]);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn open_breaker_backpressures_producer() {
+    let mut source = TestSource::new();
+    let transitions = Transitions::default();
+    let breaker = breaker(&transitions);
+    let down = Arc::new(AtomicBool::new(true));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let factory = {
+        let (breaker, down, calls) = (breaker.clone(), down.clone(), calls.clone());
+        move || breaker.wrap(Store { down: down.clone(), calls: calls.clone() })
+    };
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher(factory)
+                    .concurrency(1)
+                    .batch_size(1)
+                    .buffer_size(BUFFER)
+                    .start();
+
+    let pushed = 100;
+    let msgs = source.push_batch(0..pushed);
+
+    tokio::time::sleep(OPEN_TIMEOUT / 2).await;
+
+    // just batches before breaker opened failed, rest wait inside channels
+    assert_eq!(calls.load(Ordering::SeqCst), THRESHOLD);
+    assert!(msgs.iter().skip(THRESHOLD).all(|m| source.try_outcome(*m).is_none()));
+
+    let metrics = topology.metrics();
+    let batcher = metrics.layer(2).next().unwrap();
+    assert_eq!(batcher.failed, THRESHOLD as u64);
+    assert_eq!(batcher.queue_depth, Some(BUFFER));
+
+    let producer = metrics.layer(0).next().unwrap();
+    assert!(producer.messages_out < pushed, "producer not blocked, sent {}", producer.messages_out);
+
+    // closed again, every waiting message delivered
+    down.store(false, Ordering::SeqCst);
+    for msg in msgs.into_iter().skip(THRESHOLD) {
+        assert_eq!(source.outcome(msg).await, Outcome::Acked);
+    }
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+/// fail every call while `down`
+struct Database {
+    down: Arc<AtomicBool>,
+    calls: Arc<AtomicUsize>
+}
+
+#[async_trait]
+impl Processor<u64, ()> for Database {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<u64>) -> ProcResult<()> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+
+        if self.down.load(Ordering::SeqCst) {
+            return ProcResult::Fail("database down".to_owned())
+        }
+        ProcResult::Continue
+    }
+}
+
+
+struct Forward;
+
+#[async_trait]
+impl Processor<u64, u64> for Forward {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) -> ProcResult<u64> {
+        ProcResult::Dispatch(msg)
+    }
+}
+
+
+struct Store {
+    down: Arc<AtomicBool>,
+    calls: Arc<AtomicUsize>
+}
+
+#[async_trait]
+impl BatchProcessor<u64> for Store {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, _batch: Vec<Message<u64>>) -> Result<(), BatchError<u64>> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+
+        if self.down.load(Ordering::SeqCst) {
+            return Err(BatchError::Fail("database down".to_owned()))
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use tokio_sky::{async_trait, DeadLetter, FailReason, Message, Processor, ProcResult, Topology};
+use tokio_sky::testing::{Outcome, TestSource};
+
+
+const DLQ_LATENCY: Duration = Duration::from_secs(1);
+
+
+
+#[tokio::test(start_paused = true)]
+async fn failed_message_acked_once_dead_letter_finished() {
+    let mut source = TestSource::new();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Reject)
+                    .dead_letter(|| SlowDlq { fail: false })
+                    .start();
+
+    let msg = source.push(1);
+
+    // dead letter queued and inside dead letter stage, source not settled yet
+    tokio::time::sleep(DLQ_LATENCY / 2).await;
+    assert_eq!(source.try_outcome(msg), None);
+
+    tokio::time::sleep(DLQ_LATENCY).await;
+    assert_eq!(source.try_outcome(msg), Some(Outcome::Failed(FailReason::Error("rejected".to_owned()))));
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn failed_dead_letter_fail_source_by_its_own_reason() {
+    let mut source = TestSource::new();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Reject)
+                    .dead_letter(|| SlowDlq { fail: true })
+                    .start();
+
+    let msg = source.push(1);
+
+    let reason = source.assert_failed(msg).await;
+    assert_eq!(reason, FailReason::Error("rejected".to_owned()));
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+struct Reject;
+
+#[async_trait]
+impl Processor<u64, ()> for Reject {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<u64>) -> ProcResult<()> {
+        ProcResult::Fail("rejected".to_owned())
+    }
+}
+
+
+struct SlowDlq {
+    fail: bool
+}
+
+#[async_trait]
+impl Processor<DeadLetter<u64>, ()> for SlowDlq {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<DeadLetter<u64>>) -> ProcResult<()> {
+        tokio::time::sleep(DLQ_LATENCY).await;
+
+        if self.fail {
+            return ProcResult::Fail("dead letter store down".to_owned())
+        }
+
+        ProcResult::Continue
+    }
+}
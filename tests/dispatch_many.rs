@@ -0,0 +1,149 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio_sky::{async_trait, Message, Processor, ProcResult, Topology};
+use tokio_sky::testing::{self, Outcome, TestSource};
+
+
+const SPLIT: u64 = 10;
+const DELAY: Duration = Duration::from_secs(1);
+const STEP: Duration = Duration::from_millis(100);
+
+
+type Received = Arc<Mutex<Vec<u64>>>;
+
+
+
+#[tokio::test(start_paused = true)]
+async fn outputs_delivered_in_order_and_input_acked_after_all() {
+    let mut source = TestSource::new();
+    let received = Received::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Splitter)
+                    .concurrency(1)
+                    .then({
+                        let received = received.clone();
+                        move || Collect { received: received.clone() }
+                    })
+                    .concurrency(1)
+                    .start();
+
+    let msgs = source.push_batch(vec![1, 2]);
+
+    // last outputs still handled by next layer
+    tokio::time::sleep(DELAY * SPLIT as u32 + DELAY / 2).await;
+    assert_eq!(source.try_outcome(msgs[0]), Some(Outcome::Acked));
+    assert_eq!(source.try_outcome(msgs[1]), None);
+
+    testing::advance_until_flushed(&topology, STEP).await;
+    assert_eq!(source.try_outcome(msgs[1]), Some(Outcome::Acked));
+
+    let expected = (10..10 + SPLIT).chain(20..20 + SPLIT).collect::<Vec<_>>();
+    assert_eq!(*received.lock().unwrap(), expected);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn outputs_wait_for_room_in_next_layer() {
+    let mut source = TestSource::new();
+    let received = Received::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Splitter)
+                    .concurrency(1)
+                    .then({
+                        let received = received.clone();
+                        move || Collect { received: received.clone() }
+                    })
+                    .concurrency(1)
+                    .buffer_size(1)
+                    .start();
+
+    source.push_batch(vec![1, 2]);
+
+    tokio::time::sleep(DELAY / 2).await;
+
+    // one output inside handler of next layer, one inside its channel,
+    // splitter blocked on third one and not receive next input
+    let metrics = topology.metrics();
+    let splitter = metrics.layer(1).next().unwrap();
+    assert_eq!(splitter.messages_in, 1);
+    assert_eq!(splitter.messages_out, 0);
+
+    let collect = metrics.layer(2).next().unwrap();
+    assert_eq!(collect.messages_in, 1);
+    assert_eq!(collect.queue_depth, Some(1));
+
+    testing::advance_until_flushed(&topology, STEP).await;
+    assert_eq!(received.lock().unwrap().len(), 2 * SPLIT as usize);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn no_outputs_acks_input() {
+    let mut source = TestSource::new();
+    let received = Received::default();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Splitter)
+                    .then({
+                        let received = received.clone();
+                        move || Collect { received: received.clone() }
+                    })
+                    .start();
+
+    source.assert_acked(source.push(0)).await;
+    assert!(received.lock().unwrap().is_empty());
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+/// `n` split in `SPLIT` outputs `n * 10 ..`, `0` in none
+struct Splitter;
+
+#[async_trait]
+impl Processor<u64, u64> for Splitter {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) -> ProcResult<u64> {
+        let outputs = match msg.data {
+            0 => Vec::new(),
+            n => (n * 10..n * 10 + SPLIT).collect()
+        };
+
+        ProcResult::DispatchMany(msg.split(outputs))
+    }
+}
+
+
+/// take `DELAY` per message
+struct Collect {
+    received: Received
+}
+
+#[async_trait]
+impl Processor<u64, ()> for Collect {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) -> ProcResult<()> {
+        tokio::time::sleep(DELAY).await;
+        self.received.lock().unwrap().push(msg.data);
+        ProcResult::Continue
+    }
+}
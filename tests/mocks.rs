@@ -0,0 +1,65 @@
+use std::collections::VecDeque;
+
+use tokio_sky::{async_trait, Message, Processor, ProcResult, Producer, Terminate, Topology};
+use tokio_sky::testing::{Outcome, TestSource};
+
+
+
+#[tokio::test]
+async fn mock_producer_replace_producer() {
+    let mut source = TestSource::<u64>::new();
+
+    let topology =
+                Topology::producer(|| Never)
+                    .then(|| Forward)
+                    .mock_producer(source.producer())
+                    .start();
+
+    let msg = source.push(1);
+    assert_eq!(source.outcome(msg).await, Outcome::Acked);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test]
+#[should_panic(expected = "Mock of layer 0 must be same kind and types as layer")]
+async fn mock_producer_of_other_type_panics_at_start() {
+    let source = TestSource::<String>::new();
+
+    let _ = Topology::producer(|| Never)
+        .then(|| Forward)
+        .mock_producer(source.producer())
+        .start();
+}
+
+
+
+
+/// producer replaced by mock
+struct Never;
+
+#[async_trait]
+impl Producer<u64> for Never {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _buffer: VecDeque<Message<u64>>) {}
+
+    async fn fill_buffer(&mut self, _buffer_size: usize) -> Result<VecDeque<Message<u64>>, Terminate> {
+        unreachable!("==> mocked")
+    }
+}
+
+
+struct Forward;
+
+#[async_trait]
+impl Processor<u64, ()> for Forward {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<u64>) -> ProcResult<()> {
+        ProcResult::Continue
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}};
+
+use tokio_sky::{async_trait, BatchError, BatchKey, BatchProcessor, FailReason, Message, Processor, ProcResult, Topology};
+use tokio_sky::testing::TestSource;
+
+
+const POISON: u64 = 1;
+
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+
+
+#[tokio::test(start_paused = true)]
+async fn processor_panic_fails_message_and_keeps_instance_state() {
+    let mut source = TestSource::new();
+    let log = Log::default();
+    let built = Arc::new(AtomicUsize::new(0));
+
+    let factory = {
+        let (log, built) = (log.clone(), built.clone());
+        move || {
+            built.fetch_add(1, Ordering::SeqCst);
+            Poisoned { seen: 0, log: log.clone() }
+        }
+    };
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(factory)
+                    .concurrency(1)
+                    .start();
+
+    source.assert_acked(source.push(0)).await;
+
+    let reason = source.assert_failed(source.push(POISON)).await;
+    assert_eq!(reason, FailReason::Panicked(Some("poisoned".to_owned())));
+
+    source.assert_acked(source.push(2)).await;
+
+    // same instance (not rebuilt) served all messages, counter kept across panic
+    assert_eq!(built.load(Ordering::SeqCst), 1);
+    assert_eq!(*log.lock().unwrap(), vec!["0: 1", "1: 2", "failed 1", "2: 3"]);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn batcher_panic_fails_batch_and_keeps_instance_state() {
+    let mut source = TestSource::new();
+    let log = Log::default();
+    let built = Arc::new(AtomicUsize::new(0));
+
+    let factory = {
+        let (log, built) = (log.clone(), built.clone());
+        move || {
+            built.fetch_add(1, Ordering::SeqCst);
+            PoisonedStore { seen: 0, log: log.clone() }
+        }
+    };
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher(factory)
+                    .concurrency(1)
+                    .batch_size(1)
+                    .start();
+
+    source.assert_acked(source.push(0)).await;
+
+    let reason = source.assert_failed(source.push(POISON)).await;
+    assert_eq!(reason, FailReason::Panicked(Some("poisoned".to_owned())));
+
+    source.assert_acked(source.push(2)).await;
+
+    assert_eq!(built.load(Ordering::SeqCst), 1);
+    assert_eq!(*log.lock().unwrap(), vec!["0: 1", "1: 2", "failed 1", "2: 3"]);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+/// log each message with number of messages seen by instance, panic on `POISON`
+struct Poisoned {
+    seen: usize,
+    log: Log
+}
+
+#[async_trait]
+impl Processor<u64, ()> for Poisoned {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) -> ProcResult<()> {
+        self.seen += 1;
+        self.log.lock().unwrap().push(format!("{}: {}", msg.data, self.seen));
+
+        if msg.data == POISON {
+            panic!("poisoned");
+        }
+        ProcResult::Continue
+    }
+
+    async fn handle_failed(&mut self, msg: &Message<u64>) {
+        self.log.lock().unwrap().push(format!("failed {}", msg.data));
+    }
+}
+
+
+struct Forward;
+
+#[async_trait]
+impl Processor<u64, u64> for Forward {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) -> ProcResult<u64> {
+        ProcResult::Dispatch(msg)
+    }
+}
+
+
+struct PoisonedStore {
+    seen: usize,
+    log: Log
+}
+
+#[async_trait]
+impl BatchProcessor<u64> for PoisonedStore {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, batch: Vec<Message<u64>>) -> Result<(), BatchError<u64>> {
+        for msg in &batch {
+            self.seen += 1;
+            self.log.lock().unwrap().push(format!("{}: {}", msg.data, self.seen));
+        }
+
+        if batch.iter().any(|m| m.data == POISON) {
+            panic!("poisoned");
+        }
+        Ok(())
+    }
+
+    async fn handle_failed(&mut self, batch: &[Message<u64>]) {
+        for msg in batch {
+            self.log.lock().unwrap().push(format!("failed {}", msg.data));
+        }
+    }
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "prometheus")]
+
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+use tokio_sky::{async_trait, Message, Processor, ProcResult, Topology};
+use tokio_sky::testing::TestSource;
+
+
+
+#[tokio::test]
+async fn stage_label_is_stage_name_of_instance() {
+    let mut source = TestSource::<u64>::new();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .concurrency(2)
+                    .start();
+
+    let text = topology.metrics().to_prometheus("test");
+
+    for instance in 0..2 {
+        let labels = format!("topology=\"test\",layer=\"1\",kind=\"processor\",stage=\"{}\",instance=\"{}\"", instance, instance);
+        assert!(text.contains(&labels), "missing {} in\n{}", labels, text);
+    }
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn idle_client_disconnected_after_read_timeout() {
+    let mut source = TestSource::<u64>::new();
+
+    let mut topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .start();
+
+    let addr = topology.serve_metrics("127.0.0.1:0", "test").await.unwrap();
+
+    // request never sent, server close connection instead of waiting forever
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let mut buf = Vec::new();
+    let n = stream.read_to_end(&mut buf).await.unwrap_or(0);
+    assert_eq!(n, 0);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+struct Forward;
+
+#[async_trait]
+impl Processor<u64, ()> for Forward {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<u64>) -> ProcResult<()> {
+        ProcResult::Continue
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use tokio_sky::{async_trait, BatchError, BatchKey, BatchProcessor, Message, Processor, ProcResult, RetryPolicy, Topology};
+use tokio_sky::testing::TestSource;
+
+
+const MAX_RETRYING: usize = 2;
+const BACKOFF: Duration = Duration::from_secs(10);
+
+
+
+fn policy() -> RetryPolicy {
+    RetryPolicy::new(3)
+        .backoff(BACKOFF, BACKOFF)
+        .jitter(0.0)
+        .max_retrying(MAX_RETRYING)
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn processor_not_receive_while_retries_at_cap() {
+    let mut source = TestSource::new();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Flaky)
+                    .retry(policy())
+                    .start();
+
+    source.push_batch(0..5);
+
+    tokio::time::sleep(BACKOFF / 2).await;
+
+    let metrics = topology.metrics();
+    let stage = metrics.layer(1).next().unwrap();
+    assert_eq!(stage.retrying, MAX_RETRYING);
+    assert_eq!(stage.messages_in, MAX_RETRYING as u64);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn batcher_not_receive_while_retries_at_cap() {
+    let mut source = TestSource::new();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .batcher(|| FlakyStore)
+                    .batch_size(1)
+                    .retry(policy())
+                    .start();
+
+    source.push_batch(0..5);
+
+    tokio::time::sleep(BACKOFF / 2).await;
+
+    let metrics = topology.metrics();
+    let stage = metrics.layer(2).next().unwrap();
+    assert_eq!(stage.retrying, MAX_RETRYING);
+    assert_eq!(stage.messages_in, MAX_RETRYING as u64);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+struct Flaky;
+
+#[async_trait]
+impl Processor<u64, ()> for Flaky {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<u64>) -> ProcResult<()> {
+        ProcResult::Fail("transient".to_owned())
+    }
+}
+
+
+struct Forward;
+
+#[async_trait]
+impl Processor<u64, u64> for Forward {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, msg: Message<u64>) -> ProcResult<u64> {
+        ProcResult::Dispatch(msg)
+    }
+}
+
+
+struct FlakyStore;
+
+#[async_trait]
+impl BatchProcessor<u64> for FlakyStore {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+    async fn drain(&mut self, _batch: Vec<Message<u64>>) {}
+
+    async fn handle_batch(&mut self, _key: Option<BatchKey>, _batch: Vec<Message<u64>>) -> Result<(), BatchError<u64>> {
+        Err(BatchError::Fail("transient".to_owned()))
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}};
+use std::time::Duration;
+
+use tokio_sky::{async_trait, Message, Processor, ProcResult, Supervision, TelemetryEvent, Topology};
+use tokio_sky::testing::TestSource;
+
+
+const CONCURRENCY: usize = 3;
+const SETTLE: Duration = Duration::from_millis(100);
+
+
+
+/// count `StageStart` of each instance of layer 1
+fn starts() -> (Arc<Mutex<Vec<usize>>>, impl Fn(&TelemetryEvent) + Send + Sync + 'static) {
+    let starts = Arc::new(Mutex::new(vec![0; CONCURRENCY]));
+    let handler = {
+        let starts = starts.clone();
+        move |e: &TelemetryEvent| {
+            if let TelemetryEvent::StageStart { id } = e {
+                if id.layer == 1 {
+                    starts.lock().unwrap()[id.instance] += 1;
+                }
+            }
+        }
+    };
+    (starts, handler)
+}
+
+
+/// first built instance (instance 0) crash inside `init`, every later one is healthy
+fn crash_first() -> impl Fn() -> Crashy + Send + Sync + 'static {
+    let built = Arc::new(AtomicUsize::new(0));
+    move || Crashy { crash: built.fetch_add(1, Ordering::SeqCst) == 0 }
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn one_for_one_restarts_just_crashed_instance() {
+    let mut source = TestSource::<u64>::new();
+    let (starts, handler) = starts();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .telemetry(handler)
+                    .then(crash_first())
+                    .concurrency(CONCURRENCY)
+                    .supervise(Supervision::one_for_one())
+                    .start();
+
+    tokio::time::sleep(SETTLE).await;
+    assert_eq!(*starts.lock().unwrap(), vec![2, 1, 1]);
+
+    // restarted instance keep serving its channel
+    for msg in source.push_batch(0..6) {
+        source.assert_acked(msg).await;
+    }
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn rest_for_one_restarts_crashed_and_later_instances() {
+    let mut source = TestSource::<u64>::new();
+    let (starts, handler) = starts();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .telemetry(handler)
+                    .then(crash_first())
+                    .concurrency(CONCURRENCY)
+                    .supervise(Supervision::rest_for_one())
+                    .start();
+
+    tokio::time::sleep(SETTLE).await;
+    assert_eq!(*starts.lock().unwrap(), vec![2, 2, 2]);
+
+    for msg in source.push_batch(0..6) {
+        source.assert_acked(msg).await;
+    }
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn supervisor_gives_up_after_max_restarts() {
+    let mut source = TestSource::<u64>::new();
+    let (starts, handler) = starts();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .telemetry(handler)
+                    .then(|| Crashy { crash: true })
+                    .concurrency(CONCURRENCY)
+                    .supervise(Supervision::one_for_one().max_restarts(2, Duration::from_secs(60)))
+                    .start();
+
+    tokio::time::sleep(SETTLE).await;
+
+    // two restarts allowed for whole layer, then every instance stopped
+    let total: usize = starts.lock().unwrap().iter().sum();
+    assert_eq!(total, CONCURRENCY + 2);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+
+
+struct Crashy {
+    crash: bool
+}
+
+#[async_trait]
+impl Processor<u64, ()> for Crashy {
+    async fn init(&mut self) {
+        if self.crash {
+            panic!("init crashed");
+        }
+    }
+
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<u64>) -> ProcResult<()> {
+        ProcResult::Continue
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use tokio_sky::{async_trait, Message, Processor, ProcResult, Timeouts, Topology};
+use tokio_sky::testing::TestSource;
+
+
+const THRESHOLD: Duration = Duration::from_millis(100);
+
+
+
+#[tokio::test(start_paused = true)]
+async fn producer_waiting_for_input_not_stalled() {
+    let mut source = TestSource::<u64>::new();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Forward)
+                    .start();
+
+    tokio::time::sleep(THRESHOLD * 3).await;
+
+    assert_eq!(topology.stalled(THRESHOLD), vec![]);
+
+    let metrics = topology.metrics();
+    let producer = metrics.layer(0).next().unwrap();
+    assert_eq!(producer.latency.count, 0);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[tokio::test(start_paused = true)]
+async fn processor_inside_handler_stalled() {
+    let mut source = TestSource::new();
+
+    let topology =
+                Topology::producer(source.producer())
+                    .then(|| Slow)
+                    .start();
+
+    source.push(1);
+
+    tokio::time::sleep(THRESHOLD * 3).await;
+
+    let stalled = topology.stalled(THRESHOLD);
+    assert_eq!(stalled.len(), 1);
+    assert_eq!(stalled[0].id.layer, 1);
+
+    source.close();
+    topology.shutdown().await;
+}
+
+
+#[test]
+#[should_panic(expected = "Timeouts::handler not applied to producers")]
+fn producer_handler_timeout_rejected() {
+    let source = TestSource::<u64>::new();
+
+    let _ = Topology::producer(source.producer())
+        .timeouts(Timeouts::default().handler(THRESHOLD));
+}
+
+
+
+
+struct Forward;
+
+#[async_trait]
+impl Processor<u64, ()> for Forward {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<u64>) -> ProcResult<()> {
+        ProcResult::Continue
+    }
+}
+
+
+struct Slow;
+
+#[async_trait]
+impl Processor<u64, ()> for Slow {
+    async fn init(&mut self) {}
+    async fn terminate(&mut self) {}
+
+    async fn handle_message(&mut self, _msg: Message<u64>) -> ProcResult<()> {
+        tokio::time::sleep(THRESHOLD * 10).await;
+        ProcResult::Continue
+    }
+}